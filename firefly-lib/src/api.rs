@@ -0,0 +1,866 @@
+use std::pin::Pin;
+
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use reqwest::{RequestBuilder, StatusCode};
+
+use crate::{
+    db::auth::TokenResponse,
+    error::FireflyError,
+    pb::firefly::firefly,
+    utils::{HTTP_CLIENT, deserialize_proto, serialize_proto, write_url_comma_seperated},
+};
+
+/// Streamed request body of `FireflyApi::upload_file`.
+pub type UploadBody = Pin<Box<dyn Stream<Item = anyhow::Result<Bytes>> + Send>>;
+
+/// Streamed response body of `FireflyApi::download_file`.
+pub type DownloadBody = Pin<Box<dyn Stream<Item = anyhow::Result<Bytes>> + Send>>;
+
+/// The REST endpoints of the Firefly server `FireflyWsClient` uses, every call
+/// is authenticated with the access `token`. Device scoped calls take the
+/// `address_id` the server assigned in `register_device`.
+#[async_trait::async_trait]
+pub trait FireflyApi: Send + Sync {
+    /// Root of the endpoints, the group client of `firefly_core` makes its own
+    /// requests against it.
+    fn base_url(&self) -> &str;
+
+    /// Trades the `refresh_token` for new tokens at the auth server.
+    async fn refresh_token(
+        &self,
+        auth_base_url: &str,
+        client_id: &str,
+        refresh_token: &str,
+    ) -> anyhow::Result<TokenResponse>;
+
+    /// Registers or refreshes this device, returns the address as stored.
+    async fn register_device(
+        &self,
+        token: &str,
+        address: &firefly::Address,
+    ) -> anyhow::Result<firefly::Address>;
+
+    /// Removes the device, it stops receiving messages.
+    async fn deregister_device(&self, token: &str, address_id: u64) -> anyhow::Result<()>;
+
+    /// Entries of the pre key bundles still on the server, without the bundles.
+    async fn get_pre_key_bundle_ids(
+        &self,
+        token: &str,
+        address_id: u64,
+    ) -> anyhow::Result<firefly::PreKeyBundleEntries>;
+
+    /// Takes a bundle of every device of `username`.
+    async fn get_pre_key_bundles_of_user(
+        &self,
+        token: &str,
+        username: &str,
+    ) -> anyhow::Result<firefly::PreKeyBundleEntries>;
+
+    /// Takes a bundle of every address in `address_ids`.
+    async fn get_pre_key_bundles(
+        &self,
+        token: &str,
+        address_ids: &[u64],
+    ) -> anyhow::Result<firefly::PreKeyBundleEntries>;
+
+    async fn upload_pre_key_bundles(
+        &self,
+        token: &str,
+        bundles: &firefly::PreKeyBundleEntries,
+    ) -> anyhow::Result<()>;
+
+    async fn delete_pre_key_bundles(
+        &self,
+        token: &str,
+        address_id: u64,
+        ids: &[u32],
+    ) -> anyhow::Result<()>;
+
+    /// Stores the shared settings of the conversation with `other`, ORed into
+    /// the current ones with `merge`.
+    async fn update_conversation(
+        &self,
+        token: &str,
+        other: &str,
+        settings: u64,
+        merge: bool,
+    ) -> anyhow::Result<()>;
+
+    async fn get_conversations(&self, token: &str) -> anyhow::Result<firefly::Conversations>;
+
+    /// At most `limit` messages of the groups in `requests`, oldest first.
+    async fn sync_group_messages(
+        &self,
+        token: &str,
+        address_id: u64,
+        limit: usize,
+        requests: &firefly::GroupSyncRequests,
+    ) -> anyhow::Result<firefly::GroupMessages>;
+
+    async fn sync_group_updates(
+        &self,
+        token: &str,
+        address_id: u64,
+        updates: &firefly::GroupMemberUpdates,
+    ) -> anyhow::Result<()>;
+
+    async fn get_key_packages(
+        &self,
+        token: &str,
+        address_id: u64,
+        device_id: u8,
+    ) -> anyhow::Result<firefly::GroupKeyPackages>;
+
+    async fn upload_key_packages(
+        &self,
+        token: &str,
+        address_id: u64,
+        device_id: u8,
+        key_packages: &firefly::GroupKeyPackages,
+    ) -> anyhow::Result<()>;
+
+    async fn delete_key_packages(
+        &self,
+        token: &str,
+        address_id: u64,
+        device_id: u8,
+        ids: &[i32],
+    ) -> anyhow::Result<()>;
+
+    /// Groups the user is a member of with any device.
+    async fn get_groups(&self, token: &str) -> anyhow::Result<firefly::Groups>;
+
+    async fn get_group(&self, token: &str, group_id: u64) -> anyhow::Result<firefly::Group>;
+
+    async fn delete_group(&self, token: &str, group_id: u64) -> anyhow::Result<()>;
+
+    /// Reports that this device joined `group_id` through an invite.
+    async fn add_group_member(
+        &self,
+        token: &str,
+        group_id: u64,
+        address_id: u64,
+        device_id: u8,
+        update: &firefly::GroupMemberUpdate,
+    ) -> anyhow::Result<()>;
+
+    async fn get_group_invites(
+        &self,
+        token: &str,
+        address_id: u64,
+        device_id: u8,
+    ) -> anyhow::Result<firefly::GroupInvites>;
+
+    async fn delete_group_invites(
+        &self,
+        token: &str,
+        address_id: u64,
+        group_ids: &[u64],
+    ) -> anyhow::Result<()>;
+
+    /// Asks the other members of `group_ids` to add this device again.
+    async fn request_group_re_adds(
+        &self,
+        token: &str,
+        address_id: u64,
+        device_id: u8,
+        group_ids: &[u64],
+    ) -> anyhow::Result<()>;
+
+    /// Devices waiting to be added again to any of `group_ids`.
+    async fn get_group_re_adds(
+        &self,
+        token: &str,
+        address_id: u64,
+        group_ids: &[u64],
+    ) -> anyhow::Result<firefly::GroupReAddRequests>;
+
+    async fn delete_group_re_add(
+        &self,
+        token: &str,
+        group_id: u64,
+        address_id: u64,
+        my_address_id: u64,
+    ) -> anyhow::Result<()>;
+
+    /// Uploads `length` bytes of `body`, returns the url the file is served
+    /// from.
+    async fn upload_file(
+        &self,
+        token: &str,
+        length: u64,
+        body: UploadBody,
+    ) -> anyhow::Result<String>;
+
+    /// Streams the file at `url`, as returned by `upload_file`.
+    async fn download_file(&self, url: &str) -> anyhow::Result<DownloadBody>;
+}
+
+/// `FireflyApi` over HTTP, with the shared `HTTP_CLIENT` unless another
+/// client, like one going through a proxy, is given.
+pub struct HttpFireflyApi {
+    base_url: String,
+    client: reqwest::Client,
+}
+
+impl HttpFireflyApi {
+    pub fn new(base_url: String) -> Self {
+        Self::with_client(base_url, HTTP_CLIENT.clone())
+    }
+
+    pub fn with_client(base_url: String, client: reqwest::Client) -> Self {
+        Self { base_url, client }
+    }
+
+    async fn send(request: RequestBuilder) -> anyhow::Result<reqwest::Response> {
+        let response = request.send().await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let message = format!("unexpected status [{}]: {}", status, response.text().await?);
+            return Err(match status {
+                StatusCode::UNAUTHORIZED => FireflyError::AuthExpired(message),
+                _ => FireflyError::Server {
+                    code: status.as_u16() as u32,
+                    message,
+                },
+            }
+            .into());
+        }
+
+        Ok(response)
+    }
+
+    async fn fetch<T: prost::Message + Default>(request: RequestBuilder) -> anyhow::Result<T> {
+        let response = Self::send(request).await?;
+        Ok(deserialize_proto(&response.bytes().await?)?)
+    }
+}
+
+fn comma_separated<T: std::fmt::Display>(
+    url: &mut String,
+    items: impl IntoIterator<Item = T>,
+) -> anyhow::Result<()> {
+    write_url_comma_seperated(url, items.into_iter())?;
+    Ok(())
+}
+
+#[async_trait::async_trait]
+impl FireflyApi for HttpFireflyApi {
+    fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    async fn refresh_token(
+        &self,
+        auth_base_url: &str,
+        client_id: &str,
+        refresh_token: &str,
+    ) -> anyhow::Result<TokenResponse> {
+        let body = serde_json::json!({
+            "grant_type": "refresh_token",
+            "client_id": client_id,
+            "refresh_token": refresh_token,
+        });
+
+        let response = self
+            .client
+            .post(format!("{}/oauth/token", auth_base_url))
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(serde_json::to_string(&body)?)
+            .send()
+            .await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let message = format!(
+                "failed to refresh token, unexpected status: [{}] {}",
+                status.as_u16(),
+                response.text().await?
+            );
+            // invalid_grant: the refresh token was revoked or expired. Anything
+            // else, like a rate limit, is worth retrying with the same token
+            return Err(match status {
+                StatusCode::BAD_REQUEST | StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+                    FireflyError::AuthExpired(message)
+                }
+                _ => FireflyError::Server {
+                    code: status.as_u16() as u32,
+                    message,
+                },
+            }
+            .into());
+        }
+
+        Ok(serde_json::from_slice(&response.bytes().await?)?)
+    }
+
+    async fn register_device(
+        &self,
+        token: &str,
+        address: &firefly::Address,
+    ) -> anyhow::Result<firefly::Address> {
+        Self::fetch(
+            self.client
+                .post(format!("{}/user/device", self.base_url))
+                .bearer_auth(token)
+                .body(serialize_proto(address)?),
+        )
+        .await
+    }
+
+    async fn deregister_device(&self, token: &str, address_id: u64) -> anyhow::Result<()> {
+        Self::send(
+            self.client
+                .delete(format!("{}/user/device?id={}", self.base_url, address_id))
+                .bearer_auth(token),
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn get_pre_key_bundle_ids(
+        &self,
+        token: &str,
+        address_id: u64,
+    ) -> anyhow::Result<firefly::PreKeyBundleEntries> {
+        Self::fetch(
+            self.client
+                .get(format!(
+                    "{}/user/preKeyBundles?id={}&onlyIds=true",
+                    self.base_url, address_id
+                ))
+                .bearer_auth(token),
+        )
+        .await
+    }
+
+    async fn get_pre_key_bundles_of_user(
+        &self,
+        token: &str,
+        username: &str,
+    ) -> anyhow::Result<firefly::PreKeyBundleEntries> {
+        Self::fetch(
+            self.client
+                .get(format!(
+                    "{}/user/preKeyBundles?other={}",
+                    self.base_url, username
+                ))
+                .bearer_auth(token),
+        )
+        .await
+    }
+
+    async fn get_pre_key_bundles(
+        &self,
+        token: &str,
+        address_ids: &[u64],
+    ) -> anyhow::Result<firefly::PreKeyBundleEntries> {
+        let mut url = format!("{}/user/preKeyBundles?ids=", self.base_url);
+        comma_separated(&mut url, address_ids)?;
+
+        Self::fetch(self.client.get(url).bearer_auth(token)).await
+    }
+
+    async fn upload_pre_key_bundles(
+        &self,
+        token: &str,
+        bundles: &firefly::PreKeyBundleEntries,
+    ) -> anyhow::Result<()> {
+        Self::send(
+            self.client
+                .post(format!("{}/user/preKeyBundles", self.base_url))
+                .bearer_auth(token)
+                .body(serialize_proto(bundles)?),
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn delete_pre_key_bundles(
+        &self,
+        token: &str,
+        address_id: u64,
+        ids: &[u32],
+    ) -> anyhow::Result<()> {
+        let mut url = format!(
+            "{}/user/preKeyBundles?addressId={}&ids=",
+            self.base_url, address_id
+        );
+        comma_separated(&mut url, ids)?;
+
+        Self::send(self.client.delete(url).bearer_auth(token)).await?;
+        Ok(())
+    }
+
+    async fn update_conversation(
+        &self,
+        token: &str,
+        other: &str,
+        settings: u64,
+        merge: bool,
+    ) -> anyhow::Result<()> {
+        Self::send(
+            self.client
+                .post(format!(
+                    "{}/user/conversation?other={}&settings={}&merge={}",
+                    self.base_url, other, settings, merge
+                ))
+                .bearer_auth(token),
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn get_conversations(&self, token: &str) -> anyhow::Result<firefly::Conversations> {
+        Self::fetch(
+            self.client
+                .get(format!("{}/user/conversations", self.base_url))
+                .bearer_auth(token),
+        )
+        .await
+    }
+
+    async fn sync_group_messages(
+        &self,
+        token: &str,
+        address_id: u64,
+        limit: usize,
+        requests: &firefly::GroupSyncRequests,
+    ) -> anyhow::Result<firefly::GroupMessages> {
+        Self::fetch(
+            self.client
+                .post(format!(
+                    "{}/group/sync?address={}&limit={}",
+                    self.base_url, address_id, limit
+                ))
+                .bearer_auth(token)
+                .body(serialize_proto(requests)?),
+        )
+        .await
+    }
+
+    async fn sync_group_updates(
+        &self,
+        token: &str,
+        address_id: u64,
+        updates: &firefly::GroupMemberUpdates,
+    ) -> anyhow::Result<()> {
+        Self::send(
+            self.client
+                .post(format!(
+                    "{}/group/syncUpdate?address={}",
+                    self.base_url, address_id
+                ))
+                .bearer_auth(token)
+                .body(serialize_proto(updates)?),
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn get_key_packages(
+        &self,
+        token: &str,
+        address_id: u64,
+        device_id: u8,
+    ) -> anyhow::Result<firefly::GroupKeyPackages> {
+        Self::fetch(
+            self.client
+                .get(format!(
+                    "{}/group/keyPackages?address_id={}&device_id={}",
+                    self.base_url, address_id, device_id
+                ))
+                .bearer_auth(token),
+        )
+        .await
+    }
+
+    async fn upload_key_packages(
+        &self,
+        token: &str,
+        address_id: u64,
+        device_id: u8,
+        key_packages: &firefly::GroupKeyPackages,
+    ) -> anyhow::Result<()> {
+        Self::send(
+            self.client
+                .post(format!(
+                    "{}/group/keyPackages?address={}&device_id={}",
+                    self.base_url, address_id, device_id
+                ))
+                .bearer_auth(token)
+                .body(serialize_proto(key_packages)?),
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn delete_key_packages(
+        &self,
+        token: &str,
+        address_id: u64,
+        device_id: u8,
+        ids: &[i32],
+    ) -> anyhow::Result<()> {
+        let mut url = format!(
+            "{}/group/keyPackages?address={}&device_id={}&ids=",
+            self.base_url, address_id, device_id
+        );
+        comma_separated(&mut url, ids)?;
+
+        Self::send(self.client.delete(url).bearer_auth(token)).await?;
+        Ok(())
+    }
+
+    async fn get_groups(&self, token: &str) -> anyhow::Result<firefly::Groups> {
+        Self::fetch(
+            self.client
+                .get(format!("{}/groups", self.base_url))
+                .bearer_auth(token),
+        )
+        .await
+    }
+
+    async fn get_group(&self, token: &str, group_id: u64) -> anyhow::Result<firefly::Group> {
+        Self::fetch(
+            self.client
+                .get(format!("{}/group?id={}", self.base_url, group_id))
+                .bearer_auth(token),
+        )
+        .await
+    }
+
+    async fn delete_group(&self, token: &str, group_id: u64) -> anyhow::Result<()> {
+        Self::send(
+            self.client
+                .delete(format!("{}/group?id={}", self.base_url, group_id))
+                .bearer_auth(token),
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn add_group_member(
+        &self,
+        token: &str,
+        group_id: u64,
+        address_id: u64,
+        device_id: u8,
+        update: &firefly::GroupMemberUpdate,
+    ) -> anyhow::Result<()> {
+        Self::send(
+            self.client
+                .post(format!(
+                    "{}/group/member?groupId={}&address={}&device_id={}",
+                    self.base_url, group_id, address_id, device_id
+                ))
+                .bearer_auth(token)
+                .body(serialize_proto(update)?),
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn get_group_invites(
+        &self,
+        token: &str,
+        address_id: u64,
+        device_id: u8,
+    ) -> anyhow::Result<firefly::GroupInvites> {
+        Self::fetch(
+            self.client
+                .get(format!(
+                    "{}/group/invites?address={}&device_id={}",
+                    self.base_url, address_id, device_id
+                ))
+                .bearer_auth(token),
+        )
+        .await
+    }
+
+    async fn delete_group_invites(
+        &self,
+        token: &str,
+        address_id: u64,
+        group_ids: &[u64],
+    ) -> anyhow::Result<()> {
+        let mut url = format!(
+            "{}/group/invites?address={}&groupIds=",
+            self.base_url, address_id
+        );
+        comma_separated(&mut url, group_ids)?;
+
+        Self::send(self.client.delete(url).bearer_auth(token)).await?;
+        Ok(())
+    }
+
+    async fn request_group_re_adds(
+        &self,
+        token: &str,
+        address_id: u64,
+        device_id: u8,
+        group_ids: &[u64],
+    ) -> anyhow::Result<()> {
+        let mut url = format!(
+            "{}/group/reAdd?address={}&device_id={}&groupIds=",
+            self.base_url, address_id, device_id
+        );
+        comma_separated(&mut url, group_ids)?;
+
+        Self::send(self.client.post(url).bearer_auth(token)).await?;
+        Ok(())
+    }
+
+    async fn get_group_re_adds(
+        &self,
+        token: &str,
+        address_id: u64,
+        group_ids: &[u64],
+    ) -> anyhow::Result<firefly::GroupReAddRequests> {
+        let mut url = format!(
+            "{}/group/reAdds?address={}&groupIds=",
+            self.base_url, address_id
+        );
+        comma_separated(&mut url, group_ids)?;
+
+        Self::fetch(self.client.get(url).bearer_auth(token)).await
+    }
+
+    async fn delete_group_re_add(
+        &self,
+        token: &str,
+        group_id: u64,
+        address_id: u64,
+        my_address_id: u64,
+    ) -> anyhow::Result<()> {
+        Self::send(
+            self.client
+                .delete(format!(
+                    "{}/group/reAdd?groupId={}&address={}&myAddress={}",
+                    self.base_url, group_id, address_id, my_address_id
+                ))
+                .bearer_auth(token),
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn upload_file(
+        &self,
+        token: &str,
+        length: u64,
+        body: UploadBody,
+    ) -> anyhow::Result<String> {
+        let response = Self::send(
+            self.client
+                .post(format!("{}/file?length={}", self.base_url, length))
+                .bearer_auth(token)
+                .header(reqwest::header::CONTENT_LENGTH, length)
+                .header(reqwest::header::CONTENT_TYPE, "application/octet-stream")
+                .body(reqwest::Body::wrap_stream(body)),
+        )
+        .await?;
+
+        Ok(response.text().await?.trim().to_string())
+    }
+
+    async fn download_file(&self, url: &str) -> anyhow::Result<DownloadBody> {
+        let response = Self::send(self.client.get(url)).await?;
+
+        Ok(Box::pin(
+            response
+                .bytes_stream()
+                .map(|data| data.map_err(anyhow::Error::from)),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::stream;
+
+    use super::*;
+    use crate::mock_server::MockFirefly;
+
+    #[tokio::test]
+    async fn test_http_api_against_mock() {
+        let mock = MockFirefly::start().await.unwrap();
+        let api = HttpFireflyApi::new(mock.base_url.clone());
+        let token = MockFirefly::token("alice");
+
+        let tokens = api
+            .refresh_token(&mock.base_url, "client", "alice")
+            .await
+            .unwrap();
+        assert_eq!(tokens.access_token, token);
+        assert_eq!(tokens.refresh_token, "alice");
+
+        let address = api
+            .register_device(
+                &token,
+                &firefly::Address {
+                    device_id: 1,
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_ne!(address.id, 0);
+        assert_eq!(address.username, "alice");
+
+        let entries = firefly::PreKeyBundleEntries {
+            entries: (1..=3)
+                .map(|id| firefly::PreKeyBundleEntry {
+                    id,
+                    address: address.id,
+                    ..Default::default()
+                })
+                .collect(),
+        };
+        api.upload_pre_key_bundles(&token, &entries).await.unwrap();
+        api.delete_pre_key_bundles(&token, address.id, &[1, 3])
+            .await
+            .unwrap();
+
+        let ids = api
+            .get_pre_key_bundle_ids(&token, address.id)
+            .await
+            .unwrap();
+        assert_eq!(
+            ids.entries.iter().map(|entry| entry.id).collect::<Vec<_>>(),
+            vec![2]
+        );
+
+        // bob can't touch alice's bundles
+        let err = api
+            .delete_pre_key_bundles(&MockFirefly::token("bob"), address.id, &[2])
+            .await
+            .unwrap_err();
+        assert!(err.to_string().starts_with("unexpected status [403"));
+        assert!(matches!(
+            FireflyError::from_anyhow(err),
+            FireflyError::Server { code: 403, .. }
+        ));
+
+        let body: UploadBody = Box::pin(stream::iter([
+            Ok(Bytes::from_static(b"hello ")),
+            Ok(Bytes::from_static(b"world")),
+        ]));
+        let url = api.upload_file(&token, 11, body).await.unwrap();
+        let uploaded = api
+            .download_file(&url)
+            .await
+            .unwrap()
+            .map(|data| data.unwrap())
+            .concat()
+            .await;
+        assert_eq!(&uploaded[..], b"hello world");
+
+        api.deregister_device(&token, address.id).await.unwrap();
+        let state = mock.state();
+        assert!(state.address(address.id).is_none());
+        assert!(state.pre_key_bundles_of(address.id).is_empty());
+    }
+
+    async fn register(api: &HttpFireflyApi, username: &str) -> u64 {
+        api.register_device(
+            &MockFirefly::token(username),
+            &firefly::Address {
+                device_id: 1,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap()
+        .id
+    }
+
+    #[tokio::test]
+    async fn test_refresh_token_errors() {
+        use axum::{Router, http::StatusCode, routing::post};
+
+        let routes = Router::new()
+            .route(
+                "/throttled/oauth/token",
+                post(|| async { StatusCode::TOO_MANY_REQUESTS }),
+            )
+            .route(
+                "/revoked/oauth/token",
+                post(|| async { StatusCode::BAD_REQUEST }),
+            );
+        let mock = MockFirefly::start_with(routes).await.unwrap();
+        let api = HttpFireflyApi::new(mock.base_url.clone());
+
+        // a rate limit is retried, it doesn't log the user out
+        let err = api
+            .refresh_token(&format!("{}/throttled", mock.base_url), "client", "alice")
+            .await
+            .unwrap_err();
+        let err = FireflyError::from_anyhow(err);
+        assert!(matches!(err, FireflyError::Server { code: 429, .. }));
+        assert!(err.is_retryable());
+
+        let err = api
+            .refresh_token(&format!("{}/revoked", mock.base_url), "client", "alice")
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            FireflyError::from_anyhow(err),
+            FireflyError::AuthExpired(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_group_invites_and_re_adds_against_mock() {
+        let mock = MockFirefly::start().await.unwrap();
+        let api = HttpFireflyApi::new(mock.base_url.clone());
+        let alice = MockFirefly::token("alice");
+        let bob = MockFirefly::token("bob");
+        let alice_address = register(&api, "alice").await;
+        let bob_address = register(&api, "bob").await;
+
+        mock.state().invites.insert(
+            alice_address,
+            [7, 8]
+                .map(|group_id| firefly::GroupInvite {
+                    group_id,
+                    ..Default::default()
+                })
+                .to_vec(),
+        );
+        api.delete_group_invites(&alice, alice_address, &[7])
+            .await
+            .unwrap();
+        let invites = api
+            .get_group_invites(&alice, alice_address, 1)
+            .await
+            .unwrap();
+        assert_eq!(
+            invites
+                .invites
+                .iter()
+                .map(|invite| invite.group_id)
+                .collect::<Vec<_>>(),
+            vec![8]
+        );
+
+        api.request_group_re_adds(&alice, alice_address, 1, &[7])
+            .await
+            .unwrap();
+        let re_adds = api
+            .get_group_re_adds(&bob, bob_address, &[7, 8])
+            .await
+            .unwrap();
+        assert_eq!(re_adds.requests.len(), 1);
+        assert_eq!(re_adds.requests[0].address_id, alice_address);
+
+        api.delete_group_re_add(&bob, 7, alice_address, bob_address)
+            .await
+            .unwrap();
+        assert!(mock.state().re_adds.is_empty());
+        assert!(mock.state().unhandled.is_empty());
+    }
+}
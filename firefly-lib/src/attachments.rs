@@ -0,0 +1,914 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use aes::Aes256;
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce, aead::Aead};
+use bytes::Bytes;
+use ctr::{
+    Ctr64BE,
+    cipher::{KeyIvInit, StreamCipher},
+};
+use futures::{Stream, StreamExt};
+use rand::RngCore;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+
+use crate::{
+    FireflyError,
+    api::FireflyApi,
+    db::{
+        attachment_cache::{
+            AttachmentCacheStore, AttachmentCacheUsage, AttachmentOwner, CACHE_FILE_PREFIX,
+        },
+        setup_pool_from_path,
+    },
+    media,
+    pb::firefly::firefly::EncryptedFile,
+    utils::{get_current_timestamp_millis_since_epoch, rng},
+};
+
+/// Version byte leading `EncryptedFile.secret_key`. Version 1 keys come from
+/// the web client (unauthenticated AES-CTR), they're only read.
+pub const ATTACHMENT_KEY_VERSION: u8 = 2;
+pub const ATTACHMENT_KEY_LEN: usize = 32;
+const CTR_ATTACHMENT_KEY_VERSION: u8 = 1;
+const CTR_COUNTER_LEN: usize = 16;
+
+/// Plaintext bytes per sealed chunk, every chunk but the last one is full.
+pub const ATTACHMENT_CHUNK_LEN: usize = 64 * 1024;
+const TAG_LEN: usize = 16;
+const SEALED_CHUNK_LEN: usize = ATTACHMENT_CHUNK_LEN + TAG_LEN;
+
+/// Fresh symmetric key of a single attachment.
+#[derive(Clone)]
+pub struct AttachmentKey([u8; ATTACHMENT_KEY_LEN]);
+
+impl AttachmentKey {
+    pub fn generate() -> Self {
+        let mut key = [0u8; ATTACHMENT_KEY_LEN];
+        rng().fill_bytes(&mut key);
+        Self(key)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        let Some((&version, key)) = bytes.split_first() else {
+            anyhow::bail!("empty attachment key");
+        };
+        anyhow::ensure!(
+            version == ATTACHMENT_KEY_VERSION,
+            "unsupported attachment key version: {}",
+            version
+        );
+
+        let key = key
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("invalid attachment key length: {}", key.len()))?;
+        Ok(Self(key))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(1 + ATTACHMENT_KEY_LEN);
+        bytes.push(ATTACHMENT_KEY_VERSION);
+        bytes.extend_from_slice(&self.0);
+        bytes
+    }
+
+    fn cipher(&self) -> Aes256Gcm {
+        Aes256Gcm::new(&self.0.into())
+    }
+}
+
+/// Nonce of a chunk: its index and whether it is the last one, so chunks
+/// can't be reordered, dropped or the file cut at a chunk boundary.
+fn chunk_nonce(index: u64, last: bool) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[3..11].copy_from_slice(&index.to_be_bytes());
+    nonce[11] = last as u8;
+    nonce
+}
+
+/// Size of the sealed stream for `len` plaintext bytes.
+pub fn sealed_len(len: u64) -> u64 {
+    len + (len / ATTACHMENT_CHUNK_LEN as u64 + 1) * TAG_LEN as u64
+}
+
+pub struct ChunkSealer {
+    cipher: Aes256Gcm,
+    index: u64,
+    finished: bool,
+}
+
+impl ChunkSealer {
+    pub fn new(key: &AttachmentKey) -> Self {
+        Self {
+            cipher: key.cipher(),
+            index: 0,
+            finished: false,
+        }
+    }
+
+    /// Seals the next chunk, which has to be full unless it is the `last`.
+    pub fn seal(&mut self, chunk: &[u8], last: bool) -> anyhow::Result<Vec<u8>> {
+        anyhow::ensure!(!self.finished, "attachment already sealed");
+        anyhow::ensure!(
+            chunk.len() == ATTACHMENT_CHUNK_LEN || (last && chunk.len() < ATTACHMENT_CHUNK_LEN),
+            "unexpected chunk length: {}",
+            chunk.len()
+        );
+
+        let nonce = chunk_nonce(self.index, last);
+        let sealed = self
+            .cipher
+            .encrypt(Nonce::from_slice(&nonce), chunk)
+            .map_err(|_| anyhow::anyhow!("failed to seal attachment chunk"))?;
+
+        self.index += 1;
+        self.finished = last;
+        Ok(sealed)
+    }
+}
+
+/// Opens a sealed stream fed in arbitrary pieces. A full chunk is only opened
+/// once more data follows it, the remainder is the last chunk.
+pub struct ChunkOpener {
+    cipher: Aes256Gcm,
+    index: u64,
+    buffer: Vec<u8>,
+}
+
+impl ChunkOpener {
+    pub fn new(key: &AttachmentKey) -> Self {
+        Self {
+            cipher: key.cipher(),
+            index: 0,
+            buffer: Vec::with_capacity(SEALED_CHUNK_LEN),
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+        self.buffer.extend_from_slice(data);
+
+        let mut plain = Vec::new();
+        while self.buffer.len() > SEALED_CHUNK_LEN {
+            let rest = self.buffer.split_off(SEALED_CHUNK_LEN);
+            let sealed = std::mem::replace(&mut self.buffer, rest);
+            plain.extend(self.open(&sealed, false)?);
+        }
+        Ok(plain)
+    }
+
+    pub fn finish(mut self) -> anyhow::Result<Vec<u8>> {
+        let sealed = std::mem::take(&mut self.buffer);
+        self.open(&sealed, true)
+    }
+
+    fn open(&mut self, sealed: &[u8], last: bool) -> anyhow::Result<Vec<u8>> {
+        let nonce = chunk_nonce(self.index, last);
+        let plain = self
+            .cipher
+            .decrypt(Nonce::from_slice(&nonce), sealed)
+            .map_err(|_| anyhow::anyhow!("attachment chunk {} failed to open", self.index))?;
+
+        self.index += 1;
+        Ok(plain)
+    }
+}
+
+/// Opens a downloaded attachment of either key version. Version 1 is
+/// AES-256-CTR with the key followed by the initial counter block, whose low
+/// 64 bits count big endian like the web client's `length: 64`. Nothing but
+/// its length can be checked.
+pub enum AttachmentOpener {
+    Ctr(Box<Ctr64BE<Aes256>>),
+    Sealed(ChunkOpener),
+}
+
+impl AttachmentOpener {
+    pub fn new(secret_key: &[u8]) -> anyhow::Result<Self> {
+        let Some((&CTR_ATTACHMENT_KEY_VERSION, rest)) = secret_key.split_first() else {
+            return Ok(Self::Sealed(ChunkOpener::new(&AttachmentKey::from_bytes(
+                secret_key,
+            )?)));
+        };
+        anyhow::ensure!(
+            rest.len() == ATTACHMENT_KEY_LEN + CTR_COUNTER_LEN,
+            "invalid attachment key length: {}",
+            rest.len()
+        );
+
+        let (key, counter) = rest.split_at(ATTACHMENT_KEY_LEN);
+        let cipher = Ctr64BE::<Aes256>::new(key.into(), counter.into());
+        Ok(Self::Ctr(Box::new(cipher)))
+    }
+
+    pub fn update(&mut self, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+        match self {
+            Self::Ctr(cipher) => {
+                let mut plain = data.to_vec();
+                cipher.apply_keystream(&mut plain);
+                Ok(plain)
+            }
+            Self::Sealed(opener) => opener.update(data),
+        }
+    }
+
+    pub fn finish(self) -> anyhow::Result<Vec<u8>> {
+        match self {
+            Self::Ctr(_) => Ok(vec![]),
+            Self::Sealed(opener) => opener.finish(),
+        }
+    }
+}
+
+async fn read_chunk(reader: &mut (impl AsyncRead + Unpin)) -> std::io::Result<Vec<u8>> {
+    let mut chunk = vec![0u8; ATTACHMENT_CHUNK_LEN];
+    let mut filled = 0;
+    while filled < ATTACHMENT_CHUNK_LEN {
+        let n = reader.read(&mut chunk[filled..]).await?;
+        if n == 0 {
+            break;
+        }
+        filled += n;
+    }
+    chunk.truncate(filled);
+    Ok(chunk)
+}
+
+/// Sealed chunks of everything `reader` yields, read one chunk at a time.
+pub fn seal_stream<R>(
+    reader: R,
+    key: &AttachmentKey,
+) -> impl Stream<Item = anyhow::Result<Vec<u8>>> + use<R>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    let state = Some((reader, ChunkSealer::new(key)));
+    futures::stream::try_unfold(state, |state| async move {
+        let Some((mut reader, mut sealer)) = state else {
+            return Ok(None);
+        };
+
+        let chunk = read_chunk(&mut reader).await?;
+        let last = chunk.len() < ATTACHMENT_CHUNK_LEN;
+        let sealed = sealer.seal(&chunk, last)?;
+
+        Ok(Some((sealed, (!last).then_some((reader, sealer)))))
+    })
+}
+
+/// Encrypts the file at `path` with a fresh key while uploading it to the
+/// Firefly file endpoint, which answers with the url the file is served from.
+/// Images and videos also get their metadata and a thumbnail where possible.
+pub async fn upload_attachment(
+    api: &dyn FireflyApi,
+    token: &str,
+    path: &Path,
+    content_type: u32,
+) -> anyhow::Result<EncryptedFile> {
+    let file = tokio::fs::File::open(path).await?;
+    let len = file.metadata().await?.len();
+    let content_length =
+        u32::try_from(len).map_err(|_| anyhow::anyhow!("attachment too large: {} bytes", len))?;
+
+    let key = AttachmentKey::generate();
+    let body = Box::pin(seal_stream(file, &key).map(|chunk| chunk.map(Bytes::from)));
+
+    let url = api.upload_file(token, sealed_len(len), body).await?;
+    anyhow::ensure!(!url.is_empty(), "file endpoint returned no url");
+
+    // a file without preview is still worth sending
+    let metadata = match media::extract_media_metadata(path.to_path_buf()).await {
+        Ok(metadata) => metadata,
+        Err(err) => {
+            log::warn!("failed to read media metadata of {:?}: {:?}", path, err);
+            None
+        }
+    };
+
+    Ok(EncryptedFile {
+        url,
+        secret_key: key.to_bytes(),
+        content_type,
+        content_length,
+        metadata,
+    })
+}
+
+const PARTIAL_SUFFIX: &str = ".part";
+
+/// Names are relative to the file server directory, no nesting.
+fn check_file_name(file_name: &str) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        !file_name.is_empty()
+            && file_name != "."
+            && file_name != ".."
+            && !file_name.contains(['/', '\\']),
+        "invalid attachment file name: {:?}",
+        file_name
+    );
+    Ok(())
+}
+
+/// Downloads and decrypts `file` into `dir/file_name`. The result only shows
+/// up under its name once it was fully authenticated.
+pub async fn download_attachment(
+    api: &dyn FireflyApi,
+    file: &EncryptedFile,
+    dir: &Path,
+    file_name: &str,
+) -> anyhow::Result<PathBuf> {
+    check_file_name(file_name)?;
+    let opener = AttachmentOpener::new(&file.secret_key)?;
+
+    let body = api.download_file(&file.url).await?;
+
+    let path = dir.join(file_name);
+    // unique, so concurrent downloads of the same file don't interleave
+    let partial_path = dir.join(format!(
+        "{}.{:x}{}",
+        file_name,
+        rng().next_u64(),
+        PARTIAL_SUFFIX
+    ));
+
+    let written = write_opened(body, opener, &partial_path, file.content_length as u64).await;
+    if let Err(err) = written {
+        let _ = tokio::fs::remove_file(&partial_path).await;
+        return Err(err);
+    }
+
+    tokio::fs::rename(&partial_path, &path).await?;
+    Ok(path)
+}
+
+async fn write_opened(
+    mut stream: impl Stream<Item = anyhow::Result<Bytes>> + Unpin,
+    mut opener: AttachmentOpener,
+    path: &Path,
+    content_length: u64,
+) -> anyhow::Result<()> {
+    let mut out = tokio::fs::File::create(path).await?;
+    let mut written = 0u64;
+
+    while let Some(data) = stream.next().await {
+        let plain = opener.update(&data?)?;
+        written += plain.len() as u64;
+        anyhow::ensure!(
+            written <= content_length,
+            "attachment larger than {} bytes",
+            content_length
+        );
+        out.write_all(&plain).await?;
+    }
+
+    let plain = opener.finish()?;
+    written += plain.len() as u64;
+    anyhow::ensure!(
+        written == content_length,
+        "attachment length mismatch, expected {} got {}",
+        content_length,
+        written
+    );
+    out.write_all(&plain).await?;
+    out.flush().await?;
+
+    Ok(())
+}
+
+/// Default size limit of the attachment cache, 1 GiB.
+pub const DEFAULT_ATTACHMENT_CACHE_QUOTA: u64 = 1 << 30;
+
+/// Decrypted attachments in the file server directory. Every file is recorded
+/// with the message it belongs to, the least recently used ones are evicted
+/// once the cache grows past its quota.
+pub struct AttachmentCache {
+    store: AttachmentCacheStore,
+    api: Arc<dyn FireflyApi>,
+    dir: PathBuf,
+    quota: AtomicU64,
+    /// held shared by downloads and exclusively while files are deleted, so
+    /// garbage collection never sees a download in progress
+    lock: tokio::sync::RwLock<()>,
+}
+
+impl AttachmentCache {
+    pub async fn new(
+        store: AttachmentCacheStore,
+        api: Arc<dyn FireflyApi>,
+        dir: PathBuf,
+        default_quota: u64,
+    ) -> anyhow::Result<Self> {
+        let quota = store.get_quota().await?.unwrap_or(default_quota);
+
+        Ok(Self {
+            store,
+            api,
+            dir,
+            quota: AtomicU64::new(quota),
+            lock: Default::default(),
+        })
+    }
+
+    /// Downloads go through `api`, like the one of the client the messages
+    /// come from.
+    pub async fn from_path(
+        db_path: String,
+        api: Arc<dyn FireflyApi>,
+        dir: String,
+        default_quota: u64,
+    ) -> Result<Self, FireflyError> {
+        let pool = setup_pool_from_path(&db_path, 5)
+            .await
+            .map_err(FireflyError::from_anyhow)?;
+        let store = AttachmentCacheStore::new(pool)
+            .await
+            .map_err(FireflyError::from_anyhow)?;
+
+        Self::new(store, api, dir.into(), default_quota)
+            .await
+            .map_err(FireflyError::from_anyhow)
+    }
+
+    /// Name under which the `index`th attachment of `owner` is served,
+    /// downloaded and decrypted first unless it is cached already.
+    pub async fn fetch(
+        &self,
+        file: EncryptedFile,
+        owner: AttachmentOwner,
+        index: u32,
+    ) -> Result<String, FireflyError> {
+        self.fetch_inner(&file, &owner, index as usize)
+            .await
+            .map_err(FireflyError::from_anyhow)
+    }
+
+    async fn fetch_inner(
+        &self,
+        file: &EncryptedFile,
+        owner: &AttachmentOwner,
+        index: usize,
+    ) -> anyhow::Result<String> {
+        let file_name = owner.file_name(index);
+        let now = get_current_timestamp_millis_since_epoch();
+
+        if self.store.get(&file_name).await?.is_some()
+            && tokio::fs::try_exists(self.dir.join(&file_name)).await?
+        {
+            self.store.touch(&file_name, now).await?;
+            return Ok(file_name);
+        }
+
+        {
+            let _downloading = self.lock.read().await;
+            let path = download_attachment(self.api.as_ref(), file, &self.dir, &file_name).await?;
+            let size = tokio::fs::metadata(&path).await?.len();
+            self.store.record(&file_name, owner, size, now).await?;
+        }
+
+        self.evict(Some(&file_name)).await?;
+
+        Ok(file_name)
+    }
+
+    /// Drops the attachments of direct messages that were deleted.
+    pub async fn remove_user_messages(
+        &self,
+        other: String,
+        message_ids: Vec<u64>,
+    ) -> Result<(), FireflyError> {
+        async {
+            let file_names = self
+                .store
+                .get_of_user_messages(&other, &message_ids)
+                .await?;
+            self.remove(&file_names).await
+        }
+        .await
+        .map_err(FireflyError::from_anyhow)
+    }
+
+    /// Drops the attachments of group messages that were deleted.
+    pub async fn remove_group_messages(
+        &self,
+        group_id: u64,
+        message_ids: Vec<u64>,
+    ) -> Result<(), FireflyError> {
+        async {
+            let file_names = self
+                .store
+                .get_of_group_messages(group_id, &message_ids)
+                .await?;
+            self.remove(&file_names).await
+        }
+        .await
+        .map_err(FireflyError::from_anyhow)
+    }
+
+    pub async fn remove_group(&self, group_id: u64) -> Result<(), FireflyError> {
+        async {
+            let file_names = self.store.get_of_group(group_id).await?;
+            self.remove(&file_names).await
+        }
+        .await
+        .map_err(FireflyError::from_anyhow)
+    }
+
+    /// Drops every cached attachment, for a logout that doesn't keep the
+    /// message history.
+    pub async fn remove_all(&self) -> Result<(), FireflyError> {
+        async {
+            let file_names = self
+                .store
+                .get_all_by_last_access()
+                .await?
+                .into_iter()
+                .map(|attachment| attachment.file_name)
+                .collect::<Vec<_>>();
+            self.remove(&file_names).await
+        }
+        .await
+        .map_err(FireflyError::from_anyhow)
+    }
+
+    /// Waits for running downloads and garbage collection, then closes the
+    /// cache database. The cache can't be used afterwards.
+    pub async fn close(&self) {
+        let _guard = self.lock.write().await;
+        self.store.close().await;
+    }
+
+    pub async fn usage(&self) -> Result<AttachmentCacheUsage, FireflyError> {
+        self.store.usage().await.map_err(FireflyError::from_anyhow)
+    }
+
+    pub fn quota(&self) -> u64 {
+        self.quota.load(Ordering::Relaxed)
+    }
+
+    /// Persists the new quota and evicts down to it right away.
+    pub async fn set_quota(&self, quota: u64) -> Result<(), FireflyError> {
+        async {
+            self.store.set_quota(quota).await?;
+            self.quota.store(quota, Ordering::Relaxed);
+            self.evict(None).await
+        }
+        .await
+        .map_err(FireflyError::from_anyhow)
+    }
+
+    /// Reconciles the directory with the records: forgets files that are
+    /// gone, deletes files of the cache nobody recorded (including interrupted
+    /// downloads) and evicts down to the quota. Files saved by the UI are left
+    /// alone.
+    pub async fn collect_garbage(&self) -> Result<(), FireflyError> {
+        self.collect_garbage_inner()
+            .await
+            .map_err(FireflyError::from_anyhow)
+    }
+
+    async fn collect_garbage_inner(&self) -> anyhow::Result<()> {
+        {
+            let _guard = self.lock.write().await;
+
+            let recorded = self.store.get_all_by_last_access().await?;
+            let mut missing = Vec::new();
+            for attachment in &recorded {
+                if !tokio::fs::try_exists(self.dir.join(&attachment.file_name)).await? {
+                    missing.push(attachment.file_name.clone());
+                }
+            }
+            self.store.remove(&missing).await?;
+
+            let recorded = recorded
+                .into_iter()
+                .map(|attachment| attachment.file_name)
+                .collect::<std::collections::HashSet<_>>();
+
+            let mut entries = tokio::fs::read_dir(&self.dir).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                if !entry.file_type().await?.is_file() {
+                    continue;
+                }
+                let file_name = entry.file_name().to_string_lossy().to_string();
+                if file_name.starts_with(CACHE_FILE_PREFIX) && !recorded.contains(&file_name) {
+                    log::info!("removing untracked attachment {}", file_name);
+                    remove_file_if_exists(&entry.path()).await?;
+                }
+            }
+        }
+
+        self.evict(None).await
+    }
+
+    async fn remove(&self, file_names: &[String]) -> anyhow::Result<()> {
+        let _guard = self.lock.write().await;
+
+        for file_name in file_names {
+            remove_file_if_exists(&self.dir.join(file_name)).await?;
+        }
+        self.store.remove(file_names).await
+    }
+
+    /// Deletes least recently used files until the cache fits the quota,
+    /// `keep` is spared even if it alone is larger.
+    async fn evict(&self, keep: Option<&str>) -> anyhow::Result<()> {
+        let _guard = self.lock.write().await;
+
+        let quota = self.quota();
+        let attachments = self.store.get_all_by_last_access().await?;
+        let mut total = attachments
+            .iter()
+            .map(|attachment| attachment.size)
+            .sum::<u64>();
+
+        let mut evicted = Vec::new();
+        for attachment in attachments {
+            if total <= quota {
+                break;
+            }
+            if Some(attachment.file_name.as_str()) == keep {
+                continue;
+            }
+
+            remove_file_if_exists(&self.dir.join(&attachment.file_name)).await?;
+            total -= attachment.size;
+            evicted.push(attachment.file_name);
+        }
+
+        if !evicted.is_empty() {
+            log::info!("evicted {} attachments from the cache", evicted.len());
+        }
+        self.store.remove(&evicted).await
+    }
+}
+
+async fn remove_file_if_exists(path: &Path) -> std::io::Result<()> {
+    match tokio::fs::remove_file(path).await {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn seal_all(data: &[u8], key: &AttachmentKey) -> Vec<u8> {
+        let chunks = seal_stream(std::io::Cursor::new(data.to_vec()), key)
+            .collect::<Vec<_>>()
+            .await;
+        chunks
+            .into_iter()
+            .flat_map(|chunk| chunk.unwrap())
+            .collect()
+    }
+
+    fn open_all(sealed: &[u8], key: &AttachmentKey, piece: usize) -> anyhow::Result<Vec<u8>> {
+        let mut opener = ChunkOpener::new(key);
+        let mut plain = Vec::new();
+        for data in sealed.chunks(piece) {
+            plain.extend(opener.update(data)?);
+        }
+        plain.extend(opener.finish()?);
+        Ok(plain)
+    }
+
+    #[tokio::test]
+    async fn test_seal_and_open_round_trip() {
+        let key = AttachmentKey::generate();
+
+        for len in [
+            0,
+            1,
+            ATTACHMENT_CHUNK_LEN - 1,
+            ATTACHMENT_CHUNK_LEN,
+            2 * ATTACHMENT_CHUNK_LEN + 7,
+        ] {
+            let data = (0..len).map(|i| i as u8).collect::<Vec<_>>();
+            let sealed = seal_all(&data, &key).await;
+            assert_eq!(sealed.len() as u64, sealed_len(len as u64));
+
+            for piece in [1000, SEALED_CHUNK_LEN, sealed.len().max(1)] {
+                assert_eq!(open_all(&sealed, &key, piece).unwrap(), data);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_open_rejects_tampering() {
+        let key = AttachmentKey::generate();
+        let data = vec![7u8; 2 * ATTACHMENT_CHUNK_LEN + 3];
+        let sealed = seal_all(&data, &key).await;
+
+        let mut flipped = sealed.clone();
+        flipped[10] ^= 1;
+        assert!(open_all(&flipped, &key, 4096).is_err());
+
+        // cut at a chunk boundary
+        assert!(open_all(&sealed[..SEALED_CHUNK_LEN], &key, 4096).is_err());
+
+        let mut swapped = sealed[SEALED_CHUNK_LEN..2 * SEALED_CHUNK_LEN].to_vec();
+        swapped.extend_from_slice(&sealed[..SEALED_CHUNK_LEN]);
+        swapped.extend_from_slice(&sealed[2 * SEALED_CHUNK_LEN..]);
+        assert!(open_all(&swapped, &key, 4096).is_err());
+
+        assert!(open_all(&sealed, &AttachmentKey::generate(), 4096).is_err());
+    }
+
+    #[test]
+    fn test_attachment_key_bytes() {
+        let key = AttachmentKey::generate();
+        let bytes = key.to_bytes();
+        assert_eq!(bytes[0], ATTACHMENT_KEY_VERSION);
+        assert_eq!(AttachmentKey::from_bytes(&bytes).unwrap().0, key.0);
+
+        let mut v1 = bytes.clone();
+        v1[0] = 1;
+        assert!(AttachmentKey::from_bytes(&v1).is_err());
+        assert!(AttachmentKey::from_bytes(&bytes[..10]).is_err());
+        assert!(AttachmentKey::from_bytes(&[]).is_err());
+    }
+
+    /// `encryptStream` of the web client: every piece restarts the counter
+    /// block at the blocks used so far, pieces are whole blocks but the last.
+    fn web_encrypt(data: &[u8], key: &[u8], counter: [u8; 16], piece: usize) -> Vec<u8> {
+        let start = u64::from_be_bytes(counter[8..].try_into().unwrap());
+        let mut blocks = 0u64;
+        let mut encrypted = Vec::new();
+        for chunk in data.chunks(piece) {
+            let mut block = counter;
+            block[8..].copy_from_slice(&start.wrapping_add(blocks).to_be_bytes());
+            blocks += chunk.len().div_ceil(16) as u64;
+
+            let mut chunk = chunk.to_vec();
+            Ctr64BE::<Aes256>::new(key.into(), &block.into()).apply_keystream(&mut chunk);
+            encrypted.extend(chunk);
+        }
+        encrypted
+    }
+
+    #[test]
+    fn test_open_web_client_attachment() {
+        let key = [3u8; ATTACHMENT_KEY_LEN];
+        // the low half is about to wrap, it doesn't carry into the high half
+        let mut counter = [9u8; CTR_COUNTER_LEN];
+        counter[8..].copy_from_slice(&(u64::MAX - 1).to_be_bytes());
+
+        let mut secret_key = vec![CTR_ATTACHMENT_KEY_VERSION];
+        secret_key.extend_from_slice(&key);
+        secret_key.extend_from_slice(&counter);
+
+        let data = (0..100_000).map(|i| i as u8).collect::<Vec<_>>();
+        let encrypted = web_encrypt(&data, &key, counter, ATTACHMENT_CHUNK_LEN);
+
+        for piece in [7, 4096, encrypted.len()] {
+            let mut opener = AttachmentOpener::new(&secret_key).unwrap();
+            let mut plain = Vec::new();
+            for data in encrypted.chunks(piece) {
+                plain.extend(opener.update(data).unwrap());
+            }
+            plain.extend(opener.finish().unwrap());
+            assert_eq!(plain, data);
+        }
+
+        assert!(AttachmentOpener::new(&secret_key[..40]).is_err());
+        assert!(AttachmentOpener::new(&[]).is_err());
+    }
+
+    async fn test_cache(quota: u64) -> (AttachmentCache, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("attachment-cache-{:x}", rng().next_u64()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+
+        let pool = crate::db::setup_pool(":memory:", 1).await.unwrap();
+        let store = AttachmentCacheStore::new(pool).await.unwrap();
+        let api = Arc::new(crate::api::HttpFireflyApi::new(String::new()));
+        let cache = AttachmentCache::new(store, api, dir.clone(), quota)
+            .await
+            .unwrap();
+        (cache, dir)
+    }
+
+    /// Stands in for a download, the cache only sees the file and its record.
+    async fn put(cache: &AttachmentCache, owner: &AttachmentOwner, size: usize, now: u64) {
+        let file_name = owner.file_name(0);
+        tokio::fs::write(cache.dir.join(&file_name), vec![0u8; size])
+            .await
+            .unwrap();
+        cache
+            .store
+            .record(&file_name, owner, size as u64, now)
+            .await
+            .unwrap();
+    }
+
+    fn user(message_id: u64) -> AttachmentOwner {
+        AttachmentOwner::User {
+            other: "alice".to_string(),
+            message_id,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cache_evicts_least_recently_used() {
+        let (cache, dir) = test_cache(250).await;
+
+        put(&cache, &user(1), 100, 1).await;
+        put(&cache, &user(2), 100, 2).await;
+        put(&cache, &user(3), 100, 3).await;
+        cache.store.touch("cache-u-alice-1-0", 4).await.unwrap();
+
+        cache.evict(None).await.unwrap();
+        assert!(
+            tokio::fs::try_exists(dir.join("cache-u-alice-1-0"))
+                .await
+                .unwrap()
+        );
+        assert!(
+            !tokio::fs::try_exists(dir.join("cache-u-alice-2-0"))
+                .await
+                .unwrap()
+        );
+        assert_eq!(cache.usage().await.unwrap().bytes, 200);
+
+        // a file larger than the quota survives its own fetch
+        put(&cache, &user(4), 300, 5).await;
+        cache.evict(Some("cache-u-alice-4-0")).await.unwrap();
+        assert_eq!(cache.usage().await.unwrap().files, 1);
+        assert!(
+            tokio::fs::try_exists(dir.join("cache-u-alice-4-0"))
+                .await
+                .unwrap()
+        );
+
+        cache.set_quota(0).await.unwrap();
+        assert_eq!(cache.usage().await.unwrap().files, 0);
+        assert_eq!(cache.store.get_quota().await.unwrap(), Some(0));
+
+        tokio::fs::remove_dir_all(dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_cache_removes_deleted_messages_and_garbage() {
+        let (cache, dir) = test_cache(DEFAULT_ATTACHMENT_CACHE_QUOTA).await;
+
+        put(&cache, &user(1), 10, 1).await;
+        put(&cache, &user(2), 10, 2).await;
+        let group = AttachmentOwner::Group {
+            group_id: 9,
+            message_id: 1,
+        };
+        put(&cache, &group, 10, 3).await;
+
+        cache
+            .remove_user_messages("alice".to_string(), vec![1])
+            .await
+            .unwrap();
+        assert!(
+            !tokio::fs::try_exists(dir.join("cache-u-alice-1-0"))
+                .await
+                .unwrap()
+        );
+        cache.remove_group(9).await.unwrap();
+        assert!(
+            !tokio::fs::try_exists(dir.join("cache-g-9-1-0"))
+                .await
+                .unwrap()
+        );
+
+        tokio::fs::write(dir.join("cache-u-alice-3-0.1f.part"), b"x")
+            .await
+            .unwrap();
+        // saved by the UI, not the cache's to delete
+        tokio::fs::write(dir.join("saved"), b"x").await.unwrap();
+        tokio::fs::remove_file(dir.join("cache-u-alice-2-0"))
+            .await
+            .unwrap();
+        cache.collect_garbage().await.unwrap();
+
+        assert!(
+            !tokio::fs::try_exists(dir.join("cache-u-alice-3-0.1f.part"))
+                .await
+                .unwrap()
+        );
+        assert!(tokio::fs::try_exists(dir.join("saved")).await.unwrap());
+        assert_eq!(
+            cache.usage().await.unwrap(),
+            AttachmentCacheUsage::default()
+        );
+
+        tokio::fs::remove_dir_all(dir).await.unwrap();
+    }
+
+    #[test]
+    fn test_check_file_name() {
+        assert!(check_file_name("123_0").is_ok());
+        assert!(check_file_name("").is_err());
+        assert!(check_file_name("..").is_err());
+        assert!(check_file_name("../secret").is_err());
+        assert!(check_file_name("a\\b").is_err());
+    }
+}
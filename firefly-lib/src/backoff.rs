@@ -0,0 +1,84 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+/// Capped exponential delay without jitter, `attempt` starts at 1.
+pub fn exponential_delay(initial: Duration, max: Duration, attempt: u32) -> Duration {
+    let exponent = attempt.saturating_sub(1).min(31);
+    initial.saturating_mul(1u32 << exponent).min(max)
+}
+
+/// "Equal jitter": keeps at least half of the delay and randomizes the rest,
+/// so clients that lost the connection together don't retry in lockstep.
+pub fn with_jitter(delay: Duration) -> Duration {
+    let half = delay / 2;
+    let jitter_millis = half.as_millis() as u64;
+    if jitter_millis == 0 {
+        return delay;
+    }
+    half + Duration::from_millis(rand::rng().random_range(0..=jitter_millis))
+}
+
+/// Retry schedule for reconnecting, reset once a connection proved healthy.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    attempt: u32,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max: max.max(initial),
+            attempt: 0,
+        }
+    }
+
+    pub fn next_delay(&mut self) -> Duration {
+        self.attempt = self.attempt.saturating_add(1);
+        with_jitter(exponential_delay(self.initial, self.max, self.attempt))
+    }
+
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exponential_delay_is_capped() {
+        let initial = Duration::from_secs(1);
+        let max = Duration::from_secs(60);
+
+        assert_eq!(exponential_delay(initial, max, 1), Duration::from_secs(1));
+        assert_eq!(exponential_delay(initial, max, 2), Duration::from_secs(2));
+        assert_eq!(exponential_delay(initial, max, 4), Duration::from_secs(8));
+        assert_eq!(exponential_delay(initial, max, 7), max);
+        assert_eq!(exponential_delay(initial, max, u32::MAX), max);
+    }
+
+    #[test]
+    fn test_backoff_jitter_and_reset() {
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(10));
+
+        for attempt in 1..=10 {
+            let delay = backoff.next_delay();
+            let full = exponential_delay(Duration::from_secs(1), Duration::from_secs(10), attempt);
+            assert!(delay >= full / 2 && delay <= full);
+        }
+        assert_eq!(backoff.attempt(), 10);
+
+        backoff.reset();
+        assert_eq!(backoff.attempt(), 0);
+        assert!(backoff.next_delay() <= Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,175 @@
+use sqlx::SqlitePool;
+
+use super::keyvalue::KEY_FCM_TOKEN;
+
+/// Tables of `firefly.db` that belong to the logged in account: its signal
+/// keys and sessions, the mls group state, contacts and the outbox. Group
+/// message history is kept separately in `GroupMessagesStore`.
+const ACCOUNT_TABLES: &[&str] = &[
+    "addresses",
+    "pre_keys",
+    "signed_pre_keys",
+    "kyber_pre_keys",
+    "sessions",
+    "identities",
+    "identity_keypair",
+    "sender_keys",
+    "group_epoch_states",
+    "group_states",
+    "self_group_key_packages",
+    "group_key_packages",
+    "group_infos",
+    "user_message_outbox",
+    "user_message_outbox_deliveries",
+    "user_message_inbox",
+];
+
+/// Settings of direct conversations and groups. A kept history keeps them, so
+/// disappearing timers still expire its messages.
+const SETTINGS_TABLES: &[&str] = &["conversations", "group_settings"];
+
+/// Deletes everything `firefly.db` knows about the account, including the
+/// tokens and the group identity in `key_value_store`. The fcm token belongs
+/// to the installation and is kept for the next login, conversation settings
+/// are kept with `keep_history`.
+pub async fn delete_account_state(pool: &SqlitePool, keep_history: bool) -> anyhow::Result<()> {
+    log::info!(
+        "store delete: account state, history kept: {}",
+        keep_history
+    );
+    let mut tx = pool.begin().await?;
+
+    let settings_tables = SETTINGS_TABLES.iter().filter(|_| !keep_history);
+    for table in ACCOUNT_TABLES.iter().chain(settings_tables) {
+        sqlx::query(&format!("DELETE FROM {}", table))
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    sqlx::query("DELETE FROM key_value_store WHERE key <> ?")
+        .bind(KEY_FCM_TOKEN)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{
+        conversations::{ConversationSettings, ConversationStore},
+        keyvalue::KeyValueStore,
+        setup_pool,
+        stores::KeyStores,
+    };
+
+    #[tokio::test]
+    async fn test_delete_account_state() {
+        let pool = setup_pool(":memory:", 1).await.unwrap();
+        let stores = KeyStores::new(pool.clone()).await.unwrap();
+        let key_value_store = KeyValueStore::new(pool.clone()).await.unwrap();
+        key_value_store.set(KEY_FCM_TOKEN, "fcm").await.unwrap();
+        key_value_store
+            .set("auth0_access_token", "token")
+            .await
+            .unwrap();
+
+        let identity = stores
+            .identity_store
+            .get_full_identity_key_pair()
+            .await
+            .unwrap();
+
+        delete_account_state(&pool, false).await.unwrap();
+
+        assert!(
+            stores
+                .identity_store
+                .get_full_identity_key_pair()
+                .await
+                .is_err()
+        );
+        assert!(key_value_store.get("auth0_access_token").await.is_err());
+        assert_eq!(key_value_store.get(KEY_FCM_TOKEN).await.unwrap(), "fcm");
+
+        // the next login starts with a new identity
+        stores
+            .identity_store
+            .create_key_pair_if_missing()
+            .await
+            .unwrap();
+        let new_identity = stores
+            .identity_store
+            .get_full_identity_key_pair()
+            .await
+            .unwrap();
+        assert_ne!(
+            new_identity.keypair.serialize(),
+            identity.keypair.serialize()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_delete_account_state_keeping_history() {
+        let pool = setup_pool(":memory:", 1).await.unwrap();
+        let stores = KeyStores::new(pool.clone()).await.unwrap();
+        let conversation_store = ConversationStore::new(pool.clone()).await.unwrap();
+        let settings = ConversationSettings {
+            disappearing_timer_secs: 60,
+            ..Default::default()
+        };
+        conversation_store
+            .set_conversation("bob", settings)
+            .await
+            .unwrap();
+        conversation_store
+            .set_group_settings(1, settings)
+            .await
+            .unwrap();
+
+        delete_account_state(&pool, true).await.unwrap();
+
+        assert!(
+            stores
+                .identity_store
+                .get_full_identity_key_pair()
+                .await
+                .is_err()
+        );
+        assert_eq!(
+            conversation_store
+                .get_disappearing_conversations()
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(
+            conversation_store
+                .get_disappearing_groups()
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+
+        delete_account_state(&pool, false).await.unwrap();
+
+        assert!(
+            conversation_store
+                .get_conversation("bob")
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            conversation_store
+                .get_group_settings(1)
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+}
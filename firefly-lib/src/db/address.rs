@@ -1,6 +1,8 @@
 use sqlx::{Pool, Sqlite};
 use sqlx::{SqlitePool, prelude::*};
 
+use crate::db::migrations::{FIREFLY_DB_MIGRATIONS, migrate};
+
 #[derive(Clone)]
 pub struct AddressStore {
     pool: SqlitePool,
@@ -8,18 +10,7 @@ pub struct AddressStore {
 
 impl AddressStore {
     pub async fn new(pool: Pool<Sqlite>) -> anyhow::Result<Self> {
-        let mut db = pool.acquire().await?;
-
-        db.execute(
-            "CREATE TABLE IF NOT EXISTS addresses (id INTEGER, username TEXT, device_id INTEGER)",
-        )
-        .await?;
-
-        db.execute("CREATE INDEX IF NOT EXISTS addresses_by_username ON addresses (username)")
-            .await?;
-
-        db.execute("CREATE INDEX IF NOT EXISTS addresses_by_id ON addresses (id)")
-            .await?;
+        migrate(&pool, FIREFLY_DB_MIGRATIONS).await?;
 
         Ok(Self { pool })
     }
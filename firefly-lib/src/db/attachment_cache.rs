@@ -0,0 +1,402 @@
+use sqlx::{SqlitePool, prelude::*};
+
+use crate::db::{
+    messages::placeholders,
+    migrations::{ATTACHMENT_CACHE_DB_MIGRATIONS, migrate},
+};
+
+/// Starts the names of every file the cache writes, the file server directory
+/// also holds files saved by the UI.
+pub const CACHE_FILE_PREFIX: &str = "cache-";
+
+/// Message an attachment was downloaded for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttachmentOwner {
+    User { other: String, message_id: u64 },
+    Group { group_id: u64, message_id: u64 },
+}
+
+impl AttachmentOwner {
+    /// Rows keep both columns, the unused one is empty or zero.
+    fn columns(&self) -> (&str, u64, u64) {
+        match self {
+            AttachmentOwner::User { other, message_id } => (other, 0, *message_id),
+            AttachmentOwner::Group {
+                group_id,
+                message_id,
+            } => ("", *group_id, *message_id),
+        }
+    }
+
+    /// File name of the `index`th attachment of the message.
+    pub fn file_name(&self, index: usize) -> String {
+        match self {
+            AttachmentOwner::User { other, message_id } => {
+                format!("{}u-{}-{}-{}", CACHE_FILE_PREFIX, other, message_id, index)
+            }
+            AttachmentOwner::Group {
+                group_id,
+                message_id,
+            } => format!(
+                "{}g-{}-{}-{}",
+                CACHE_FILE_PREFIX, group_id, message_id, index
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedAttachment {
+    pub file_name: String,
+    pub owner: AttachmentOwner,
+    pub size: u64,
+    /// milliseconds since epoch
+    pub last_access: u64,
+}
+
+impl<'r> FromRow<'r, sqlx::sqlite::SqliteRow> for CachedAttachment {
+    fn from_row(row: &'r sqlx::sqlite::SqliteRow) -> Result<Self, sqlx::Error> {
+        let other: String = row.try_get("other")?;
+        let group_id: i64 = row.try_get("group_id")?;
+        let message_id: i64 = row.try_get("message_id")?;
+        let size: i64 = row.try_get("size")?;
+        let last_access: i64 = row.try_get("last_access")?;
+
+        let owner = if other.is_empty() {
+            AttachmentOwner::Group {
+                group_id: group_id as u64,
+                message_id: message_id as u64,
+            }
+        } else {
+            AttachmentOwner::User {
+                other,
+                message_id: message_id as u64,
+            }
+        };
+
+        Ok(Self {
+            file_name: row.try_get("file_name")?,
+            owner,
+            size: size as u64,
+            last_access: last_access as u64,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AttachmentCacheUsage {
+    pub files: u64,
+    pub bytes: u64,
+}
+
+/// Bookkeeping of the decrypted attachments in the file server directory.
+#[derive(Clone)]
+pub struct AttachmentCacheStore {
+    pool: SqlitePool,
+}
+
+impl AttachmentCacheStore {
+    pub async fn new(pool: SqlitePool) -> anyhow::Result<Self> {
+        migrate(&pool, ATTACHMENT_CACHE_DB_MIGRATIONS).await?;
+
+        Ok(Self { pool })
+    }
+
+    pub async fn close(&self) {
+        self.pool.close().await;
+    }
+
+    pub async fn get(&self, file_name: &str) -> anyhow::Result<Option<CachedAttachment>> {
+        let attachment = sqlx::query_as(
+            "SELECT file_name, other, group_id, message_id, size, last_access FROM attachment_cache WHERE file_name = ?",
+        )
+        .bind(file_name)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(attachment)
+    }
+
+    pub async fn record(
+        &self,
+        file_name: &str,
+        owner: &AttachmentOwner,
+        size: u64,
+        now: u64,
+    ) -> anyhow::Result<()> {
+        let (other, group_id, message_id) = owner.columns();
+        log::info!("store insert: attachment cache file_name={}", file_name);
+        sqlx::query(
+            "INSERT INTO attachment_cache (file_name, other, group_id, message_id, size, last_access) VALUES (?, ?, ?, ?, ?, ?)
+            ON CONFLICT (file_name) DO UPDATE SET other = excluded.other, group_id = excluded.group_id, message_id = excluded.message_id, size = excluded.size, last_access = excluded.last_access",
+        )
+        .bind(file_name)
+        .bind(other)
+        .bind(group_id as i64)
+        .bind(message_id as i64)
+        .bind(size as i64)
+        .bind(now as i64)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn touch(&self, file_name: &str, now: u64) -> anyhow::Result<bool> {
+        let result = sqlx::query("UPDATE attachment_cache SET last_access = ? WHERE file_name = ?")
+            .bind(now as i64)
+            .bind(file_name)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn usage(&self) -> anyhow::Result<AttachmentCacheUsage> {
+        let (files, bytes): (i64, i64) =
+            sqlx::query_as("SELECT COUNT(*), COALESCE(SUM(size), 0) FROM attachment_cache")
+                .fetch_one(&self.pool)
+                .await?;
+
+        Ok(AttachmentCacheUsage {
+            files: files as u64,
+            bytes: bytes as u64,
+        })
+    }
+
+    /// Every entry, least recently used first.
+    pub async fn get_all_by_last_access(&self) -> anyhow::Result<Vec<CachedAttachment>> {
+        let attachments = sqlx::query_as(
+            "SELECT file_name, other, group_id, message_id, size, last_access FROM attachment_cache ORDER BY last_access, file_name",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(attachments)
+    }
+
+    pub async fn get_of_user_messages(
+        &self,
+        other: &str,
+        message_ids: &[u64],
+    ) -> anyhow::Result<Vec<String>> {
+        if message_ids.is_empty() || other.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let q = format!(
+            "SELECT file_name FROM attachment_cache WHERE other = ? AND message_id IN ({})",
+            placeholders(message_ids.len())
+        );
+        let mut query = sqlx::query_scalar(&q).bind(other);
+        for message_id in message_ids {
+            query = query.bind(*message_id as i64);
+        }
+
+        Ok(query.fetch_all(&self.pool).await?)
+    }
+
+    pub async fn get_of_group_messages(
+        &self,
+        group_id: u64,
+        message_ids: &[u64],
+    ) -> anyhow::Result<Vec<String>> {
+        if message_ids.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let q = format!(
+            "SELECT file_name FROM attachment_cache WHERE other = '' AND group_id = ? AND message_id IN ({})",
+            placeholders(message_ids.len())
+        );
+        let mut query = sqlx::query_scalar(&q).bind(group_id as i64);
+        for message_id in message_ids {
+            query = query.bind(*message_id as i64);
+        }
+
+        Ok(query.fetch_all(&self.pool).await?)
+    }
+
+    pub async fn get_of_group(&self, group_id: u64) -> anyhow::Result<Vec<String>> {
+        let file_names = sqlx::query_scalar(
+            "SELECT file_name FROM attachment_cache WHERE other = '' AND group_id = ?",
+        )
+        .bind(group_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(file_names)
+    }
+
+    pub async fn remove(&self, file_names: &[String]) -> anyhow::Result<()> {
+        if file_names.is_empty() {
+            return Ok(());
+        }
+
+        log::info!(
+            "store delete: {} attachment cache entries",
+            file_names.len()
+        );
+        let q = format!(
+            "DELETE FROM attachment_cache WHERE file_name IN ({})",
+            placeholders(file_names.len())
+        );
+        let mut query = sqlx::query(&q);
+        for file_name in file_names {
+            query = query.bind(file_name);
+        }
+        query.execute(&self.pool).await?;
+
+        Ok(())
+    }
+
+    pub async fn get_quota(&self) -> anyhow::Result<Option<u64>> {
+        let quota: Option<i64> =
+            sqlx::query_scalar("SELECT quota FROM attachment_cache_quota WHERE id = 0")
+                .fetch_optional(&self.pool)
+                .await?;
+
+        Ok(quota.map(|quota| quota as u64))
+    }
+
+    pub async fn set_quota(&self, quota: u64) -> anyhow::Result<()> {
+        sqlx::query(
+            "INSERT INTO attachment_cache_quota (id, quota) VALUES (0, ?) ON CONFLICT (id) DO UPDATE SET quota = excluded.quota",
+        )
+        .bind(quota.min(i64::MAX as u64) as i64)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::setup_pool;
+
+    const DB_URI: &str = ":memory:";
+
+    fn user(other: &str, message_id: u64) -> AttachmentOwner {
+        AttachmentOwner::User {
+            other: other.to_string(),
+            message_id,
+        }
+    }
+
+    fn group(group_id: u64, message_id: u64) -> AttachmentOwner {
+        AttachmentOwner::Group {
+            group_id,
+            message_id,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_record_and_usage() {
+        let pool = setup_pool(DB_URI, 1).await.unwrap();
+        let store = AttachmentCacheStore::new(pool).await.unwrap();
+
+        assert_eq!(
+            store.usage().await.unwrap(),
+            AttachmentCacheUsage::default()
+        );
+
+        let alice = user("alice", 10);
+        store
+            .record(&alice.file_name(0), &alice, 100, 1)
+            .await
+            .unwrap();
+        let team = group(7, 20);
+        store
+            .record(&team.file_name(0), &team, 50, 2)
+            .await
+            .unwrap();
+
+        let cached = store.get("cache-u-alice-10-0").await.unwrap().unwrap();
+        assert_eq!(cached.owner, alice);
+        assert_eq!(cached.size, 100);
+        assert_eq!(
+            store.get("cache-g-7-20-0").await.unwrap().unwrap().owner,
+            team
+        );
+
+        assert_eq!(
+            store.usage().await.unwrap(),
+            AttachmentCacheUsage {
+                files: 2,
+                bytes: 150
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_lru_order_follows_access() {
+        let pool = setup_pool(DB_URI, 1).await.unwrap();
+        let store = AttachmentCacheStore::new(pool).await.unwrap();
+
+        for (i, name) in ["a", "b", "c"].iter().enumerate() {
+            store
+                .record(name, &user("alice", i as u64), 1, i as u64)
+                .await
+                .unwrap();
+        }
+        assert!(store.touch("a", 10).await.unwrap());
+        assert!(!store.touch("missing", 10).await.unwrap());
+
+        let order = store
+            .get_all_by_last_access()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|attachment| attachment.file_name)
+            .collect::<Vec<_>>();
+        assert_eq!(order, vec!["b", "c", "a"]);
+    }
+
+    #[tokio::test]
+    async fn test_lookup_by_message_and_remove() {
+        let pool = setup_pool(DB_URI, 1).await.unwrap();
+        let store = AttachmentCacheStore::new(pool).await.unwrap();
+
+        for owner in [user("alice", 1), user("alice", 2), user("bob", 1)] {
+            store
+                .record(&owner.file_name(0), &owner, 1, 0)
+                .await
+                .unwrap();
+        }
+        for owner in [group(1, 1), group(1, 2), group(2, 1)] {
+            store
+                .record(&owner.file_name(0), &owner, 1, 0)
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(
+            store.get_of_user_messages("alice", &[1, 3]).await.unwrap(),
+            vec!["cache-u-alice-1-0"]
+        );
+        assert_eq!(
+            store.get_of_group_messages(1, &[2]).await.unwrap(),
+            vec!["cache-g-1-2-0"]
+        );
+        let mut of_group = store.get_of_group(1).await.unwrap();
+        of_group.sort();
+        assert_eq!(of_group, vec!["cache-g-1-1-0", "cache-g-1-2-0"]);
+
+        store.remove(&of_group).await.unwrap();
+        assert!(store.get_of_group(1).await.unwrap().is_empty());
+        assert_eq!(store.usage().await.unwrap().files, 4);
+    }
+
+    #[tokio::test]
+    async fn test_quota() {
+        let pool = setup_pool(DB_URI, 1).await.unwrap();
+        let store = AttachmentCacheStore::new(pool).await.unwrap();
+
+        assert_eq!(store.get_quota().await.unwrap(), None);
+        store.set_quota(1024).await.unwrap();
+        store.set_quota(2048).await.unwrap();
+        assert_eq!(store.get_quota().await.unwrap(), Some(2048));
+    }
+}
@@ -1,13 +1,13 @@
+use std::sync::Arc;
+
 use anyhow::Context;
 use base64::{Engine, prelude::BASE64_URL_SAFE_NO_PAD};
-use reqwest::header;
 use serde::Deserialize;
 use tokio::sync::RwLock;
 
 use crate::{
-    db::keyvalue::KeyValueStore,
-    error::DumbError,
-    utils::{HTTP_CLIENT, get_current_timestamp_seconds_since_epoch},
+    api::FireflyApi, db::keyvalue::KeyValueStore, error::FireflyError,
+    utils::get_current_timestamp_seconds_since_epoch,
 };
 
 const KEY_ACCESS_TOKEN: &str = "auth0_access_token";
@@ -37,6 +37,7 @@ pub fn get_claims_from_token(s: &str) -> anyhow::Result<TokenClaims> {
 
 pub struct FfiAuthHandler {
     key_value_store: KeyValueStore,
+    api: Arc<dyn FireflyApi>,
 
     fetching_token: RwLock<()>,
 
@@ -45,9 +46,15 @@ pub struct FfiAuthHandler {
 }
 
 impl FfiAuthHandler {
-    pub fn new(key_value_store: KeyValueStore, client_id: String, auth_base_url: String) -> Self {
+    pub fn new(
+        key_value_store: KeyValueStore,
+        api: Arc<dyn FireflyApi>,
+        client_id: String,
+        auth_base_url: String,
+    ) -> Self {
         Self {
             key_value_store,
+            api,
             fetching_token: RwLock::new(()),
             client_id,
             auth_base_url,
@@ -73,33 +80,14 @@ impl FfiAuthHandler {
                 .key_value_store
                 .get(KEY_REFRESH_TOKEN)
                 .await
-                .map_err(|_| anyhow::anyhow!("refresh token not found"))?;
-
-            let body = serde_json::json!({
-                "grant_type": "refresh_token",
-                "client_id": self.client_id,
-                "refresh_token": refresh_token,
-            });
-
-            let response = HTTP_CLIENT
-                .post(format!("{}/oauth/token", self.auth_base_url))
-                .header(header::CONTENT_TYPE, "application/json")
-                .body(serde_json::to_string(&body)?)
-                .send()
-                .await?;
+                .map_err(|_| FireflyError::NotLoggedIn("refresh token not found".to_string()))?;
 
-            if !response.status().is_success() {
-                return Err(anyhow::anyhow!(
-                    "failed to refresh token, unexpected status: [{}] {}",
-                    response.status().as_u16(),
-                    response.text().await?
-                ));
-            }
-            let body = response.text().await?;
-
-            let response = serde_json::from_str::<TokenResponse>(&body)?;
+            let response = self
+                .api
+                .refresh_token(&self.auth_base_url, &self.client_id, &refresh_token)
+                .await?;
 
-            log::info!("Received new access token '{}'", body);
+            log::info!("Received new access token");
             self.key_value_store
                 .set(KEY_ACCESS_TOKEN, &response.access_token)
                 .await?;
@@ -120,18 +108,18 @@ pub struct TokenResponse {
 }
 
 impl FfiAuthHandler {
-    pub async fn set_access_token(&self, access_token: String) -> Result<(), DumbError> {
+    pub async fn set_access_token(&self, access_token: String) -> Result<(), FireflyError> {
         self.key_value_store
             .set(KEY_ACCESS_TOKEN, &access_token)
             .await
-            .map_err(DumbError::from_anyhow)
+            .map_err(FireflyError::from_anyhow)
     }
 
-    pub async fn set_refresh_token(&self, refresh_token: String) -> Result<(), DumbError> {
+    pub async fn set_refresh_token(&self, refresh_token: String) -> Result<(), FireflyError> {
         self.key_value_store
             .set(KEY_REFRESH_TOKEN, &refresh_token)
             .await
-            .map_err(DumbError::from_anyhow)
+            .map_err(FireflyError::from_anyhow)
     }
 
     /// Returns true if a non-expired access token OR a refresh token exists.
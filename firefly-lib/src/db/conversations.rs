@@ -1,15 +1,166 @@
+use std::time::Duration;
 
-use sqlx::SqlitePool;
 use sqlx::prelude::*;
+use sqlx::{SqliteConnection, SqlitePool};
 
-#[derive(Default)]
+use crate::{
+    db::migrations::{FIREFLY_DB_MIGRATIONS, migrate},
+    utils::get_current_timestamp_microseconds_since_epoch,
+};
+
+/// Flags of conversations we start, interpreted by the server.
+pub const DEFAULT_CONVERSATION_FLAGS: u32 = 1;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NotificationLevel {
+    #[default]
+    All,
+    Mentions,
+    None,
+}
+
+impl NotificationLevel {
+    pub fn as_i64(self) -> i64 {
+        match self {
+            NotificationLevel::All => 0,
+            NotificationLevel::Mentions => 1,
+            NotificationLevel::None => 2,
+        }
+    }
+
+    pub fn from_i64(value: i64) -> anyhow::Result<Self> {
+        match value {
+            0 => Ok(NotificationLevel::All),
+            1 => Ok(NotificationLevel::Mentions),
+            2 => Ok(NotificationLevel::None),
+            _ => Err(anyhow::anyhow!("invalid notification level {}", value)),
+        }
+    }
+}
+
+/// Settings of a conversation. `flags` and the disappearing timer are shared
+/// with the other side, direct conversations sync them through
+/// `/user/conversation` as a `u64`, see `synced_bits`. Everything else only
+/// concerns us.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct ConversationSettings {
-    inner: u64,
+    /// interpreted by the server
+    pub flags: u32,
+    /// messages are deleted once they are this old, 0 keeps them
+    pub disappearing_timer_secs: u32,
+    /// milliseconds since the epoch, 0 if not muted, `u64::MAX` for ever
+    pub muted_until: u64,
+    pub archived: bool,
+    pub pinned: bool,
+    pub blocked: bool,
+    pub notification_level: NotificationLevel,
+}
+
+impl Default for ConversationSettings {
+    fn default() -> Self {
+        Self {
+            flags: DEFAULT_CONVERSATION_FLAGS,
+            disappearing_timer_secs: 0,
+            muted_until: 0,
+            archived: false,
+            pinned: false,
+            blocked: false,
+            notification_level: NotificationLevel::default(),
+        }
+    }
 }
 
 impl ConversationSettings {
-    pub fn new(settings: u64) -> Self {
-        Self { inner: settings }
+    /// The shared part as synced with the server, the low 32 bits are `flags`
+    /// and the high 32 bits the disappearing timer.
+    pub fn synced_bits(&self) -> u64 {
+        ((self.disappearing_timer_secs as u64) << 32) | self.flags as u64
+    }
+
+    /// Replaces the shared part, keeping our own settings.
+    pub fn with_synced_bits(self, bits: u64) -> Self {
+        Self {
+            flags: bits as u32,
+            disappearing_timer_secs: (bits >> 32) as u32,
+            ..self
+        }
+    }
+
+    /// Turns on the flags of `bits` like `merge=true` does. The timer of
+    /// `bits` is ignored, changing it takes a replace.
+    pub fn merge_synced_bits(self, bits: u64) -> Self {
+        Self {
+            flags: self.flags | bits as u32,
+            ..self
+        }
+    }
+
+    pub fn disappearing_timer(&self) -> Option<Duration> {
+        (self.disappearing_timer_secs != 0)
+            .then(|| Duration::from_secs(self.disappearing_timer_secs as u64))
+    }
+
+    pub fn is_muted(&self, now_millis: u64) -> bool {
+        self.muted_until > now_millis
+    }
+
+    /// Whether a new message shows a notification. Messages carry no mentions,
+    /// a direct message is addressed to us anyway while `Mentions` silences
+    /// the messages of a group.
+    pub fn notifies(&self, now_millis: u64, direct: bool) -> bool {
+        if self.is_muted(now_millis) {
+            return false;
+        }
+
+        match self.notification_level {
+            NotificationLevel::All => true,
+            NotificationLevel::Mentions => direct,
+            NotificationLevel::None => false,
+        }
+    }
+}
+
+/// A disappearing message timer in effect. Only messages sent after the timer
+/// was set disappear, turning it on doesn't wipe the existing history.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisappearingTimer {
+    pub timer: Duration,
+    /// microseconds since the epoch, like message ids
+    pub since: u64,
+}
+
+impl DisappearingTimer {
+    /// Messages with ids in `since..expired_before(now)` are expired.
+    pub fn expired_before(&self, now: u64) -> u64 {
+        now.saturating_sub(self.timer.as_micros() as u64)
+    }
+}
+
+/// Whose settings, direct conversations and groups are kept in separate tables.
+#[derive(Debug, Clone, Copy)]
+enum SettingsOwner<'a> {
+    User(&'a str),
+    Group(u64),
+}
+
+impl SettingsOwner<'_> {
+    /// table and key column, trusted constants that are safe to format into
+    /// queries
+    fn table(&self) -> (&'static str, &'static str) {
+        match self {
+            SettingsOwner::User(_) => ("conversations", "username"),
+            SettingsOwner::Group(_) => ("group_settings", "group_id"),
+        }
+    }
+
+    fn bind<'q>(
+        &self,
+        query: sqlx::query::Query<'q, sqlx::Sqlite, sqlx::sqlite::SqliteArguments<'q>>,
+    ) -> sqlx::query::Query<'q, sqlx::Sqlite, sqlx::sqlite::SqliteArguments<'q>> {
+        match *self {
+            SettingsOwner::User(username) => query.bind(username.to_string()),
+            SettingsOwner::Group(group_id) => query.bind(group_id as i64),
+        }
     }
 }
 
@@ -20,17 +171,7 @@ pub struct ConversationStore {
 
 impl ConversationStore {
     pub async fn new(pool: SqlitePool) -> anyhow::Result<Self> {
-        let mut db = pool.acquire().await?;
-
-        db.execute(
-            r#"
-        CREATE TABLE IF NOT EXISTS conversations (
-            username TEXT NOT NULL PRIMARY KEY,
-            settings INTEGER NOT NULL
-        )
-        "#,
-        )
-        .await?;
+        migrate(&pool, FIREFLY_DB_MIGRATIONS).await?;
 
         Ok(Self { pool })
     }
@@ -39,41 +180,577 @@ impl ConversationStore {
         &self,
         username: &str,
     ) -> anyhow::Result<Option<ConversationSettings>> {
-        let row = sqlx::query(
-            r#"
-            SELECT settings
-            FROM conversations
-            WHERE username = ?
-            "#,
+        let mut conn = self.pool.acquire().await?;
+        select_settings_in(&mut conn, SettingsOwner::User(username)).await
+    }
+
+    /// Stores all settings of the conversation with `username`, the
+    /// disappearing timer counts from now if it changed.
+    pub async fn set_conversation(
+        &self,
+        username: &str,
+        settings: ConversationSettings,
+    ) -> anyhow::Result<()> {
+        let mut conn = self.pool.acquire().await?;
+        upsert_settings_in(
+            &mut conn,
+            SettingsOwner::User(username),
+            &settings,
+            get_current_timestamp_microseconds_since_epoch(),
+        )
+        .await
+    }
+
+    /// Applies the shared part of the settings as the server stored it,
+    /// merged like `merge=true` does or replaced. Our own settings are kept.
+    pub async fn set_synced_settings(
+        &self,
+        username: &str,
+        bits: u64,
+        merge: bool,
+    ) -> anyhow::Result<ConversationSettings> {
+        let owner = SettingsOwner::User(username);
+        let mut tx = self.pool.begin().await?;
+
+        let settings = match select_settings_in(&mut tx, owner).await? {
+            Some(current) if merge => current.merge_synced_bits(bits),
+            current => current.unwrap_or_default().with_synced_bits(bits),
+        };
+        upsert_settings_in(
+            &mut tx,
+            owner,
+            &settings,
+            get_current_timestamp_microseconds_since_epoch(),
         )
-        .bind(username)
-        .fetch_optional(&self.pool)
         .await?;
 
-        if let Some(row) = row {
-            let settings = row.try_get(0)?;
-            return Ok(Some(ConversationSettings::new(settings)));
-        } else {
-            return Ok(None);
-        }
+        tx.commit().await?;
+
+        Ok(settings)
     }
 
-    pub async fn set_conversation(
+    /// Applies a disappearing timer either side set at `changed_at`,
+    /// microseconds since the epoch like message ids. The timer counts from
+    /// that change rather than from when it got here, a change older than the
+    /// timer in effect is ignored.
+    pub async fn apply_disappearing_timer(
         &self,
         username: &str,
+        timer_secs: u32,
+        changed_at: u64,
+    ) -> anyhow::Result<()> {
+        let owner = SettingsOwner::User(username);
+        let mut tx = self.pool.begin().await?;
+
+        let current = select_settings_in(&mut tx, owner)
+            .await?
+            .unwrap_or_default();
+        let since: Option<i64> =
+            sqlx::query_scalar("SELECT disappearing_since FROM conversations WHERE username = ?")
+                .bind(username)
+                .fetch_optional(&mut *tx)
+                .await?;
+        let since = since.unwrap_or_default() as u64;
+
+        if current.disappearing_timer_secs == timer_secs {
+            // already in effect, like from settings fetched on connect, which
+            // are dated by when they got here
+            if changed_at < since {
+                sqlx::query("UPDATE conversations SET disappearing_since = ? WHERE username = ?")
+                    .bind(changed_at as i64)
+                    .bind(username)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+        } else if changed_at > since {
+            let settings = ConversationSettings {
+                disappearing_timer_secs: timer_secs,
+                ..current
+            };
+            upsert_settings_in(&mut tx, owner, &settings, changed_at).await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    pub async fn is_blocked(&self, username: &str) -> anyhow::Result<bool> {
+        Ok(self
+            .get_conversation(username)
+            .await?
+            .is_some_and(|settings| settings.blocked))
+    }
+
+    /// Updates only the blocked setting of `username`, the block list is
+    /// backed by the conversation settings.
+    pub async fn set_blocked(&self, username: &str, blocked: bool) -> anyhow::Result<()> {
+        let owner = SettingsOwner::User(username);
+        let mut tx = self.pool.begin().await?;
+
+        let current = select_settings_in(&mut tx, owner)
+            .await?
+            .unwrap_or_default();
+        upsert_settings_in(
+            &mut tx,
+            owner,
+            &ConversationSettings { blocked, ..current },
+            get_current_timestamp_microseconds_since_epoch(),
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    pub async fn get_blocked(&self) -> anyhow::Result<Vec<String>> {
+        let usernames = sqlx::query_scalar(
+            "SELECT username FROM conversations WHERE blocked = 1 ORDER BY username",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(usernames)
+    }
+
+    /// Direct conversations with a disappearing message timer.
+    pub async fn get_disappearing_conversations(
+        &self,
+    ) -> anyhow::Result<Vec<(String, DisappearingTimer)>> {
+        let rows = sqlx::query(
+            "SELECT username, settings, disappearing_since FROM conversations WHERE (settings >> 32) != 0",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter()
+            .map(|row| {
+                let settings: i64 = row.try_get(1)?;
+                Ok((
+                    row.try_get(0)?,
+                    disappearing_timer_of(settings, row.try_get(2)?),
+                ))
+            })
+            .collect()
+    }
+
+    /// Group settings are local to this device, groups don't sync them.
+    pub async fn get_group_settings(
+        &self,
+        group_id: u64,
+    ) -> anyhow::Result<Option<ConversationSettings>> {
+        let mut conn = self.pool.acquire().await?;
+        select_settings_in(&mut conn, SettingsOwner::Group(group_id)).await
+    }
+
+    /// Group counterpart of `set_conversation`.
+    pub async fn set_group_settings(
+        &self,
+        group_id: u64,
         settings: ConversationSettings,
     ) -> anyhow::Result<()> {
-        sqlx::query(
-            r#"
-            INSERT OR REPLACE INTO conversations (username, settings)
-            VALUES (?, ?)
-            "#,
+        let mut conn = self.pool.acquire().await?;
+        upsert_settings_in(
+            &mut conn,
+            SettingsOwner::Group(group_id),
+            &settings,
+            get_current_timestamp_microseconds_since_epoch(),
         )
-        .bind(username)
-        .bind(settings.inner as u32)
-        .execute(&self.pool)
+        .await
+    }
+
+    /// Groups with a disappearing message timer.
+    pub async fn get_disappearing_groups(&self) -> anyhow::Result<Vec<(u64, DisappearingTimer)>> {
+        let rows = sqlx::query(
+            "SELECT group_id, settings, disappearing_since FROM group_settings WHERE (settings >> 32) != 0",
+        )
+        .fetch_all(&self.pool)
         .await?;
 
+        rows.into_iter()
+            .map(|row| {
+                let group_id: i64 = row.try_get(0)?;
+                let settings: i64 = row.try_get(1)?;
+                Ok((
+                    group_id as u64,
+                    disappearing_timer_of(settings, row.try_get(2)?),
+                ))
+            })
+            .collect()
+    }
+
+    pub async fn delete_group_settings(&self, group_id: u64) -> anyhow::Result<()> {
+        sqlx::query("DELETE FROM group_settings WHERE group_id = ?")
+            .bind(group_id as i64)
+            .execute(&self.pool)
+            .await?;
+
         Ok(())
     }
 }
+
+async fn select_settings_in(
+    conn: &mut SqliteConnection,
+    owner: SettingsOwner<'_>,
+) -> anyhow::Result<Option<ConversationSettings>> {
+    let (table, key_column) = owner.table();
+    let query = format!(
+        "SELECT settings, muted_until, archived, pinned, blocked, notification_level FROM {} WHERE {} = ?",
+        table, key_column
+    );
+
+    let Some(row) = owner
+        .bind(sqlx::query(&query))
+        .fetch_optional(&mut *conn)
+        .await?
+    else {
+        return Ok(None);
+    };
+
+    let settings: i64 = row.try_get(0)?;
+    let muted_until: i64 = row.try_get(1)?;
+    let settings = ConversationSettings {
+        muted_until: muted_until as u64,
+        archived: row.try_get(2)?,
+        pinned: row.try_get(3)?,
+        blocked: row.try_get(4)?,
+        notification_level: NotificationLevel::from_i64(row.try_get(5)?)?,
+        ..Default::default()
+    }
+    .with_synced_bits(settings as u64);
+
+    Ok(Some(settings))
+}
+
+/// Stores `settings` losslessly, the `u64` parts are kept as their `i64` bit
+/// patterns. `disappearing_since` moves to `changed_at` whenever the timer
+/// changes.
+async fn upsert_settings_in(
+    conn: &mut SqliteConnection,
+    owner: SettingsOwner<'_>,
+    settings: &ConversationSettings,
+    changed_at: u64,
+) -> anyhow::Result<()> {
+    let (table, key_column) = owner.table();
+    let query = format!(
+        r#"
+        INSERT INTO {table} ({key_column}, settings, disappearing_since, muted_until, archived, pinned, blocked, notification_level)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+        ON CONFLICT ({key_column}) DO UPDATE SET
+            disappearing_since = CASE
+                WHEN ({table}.settings >> 32) = (excluded.settings >> 32)
+                THEN {table}.disappearing_since
+                ELSE excluded.disappearing_since
+            END,
+            settings = excluded.settings,
+            muted_until = excluded.muted_until,
+            archived = excluded.archived,
+            pinned = excluded.pinned,
+            blocked = excluded.blocked,
+            notification_level = excluded.notification_level
+        "#
+    );
+
+    owner
+        .bind(sqlx::query(&query))
+        .bind(settings.synced_bits() as i64)
+        .bind(changed_at as i64)
+        .bind(settings.muted_until as i64)
+        .bind(settings.archived)
+        .bind(settings.pinned)
+        .bind(settings.blocked)
+        .bind(settings.notification_level.as_i64())
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(())
+}
+
+fn disappearing_timer_of(settings: i64, since: i64) -> DisappearingTimer {
+    let settings = ConversationSettings::default().with_synced_bits(settings as u64);
+    DisappearingTimer {
+        timer: settings.disappearing_timer().unwrap_or_default(),
+        since: since as u64,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::setup_pool;
+
+    use super::*;
+
+    #[test]
+    fn test_synced_bits() {
+        let settings = ConversationSettings {
+            flags: 1,
+            disappearing_timer_secs: 60,
+            pinned: true,
+            ..Default::default()
+        };
+        assert_eq!(settings.synced_bits(), (60 << 32) | 1);
+        assert_eq!(
+            ConversationSettings::default().with_synced_bits(settings.synced_bits()),
+            ConversationSettings {
+                pinned: false,
+                ..settings
+            }
+        );
+        assert_eq!(settings.disappearing_timer(), Some(Duration::from_secs(60)));
+
+        let max = ConversationSettings {
+            flags: u32::MAX,
+            disappearing_timer_secs: u32::MAX,
+            ..Default::default()
+        };
+        assert_eq!(
+            ConversationSettings::default().with_synced_bits(max.synced_bits()),
+            max
+        );
+
+        // merging only turns flags on, the timer stays
+        let merged = settings.merge_synced_bits((30 << 32) | 2);
+        assert_eq!(merged.flags, 3);
+        assert_eq!(merged.disappearing_timer_secs, 60);
+        assert!(merged.pinned);
+    }
+
+    #[test]
+    fn test_notifies() {
+        let settings = ConversationSettings::default();
+        assert!(settings.notifies(1000, true));
+        assert!(settings.notifies(1000, false));
+
+        let muted = ConversationSettings {
+            muted_until: 2000,
+            ..settings
+        };
+        assert!(!muted.notifies(1000, true));
+        assert!(muted.notifies(2000, true));
+
+        let mentions = ConversationSettings {
+            notification_level: NotificationLevel::Mentions,
+            ..settings
+        };
+        assert!(mentions.notifies(1000, true));
+        assert!(!mentions.notifies(1000, false));
+
+        let none = ConversationSettings {
+            notification_level: NotificationLevel::None,
+            ..settings
+        };
+        assert!(!none.notifies(1000, true));
+    }
+
+    #[tokio::test]
+    async fn test_settings_round_trip() {
+        let pool = setup_pool(":memory:", 1).await.unwrap();
+        let store = ConversationStore::new(pool).await.unwrap();
+
+        assert_eq!(store.get_conversation("alice").await.unwrap(), None);
+
+        let settings = ConversationSettings {
+            flags: u32::MAX,
+            disappearing_timer_secs: u32::MAX,
+            muted_until: u64::MAX,
+            archived: true,
+            pinned: true,
+            blocked: true,
+            notification_level: NotificationLevel::Mentions,
+        };
+        store.set_conversation("alice", settings).await.unwrap();
+        assert_eq!(
+            store.get_conversation("alice").await.unwrap(),
+            Some(settings)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_synced_settings_keep_own_settings() {
+        let pool = setup_pool(":memory:", 1).await.unwrap();
+        let store = ConversationStore::new(pool).await.unwrap();
+
+        // nothing stored yet, merging takes the bits as they are
+        let settings = store
+            .set_synced_settings("alice", 1 << 1, true)
+            .await
+            .unwrap();
+        assert_eq!(settings.flags, 2);
+
+        let own = ConversationSettings {
+            archived: true,
+            notification_level: NotificationLevel::None,
+            ..settings
+        };
+        store.set_conversation("alice", own).await.unwrap();
+
+        let merged = store.set_synced_settings("alice", 1, true).await.unwrap();
+        assert_eq!(merged.flags, 3);
+        assert!(merged.archived);
+
+        let replaced = store
+            .set_synced_settings("alice", 30 << 32, false)
+            .await
+            .unwrap();
+        assert_eq!(replaced.flags, 0);
+        assert_eq!(replaced.disappearing_timer_secs, 30);
+        assert_eq!(replaced.notification_level, NotificationLevel::None);
+        assert_eq!(
+            store.get_conversation("alice").await.unwrap(),
+            Some(replaced)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_block_list() {
+        let pool = setup_pool(":memory:", 1).await.unwrap();
+        let store = ConversationStore::new(pool).await.unwrap();
+
+        assert!(!store.is_blocked("alice").await.unwrap());
+
+        let pinned = ConversationSettings {
+            pinned: true,
+            ..Default::default()
+        };
+        store.set_conversation("bob", pinned).await.unwrap();
+
+        store.set_blocked("bob", true).await.unwrap();
+        store.set_blocked("alice", true).await.unwrap();
+        assert!(store.is_blocked("alice").await.unwrap());
+        assert_eq!(store.get_blocked().await.unwrap(), vec!["alice", "bob"]);
+
+        // the other settings stay
+        assert!(store.get_conversation("bob").await.unwrap().unwrap().pinned);
+
+        store.set_blocked("alice", false).await.unwrap();
+        assert_eq!(store.get_blocked().await.unwrap(), vec!["bob"]);
+    }
+
+    #[tokio::test]
+    async fn test_disappearing_since_resets_only_when_timer_changes() {
+        let pool = setup_pool(":memory:", 1).await.unwrap();
+        let store = ConversationStore::new(pool).await.unwrap();
+
+        store
+            .set_conversation("alice", ConversationSettings::default())
+            .await
+            .unwrap();
+        assert!(
+            store
+                .get_disappearing_conversations()
+                .await
+                .unwrap()
+                .is_empty()
+        );
+
+        let settings = ConversationSettings {
+            disappearing_timer_secs: u32::MAX,
+            ..Default::default()
+        };
+        store.set_conversation("alice", settings).await.unwrap();
+
+        let timers = store.get_disappearing_conversations().await.unwrap();
+        assert_eq!(timers.len(), 1);
+        let (username, timer) = &timers[0];
+        assert_eq!(username, "alice");
+        assert_eq!(timer.timer, Duration::from_secs(u32::MAX as u64));
+
+        // other settings changed, the timer didn't
+        store
+            .set_conversation(
+                "alice",
+                ConversationSettings {
+                    flags: 3,
+                    pinned: true,
+                    ..settings
+                },
+            )
+            .await
+            .unwrap();
+        let again = store.get_disappearing_conversations().await.unwrap();
+        assert_eq!(again[0].1.since, timer.since);
+    }
+
+    #[tokio::test]
+    async fn test_disappearing_timer_dated_from_change() {
+        let pool = setup_pool(":memory:", 1).await.unwrap();
+        let store = ConversationStore::new(pool).await.unwrap();
+
+        store
+            .apply_disappearing_timer("alice", 60, 1000)
+            .await
+            .unwrap();
+        let timers = store.get_disappearing_conversations().await.unwrap();
+        assert_eq!(timers[0].1.timer, Duration::from_secs(60));
+        assert_eq!(timers[0].1.since, 1000);
+
+        // an older change to another timer is stale
+        store
+            .apply_disappearing_timer("alice", 30, 500)
+            .await
+            .unwrap();
+        let timers = store.get_disappearing_conversations().await.unwrap();
+        assert_eq!(timers[0].1.timer, Duration::from_secs(60));
+
+        // settings fetched on connect date the timer when they got here, the
+        // change itself moves it back
+        store
+            .apply_disappearing_timer("alice", 30, 2000)
+            .await
+            .unwrap();
+        let settings = store.get_conversation("alice").await.unwrap().unwrap();
+        store.set_conversation("bob", settings).await.unwrap();
+        store
+            .apply_disappearing_timer("bob", 30, 2000)
+            .await
+            .unwrap();
+
+        let timers = store.get_disappearing_conversations().await.unwrap();
+        assert_eq!(timers.len(), 2);
+        assert!(timers.iter().all(|(_, timer)| timer.since == 2000));
+
+        // turning it off
+        store
+            .apply_disappearing_timer("alice", 0, 3000)
+            .await
+            .unwrap();
+        let timers = store.get_disappearing_conversations().await.unwrap();
+        assert_eq!(timers.len(), 1);
+        assert_eq!(timers[0].0, "bob");
+    }
+
+    #[tokio::test]
+    async fn test_group_settings() {
+        let pool = setup_pool(":memory:", 1).await.unwrap();
+        let store = ConversationStore::new(pool).await.unwrap();
+
+        assert_eq!(store.get_group_settings(1).await.unwrap(), None);
+
+        let settings = ConversationSettings {
+            disappearing_timer_secs: 30,
+            muted_until: 1000,
+            ..Default::default()
+        };
+        store.set_group_settings(1, settings).await.unwrap();
+        assert_eq!(store.get_group_settings(1).await.unwrap(), Some(settings));
+
+        let groups = store.get_disappearing_groups().await.unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].0, 1);
+        assert_eq!(groups[0].1.timer, Duration::from_secs(30));
+
+        store.delete_group_settings(1).await.unwrap();
+        assert!(store.get_disappearing_groups().await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_expired_before() {
+        let timer = DisappearingTimer {
+            timer: Duration::from_secs(1),
+            since: 0,
+        };
+        assert_eq!(timer.expired_before(3_000_000), 2_000_000);
+        assert_eq!(timer.expired_before(10), 0);
+    }
+}
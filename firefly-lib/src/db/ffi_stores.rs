@@ -2,11 +2,7 @@ use libsignal_protocol::ProtocolAddress;
 use sqlx::SqlitePool;
 use std::sync::{Arc, Mutex};
 
-use crate::{
-    EncryptedMessage, FfiPreKeyBundle,
-    db::stores::KeyStores,
-    error::DumbError,
-};
+use crate::{EncryptedMessage, FfiPreKeyBundle, db::stores::KeyStores, error::FireflyError};
 
 pub enum Command {
     Exit,
@@ -14,20 +10,27 @@ pub enum Command {
         other: ProtocolAddress,
         cipher_text: Vec<u8>,
         ty: u8,
-        reply: tokio::sync::oneshot::Sender<Result<Vec<u8>, DumbError>>,
+        reply: tokio::sync::oneshot::Sender<Result<Vec<u8>, FireflyError>>,
+    },
+    DecryptToInbox {
+        id: u64,
+        other: ProtocolAddress,
+        cipher_text: Vec<u8>,
+        ty: u8,
+        reply: tokio::sync::oneshot::Sender<Result<Vec<u8>, FireflyError>>,
     },
     Encrypt {
         other: ProtocolAddress,
         plain_text: Vec<u8>,
-        reply: tokio::sync::oneshot::Sender<Result<EncryptedMessage, DumbError>>,
+        reply: tokio::sync::oneshot::Sender<Result<EncryptedMessage, FireflyError>>,
     },
     ProcessPreKeyBundle {
         other: String,
         pre_key_bundle: FfiPreKeyBundle,
-        reply: tokio::sync::oneshot::Sender<Result<(), DumbError>>,
+        reply: tokio::sync::oneshot::Sender<Result<(), FireflyError>>,
     },
     GeneratePreKeyBundle {
-        reply: tokio::sync::oneshot::Sender<Result<FfiPreKeyBundle, DumbError>>,
+        reply: tokio::sync::oneshot::Sender<Result<FfiPreKeyBundle, FireflyError>>,
     },
 }
 
@@ -52,7 +55,10 @@ impl FfiKeyStores {
     // All sub-stores hold SqlitePool which is Arc-backed and cheap to clone.
     // State is always consistent because all writes go through the same SqlitePool.
     pub fn store(&self) -> KeyStores {
-        self.stores.lock().expect("KeyStores mutex poisoned").clone()
+        self.stores
+            .lock()
+            .expect("KeyStores mutex poisoned")
+            .clone()
     }
 }
 
@@ -68,37 +74,70 @@ impl FfiKeyStores {
             rt.block_on(async move {
                 while let Ok(cmd) = receiver.recv() {
                     match cmd {
-                        Command::Decrypt { other, cipher_text, ty, reply } => {
+                        Command::Decrypt {
+                            other,
+                            cipher_text,
+                            ty,
+                            reply,
+                        } => {
                             let result = stores_clone
                                 .lock()
                                 .expect("poisoned")
                                 .decrypt(other, cipher_text, ty)
                                 .await
-                                .map_err(DumbError::from_anyhow);
+                                .map_err(FireflyError::from_anyhow);
                             if let Err(err) = reply.send(result) {
                                 log::error!("Error sending decrypt reply: {:?}", err);
                             }
                         }
-                        Command::Encrypt { other, plain_text, reply } => {
+                        Command::DecryptToInbox {
+                            id,
+                            other,
+                            cipher_text,
+                            ty,
+                            reply,
+                        } => {
+                            let result = stores_clone
+                                .lock()
+                                .expect("poisoned")
+                                .decrypt_to_inbox(id, other, cipher_text, ty)
+                                .await
+                                .map_err(FireflyError::from_anyhow);
+                            if let Err(err) = reply.send(result) {
+                                log::error!("Error sending decrypt_to_inbox reply: {:?}", err);
+                            }
+                        }
+                        Command::Encrypt {
+                            other,
+                            plain_text,
+                            reply,
+                        } => {
                             let result = stores_clone
                                 .lock()
                                 .expect("poisoned")
                                 .encrypt(other, plain_text)
                                 .await
-                                .map_err(DumbError::from_anyhow);
+                                .map_err(FireflyError::from_anyhow);
                             if let Err(err) = reply.send(result) {
                                 log::error!("Error sending encrypt reply: {:?}", err);
                             }
                         }
-                        Command::ProcessPreKeyBundle { other, pre_key_bundle, reply } => {
+                        Command::ProcessPreKeyBundle {
+                            other,
+                            pre_key_bundle,
+                            reply,
+                        } => {
                             let result = stores_clone
                                 .lock()
                                 .expect("poisoned")
                                 .process_pre_key_bundle(other, pre_key_bundle)
                                 .await
-                                .map_err(DumbError::from_anyhow);
+                                .map_err(FireflyError::from_anyhow);
                             if let Err(err) = reply.send(result) {
-                                log::error!("Error sending process_pre_key_bundle reply: {:?}", err);
+                                log::error!(
+                                    "Error sending process_pre_key_bundle reply: {:?}",
+                                    err
+                                );
                             }
                         }
                         Command::GeneratePreKeyBundle { reply } => {
@@ -107,7 +146,7 @@ impl FfiKeyStores {
                                 .expect("poisoned")
                                 .generate_prekey_bundle()
                                 .await
-                                .map_err(DumbError::from_anyhow);
+                                .map_err(FireflyError::from_anyhow);
                             if let Err(_) = reply.send(result) {
                                 log::error!("Error sending generate_prekey_bundle reply");
                             }
@@ -118,7 +157,11 @@ impl FfiKeyStores {
             });
         });
 
-        Ok(Self { sender, handler, stores })
+        Ok(Self {
+            sender,
+            handler,
+            stores,
+        })
     }
 }
 
@@ -128,9 +171,32 @@ impl FfiKeyStores {
         other: ProtocolAddress,
         cipher_text: Vec<u8>,
         ty: u8,
-    ) -> Result<Vec<u8>, DumbError> {
+    ) -> Result<Vec<u8>, FireflyError> {
         let (reply, receiver) = tokio::sync::oneshot::channel();
-        self.sender.send(Command::Decrypt { other, cipher_text, ty, reply })?;
+        self.sender.send(Command::Decrypt {
+            other,
+            cipher_text,
+            ty,
+            reply,
+        })?;
+        receiver.await?
+    }
+
+    pub async fn decrypt_to_inbox(
+        &self,
+        id: u64,
+        other: ProtocolAddress,
+        cipher_text: Vec<u8>,
+        ty: u8,
+    ) -> Result<Vec<u8>, FireflyError> {
+        let (reply, receiver) = tokio::sync::oneshot::channel();
+        self.sender.send(Command::DecryptToInbox {
+            id,
+            other,
+            cipher_text,
+            ty,
+            reply,
+        })?;
         receiver.await?
     }
 
@@ -138,9 +204,13 @@ impl FfiKeyStores {
         &self,
         other: ProtocolAddress,
         plain_text: Vec<u8>,
-    ) -> Result<EncryptedMessage, DumbError> {
+    ) -> Result<EncryptedMessage, FireflyError> {
         let (reply, receiver) = tokio::sync::oneshot::channel();
-        self.sender.send(Command::Encrypt { other, plain_text, reply })?;
+        self.sender.send(Command::Encrypt {
+            other,
+            plain_text,
+            reply,
+        })?;
         receiver.await?
     }
 
@@ -148,13 +218,17 @@ impl FfiKeyStores {
         &self,
         other: String,
         pre_key_bundle: FfiPreKeyBundle,
-    ) -> Result<(), DumbError> {
+    ) -> Result<(), FireflyError> {
         let (reply, receiver) = tokio::sync::oneshot::channel();
-        self.sender.send(Command::ProcessPreKeyBundle { other, pre_key_bundle, reply })?;
+        self.sender.send(Command::ProcessPreKeyBundle {
+            other,
+            pre_key_bundle,
+            reply,
+        })?;
         receiver.await?
     }
 
-    pub async fn generate_prekey_bundle(&self) -> Result<FfiPreKeyBundle, DumbError> {
+    pub async fn generate_prekey_bundle(&self) -> Result<FfiPreKeyBundle, FireflyError> {
         let (reply, receiver) = tokio::sync::oneshot::channel();
         self.sender.send(Command::GeneratePreKeyBundle { reply })?;
         receiver.await?
@@ -163,8 +237,8 @@ impl FfiKeyStores {
 
 #[cfg(test)]
 mod tests {
-    use crate::db::setup_pool;
     use super::*;
+    use crate::db::setup_pool;
     use libsignal_protocol::{DeviceId, ProtocolAddress};
 
     const DB_URI: &str = ":memory:";
@@ -175,25 +249,57 @@ mod tests {
         user2: &FfiKeyStores,
         user2_name: &str,
         user2_pre_key_bundle: FfiPreKeyBundle,
-    ) -> Result<(), DumbError> {
-        let bob_device_id = user2.store().identity_store.get_full_identity_key_pair().await.unwrap().device_id;
-        let alice_device_id = user1.store().identity_store.get_full_identity_key_pair().await.unwrap().device_id;
+    ) -> Result<(), FireflyError> {
+        let bob_device_id = user2
+            .store()
+            .identity_store
+            .get_full_identity_key_pair()
+            .await
+            .unwrap()
+            .device_id;
+        let alice_device_id = user1
+            .store()
+            .identity_store
+            .get_full_identity_key_pair()
+            .await
+            .unwrap()
+            .device_id;
 
-        let bob_address = ProtocolAddress::new(user2_name.to_string(), DeviceId::new(bob_device_id).unwrap());
-        let alice_address = ProtocolAddress::new(user1_name.to_string(), DeviceId::new(alice_device_id).unwrap());
+        let bob_address = ProtocolAddress::new(
+            user2_name.to_string(),
+            DeviceId::new(bob_device_id).unwrap(),
+        );
+        let alice_address = ProtocolAddress::new(
+            user1_name.to_string(),
+            DeviceId::new(alice_device_id).unwrap(),
+        );
 
-        user1.process_pre_key_bundle(user2_name.to_string(), user2_pre_key_bundle).await?;
+        user1
+            .process_pre_key_bundle(user2_name.to_string(), user2_pre_key_bundle)
+            .await?;
 
-        let msg1 = user1.encrypt(bob_address.clone(), b"Hello Bob".to_vec()).await?;
-        let decrypted1 = user2.decrypt(alice_address.clone(), msg1.cipher_text, msg1.ty).await?;
+        let msg1 = user1
+            .encrypt(bob_address.clone(), b"Hello Bob".to_vec())
+            .await?;
+        let decrypted1 = user2
+            .decrypt(alice_address.clone(), msg1.cipher_text, msg1.ty)
+            .await?;
         assert_eq!(decrypted1, b"Hello Bob");
 
-        let msg2 = user2.encrypt(alice_address.clone(), b"Hi Alice".to_vec()).await?;
-        let decrypted2 = user1.decrypt(bob_address.clone(), msg2.cipher_text, msg2.ty).await?;
+        let msg2 = user2
+            .encrypt(alice_address.clone(), b"Hi Alice".to_vec())
+            .await?;
+        let decrypted2 = user1
+            .decrypt(bob_address.clone(), msg2.cipher_text, msg2.ty)
+            .await?;
         assert_eq!(decrypted2, b"Hi Alice");
 
-        let msg3 = user1.encrypt(bob_address.clone(), b"How are you?".to_vec()).await?;
-        let decrypted3 = user2.decrypt(alice_address.clone(), msg3.cipher_text, msg3.ty).await?;
+        let msg3 = user1
+            .encrypt(bob_address.clone(), b"How are you?".to_vec())
+            .await?;
+        let decrypted3 = user2
+            .decrypt(alice_address.clone(), msg3.cipher_text, msg3.ty)
+            .await?;
         assert_eq!(decrypted3, b"How are you?");
 
         Ok(())
@@ -212,7 +318,11 @@ mod tests {
         let bob_bundle = bob.generate_prekey_bundle().await.unwrap();
         let bob_bundle2 = bob.generate_prekey_bundle().await.unwrap();
 
-        test_ffi_encryption(&charles, "charles", &bob, "bob", bob_bundle).await.unwrap();
-        test_ffi_encryption(&alice, "alice", &bob, "bob", bob_bundle2).await.unwrap();
+        test_ffi_encryption(&charles, "charles", &bob, "bob", bob_bundle)
+            .await
+            .unwrap();
+        test_ffi_encryption(&alice, "alice", &bob, "bob", bob_bundle2)
+            .await
+            .unwrap();
     }
 }
@@ -1,6 +1,13 @@
 use sqlx::SqlitePool;
 use sqlx::prelude::*;
 
+use crate::db::messages::{MessageEdit, ReactionCount, aggregate_reactions, placeholders};
+use crate::db::migrations::{FIREFLY_DB_MIGRATIONS, migrate};
+use crate::db::search::{
+    GroupMessageSearchHit, SNIPPET_ELLIPSIS, SNIPPET_MATCH_END, SNIPPET_MATCH_START,
+    SNIPPET_TOKENS, fts_query, group_message_text, snippet_html,
+};
+
 #[derive(sqlx::FromRow)]
 pub struct GroupMessage {
     pub id: u64,
@@ -9,6 +16,33 @@ pub struct GroupMessage {
     pub message: Vec<u8>,
     pub channel_id: u32,
     pub epoch: u32,
+    /// id of the edit that wrote the current `message`, 0 if never edited
+    pub edited_at: u64,
+    /// deleted for everyone, `message` is empty
+    pub deleted: bool,
+}
+
+impl GroupMessage {
+    /// A message as it was sent, neither edited nor deleted.
+    pub fn new(
+        id: u64,
+        group_id: u64,
+        by: String,
+        message: Vec<u8>,
+        channel_id: u32,
+        epoch: u32,
+    ) -> Self {
+        Self {
+            id,
+            group_id,
+            by,
+            message,
+            channel_id,
+            epoch,
+            edited_at: 0,
+            deleted: false,
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -18,27 +52,124 @@ pub struct GroupMessagesStore {
 
 impl GroupMessagesStore {
     pub async fn new(pool: SqlitePool) -> anyhow::Result<Self> {
-        pool.execute(
+        migrate(&pool, FIREFLY_DB_MIGRATIONS).await?;
+
+        let store = Self { pool };
+        store.rebuild_search_index_if_pending().await?;
+
+        Ok(store)
+    }
+
+    /// Rows of the index share the rowid of the message they were extracted
+    /// from. The migration creating the index marks it for a rebuild, so the
+    /// history of existing installs gets indexed once.
+    async fn rebuild_search_index_if_pending(&self) -> anyhow::Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        let pending = sqlx::query("SELECT name FROM pending_index_rebuilds WHERE name = ?")
+            .bind("group_messages_fts")
+            .fetch_optional(&mut *tx)
+            .await?;
+        if pending.is_none() {
+            return Ok(());
+        }
+
+        sqlx::query("DELETE FROM group_messages_fts")
+            .execute(&mut *tx)
+            .await?;
+
+        let rows = sqlx::query("SELECT rowid, message FROM group_messages")
+            .fetch_all(&mut *tx)
+            .await?;
+
+        let mut indexed = 0;
+        for row in rows {
+            let rowid: i64 = row.try_get("rowid")?;
+            let message: Vec<u8> = row.try_get("message")?;
+            if let Some(text) = group_message_text(&message) {
+                sqlx::query("INSERT INTO group_messages_fts (rowid, text) VALUES (?, ?)")
+                    .bind(rowid)
+                    .bind(text)
+                    .execute(&mut *tx)
+                    .await?;
+                indexed += 1;
+            }
+        }
+
+        sqlx::query("DELETE FROM pending_index_rebuilds WHERE name = ?")
+            .bind("group_messages_fts")
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        log::info!("rebuilt group messages search index, indexed: {}", indexed);
+
+        Ok(())
+    }
+
+    /// Moves the group's cursor past `id`, for what was handled without
+    /// storing a message: commits, proposals, typing signals, edits. Never
+    /// moves it back.
+    pub async fn update_cursor(&self, id: u64, group_id: u64, epoch: u32) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+        INSERT INTO group_message_cursors (group_id, id, epoch) VALUES (?, ?, ?)
+        ON CONFLICT (group_id) DO UPDATE SET id = excluded.id, epoch = excluded.epoch
+        WHERE excluded.id > group_message_cursors.id
+        "#,
+        )
+        .bind(group_id as i64)
+        .bind(id as i64)
+        .bind(epoch)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Id of the last message of the group that was stored or moved past, 0
+    /// if there was none.
+    pub async fn get_cursor(&self, group_id: u64) -> anyhow::Result<u64> {
+        let id: Option<i64> = sqlx::query_scalar(
             r#"
-        CREATE TABLE IF NOT EXISTS group_messages (
-            id INTEGER NOT NULL,
-            group_id INTEGER NOT NULL,
-            by TEXT NOT NULL,
-            message BLOB NOT NULL,
-            channel_id INTEGER NOT NULL,
-            epoch INTEGER NOT NULL DEFAULT 0,
-
-            PRIMARY KEY (group_id, id)
+        SELECT MAX(id) FROM (
+            SELECT id FROM group_message_cursors WHERE group_id = ?
+            UNION ALL
+            SELECT MAX(id) AS id FROM group_messages WHERE group_id = ?
         )
         "#,
         )
+        .bind(group_id as i64)
+        .bind(group_id as i64)
+        .fetch_one(&self.pool)
         .await?;
 
-        Ok(Self { pool })
+        Ok(id.unwrap_or_default() as u64)
     }
 
-    pub async fn update_cursor(&self, id: u64, group_id: u64, epoch: u32) -> anyhow::Result<()> {
-        self.add(id, group_id, 0, epoch, "", "".as_bytes()).await
+    /// `(group_id, cursor)` of every group with a stored message or a cursor,
+    /// see `get_cursor`.
+    pub async fn get_all_cursors(&self) -> anyhow::Result<Vec<(u64, u64)>> {
+        let rows = sqlx::query(
+            r#"
+        SELECT group_id, MAX(id) AS id FROM (
+            SELECT group_id, id FROM group_message_cursors
+            UNION ALL
+            SELECT group_id, MAX(id) AS id FROM group_messages GROUP BY group_id
+        )
+        GROUP BY group_id
+        "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter()
+            .map(|row| -> anyhow::Result<(u64, u64)> {
+                let group_id: i64 = row.try_get("group_id")?;
+                let id: i64 = row.try_get("id")?;
+                Ok((group_id as u64, id as u64))
+            })
+            .collect()
     }
 
     pub async fn add(
@@ -57,7 +188,8 @@ impl GroupMessagesStore {
             channel_id,
             by
         );
-        sqlx::query(
+        let mut tx = self.pool.begin().await?;
+        let rowid = sqlx::query(
             r#"
         INSERT INTO group_messages (id, group_id, by, message, channel_id, epoch)
         VALUES (?, ?, ?, ?, ?, ?)
@@ -69,12 +201,64 @@ impl GroupMessagesStore {
         .bind(message)
         .bind(channel_id)
         .bind(epoch)
-        .execute(&self.pool)
-        .await?;
+        .execute(&mut *tx)
+        .await?
+        .last_insert_rowid();
+
+        if let Some(text) = group_message_text(message) {
+            sqlx::query("INSERT INTO group_messages_fts (rowid, text) VALUES (?, ?)")
+                .bind(rowid)
+                .bind(text)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
 
         Ok(())
     }
 
+    /// Full-text search over every group, most relevant first.
+    pub async fn search(
+        &self,
+        query: &str,
+        limit: u32,
+    ) -> anyhow::Result<Vec<GroupMessageSearchHit>> {
+        let Some(query) = fts_query(query) else {
+            return Ok(vec![]);
+        };
+
+        let rows = sqlx::query(
+            r#"
+        SELECT gm.id, gm.group_id, gm.by, gm.message, gm.channel_id, gm.epoch, gm.edited_at, gm.deleted,
+            snippet(group_messages_fts, 0, ?, ?, ?, ?) AS snippet,
+            bm25(group_messages_fts) AS rank
+        FROM group_messages_fts
+        JOIN group_messages gm ON gm.rowid = group_messages_fts.rowid
+        WHERE group_messages_fts MATCH ?
+        ORDER BY rank LIMIT ?
+        "#,
+        )
+        .bind(SNIPPET_MATCH_START.to_string())
+        .bind(SNIPPET_MATCH_END.to_string())
+        .bind(SNIPPET_ELLIPSIS)
+        .bind(SNIPPET_TOKENS)
+        .bind(query)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut hits = Vec::with_capacity(rows.len());
+        for row in rows.iter() {
+            hits.push(GroupMessageSearchHit {
+                message: GroupMessage::from_row(row)?,
+                snippet: snippet_html(&row.try_get::<String, _>("snippet")?),
+                rank: row.try_get("rank")?,
+            });
+        }
+
+        Ok(hits)
+    }
+
     pub async fn get(
         &self,
         group_id: u64,
@@ -83,7 +267,7 @@ impl GroupMessagesStore {
     ) -> anyhow::Result<Vec<GroupMessage>> {
         let rows = sqlx::query(
             r#"
-        SELECT id, by, message, channel_id, group_id, epoch
+        SELECT id, by, message, channel_id, group_id, epoch, edited_at, deleted
         FROM group_messages
         WHERE group_id = ? AND id < ?
         ORDER BY id DESC LIMIT ?
@@ -101,10 +285,26 @@ impl GroupMessagesStore {
             .collect::<Result<Vec<_>, _>>()?)
     }
 
+    pub async fn get_by_id(&self, group_id: u64, id: u64) -> anyhow::Result<Option<GroupMessage>> {
+        let row = sqlx::query(
+            r#"
+        SELECT id, by, message, channel_id, group_id, epoch, edited_at, deleted
+        FROM group_messages
+        WHERE group_id = ? AND id = ?
+        "#,
+        )
+        .bind(group_id as i64)
+        .bind(id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.as_ref().map(GroupMessage::from_row).transpose()?)
+    }
+
     pub async fn get_all_last_messages(&self) -> anyhow::Result<Vec<GroupMessage>> {
         let rows = sqlx::query(
             r#"
-SELECT gm.group_id, gm.id, gm.by, gm.message, gm.channel_id, gm.epoch
+SELECT gm.group_id, gm.id, gm.by, gm.message, gm.channel_id, gm.epoch, gm.edited_at, gm.deleted
 FROM group_messages gm
 JOIN (
     SELECT group_id, MAX(id) AS max_id
@@ -125,31 +325,365 @@ AND gm.id = last.max_id;
     }
 
     pub async fn get_last_message_of_group(&self, group_id: u64) -> anyhow::Result<GroupMessage> {
-        let row = sqlx::query("SELECT group_id, id, by, message, channel_id, epoch FROM group_messages WHERE group_id = ? ORDER BY id DESC LIMIT 1").bind(group_id as i64).fetch_one(&self.pool).await?;
+        let row = sqlx::query("SELECT group_id, id, by, message, channel_id, epoch, edited_at, deleted FROM group_messages WHERE group_id = ? ORDER BY id DESC LIMIT 1").bind(group_id as i64).fetch_one(&self.pool).await?;
         Ok(GroupMessage::from_row(&row)?)
     }
 
+    /// Replaces message `id` of the group with `message`, keeping the replaced
+    /// version in the edit history. `by` is the MLS authenticated sender of
+    /// the edit, only the sender of the message may edit it. Returns the
+    /// updated message, `None` if there was nothing to edit or a newer edit
+    /// was already applied.
+    pub async fn edit(
+        &self,
+        group_id: u64,
+        id: u64,
+        by: &str,
+        edited_at: u64,
+        message: &[u8],
+    ) -> anyhow::Result<Option<GroupMessage>> {
+        let mut tx = self.pool.begin().await?;
+
+        let Some((rowid, mut current)) = select_message_in(&mut tx, group_id, id, by).await? else {
+            return Ok(None);
+        };
+        if current.deleted || current.edited_at >= edited_at {
+            return Ok(None);
+        }
+
+        sqlx::query(
+            "INSERT INTO group_message_edits (group_id, id, edited_at, message) VALUES (?, ?, ?, ?)",
+        )
+        .bind(group_id as i64)
+        .bind(id as i64)
+        .bind(current.edited_at as i64)
+        .bind(&current.message)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query("UPDATE group_messages SET message = ?, edited_at = ? WHERE rowid = ?")
+            .bind(message)
+            .bind(edited_at as i64)
+            .bind(rowid)
+            .execute(&mut *tx)
+            .await?;
+
+        reindex_message_in(&mut tx, rowid, group_message_text(message)).await?;
+
+        tx.commit().await?;
+
+        current.message = message.to_vec();
+        current.edited_at = edited_at;
+        Ok(Some(current))
+    }
+
+    /// Tombstones message `id` of the group, dropping its content and edit
+    /// history. `by` is the MLS authenticated sender of the delete, only the
+    /// sender of the message may delete it. Returns the tombstone, `None` if
+    /// there was nothing to delete.
+    pub async fn delete(
+        &self,
+        group_id: u64,
+        id: u64,
+        by: &str,
+    ) -> anyhow::Result<Option<GroupMessage>> {
+        let mut tx = self.pool.begin().await?;
+
+        let Some((rowid, mut current)) = select_message_in(&mut tx, group_id, id, by).await? else {
+            return Ok(None);
+        };
+        if current.deleted {
+            return Ok(None);
+        }
+
+        sqlx::query("DELETE FROM group_message_edits WHERE group_id = ? AND id = ?")
+            .bind(group_id as i64)
+            .bind(id as i64)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM group_message_reactions WHERE group_id = ? AND message_id = ?")
+            .bind(group_id as i64)
+            .bind(id as i64)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query("UPDATE group_messages SET message = ?, deleted = 1 WHERE rowid = ?")
+            .bind(Vec::<u8>::new())
+            .bind(rowid)
+            .execute(&mut *tx)
+            .await?;
+
+        reindex_message_in(&mut tx, rowid, None).await?;
+
+        tx.commit().await?;
+
+        current.message.clear();
+        current.deleted = true;
+        Ok(Some(current))
+    }
+
+    /// Sets the reaction of `reactor`, the MLS authenticated sender, on message
+    /// `message_id` of the group, an empty `emoji` removes it. Changes older
+    /// than the stored reaction are ignored. Returns whether anything changed.
+    pub async fn set_reaction(
+        &self,
+        group_id: u64,
+        message_id: u64,
+        reactor: &str,
+        emoji: &str,
+        reacted_at: u64,
+    ) -> anyhow::Result<bool> {
+        let result = if emoji.is_empty() {
+            sqlx::query(
+                "DELETE FROM group_message_reactions WHERE group_id = ? AND message_id = ? AND reactor = ? AND reacted_at < ?",
+            )
+            .bind(group_id as i64)
+            .bind(message_id as i64)
+            .bind(reactor)
+            .bind(reacted_at as i64)
+            .execute(&self.pool)
+            .await?
+        } else {
+            sqlx::query(
+                r#"
+        INSERT INTO group_message_reactions (group_id, message_id, reactor, emoji, reacted_at)
+        VALUES (?, ?, ?, ?, ?)
+        ON CONFLICT (group_id, message_id, reactor) DO UPDATE
+        SET emoji = excluded.emoji, reacted_at = excluded.reacted_at
+        WHERE excluded.reacted_at > group_message_reactions.reacted_at
+        "#,
+            )
+            .bind(group_id as i64)
+            .bind(message_id as i64)
+            .bind(reactor)
+            .bind(emoji)
+            .bind(reacted_at as i64)
+            .execute(&self.pool)
+            .await?
+        };
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Reaction counts of the given messages of the group, grouped by message
+    /// and emoji.
+    pub async fn get_reactions(
+        &self,
+        group_id: u64,
+        message_ids: &[u64],
+    ) -> anyhow::Result<Vec<ReactionCount>> {
+        if message_ids.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let q = format!(
+            "SELECT message_id, emoji, reactor FROM group_message_reactions WHERE group_id = ? AND message_id IN ({}) ORDER BY message_id, emoji, reacted_at",
+            placeholders(message_ids.len())
+        );
+        let mut query = sqlx::query(&q).bind(group_id as i64);
+        for message_id in message_ids {
+            query = query.bind(*message_id as i64);
+        }
+        let rows = query.fetch_all(&self.pool).await?;
+
+        let mut reactions = Vec::with_capacity(rows.len());
+        for row in rows {
+            let message_id: i64 = row.try_get("message_id")?;
+            reactions.push((
+                message_id as u64,
+                row.try_get("emoji")?,
+                row.try_get("reactor")?,
+            ));
+        }
+
+        Ok(aggregate_reactions(reactions))
+    }
+
+    /// Replaced versions of message `id` of the group, oldest first.
+    pub async fn get_edits(&self, group_id: u64, id: u64) -> anyhow::Result<Vec<MessageEdit>> {
+        let rows = sqlx::query(
+            "SELECT edited_at, message FROM group_message_edits WHERE group_id = ? AND id = ? ORDER BY edited_at",
+        )
+        .bind(group_id as i64)
+        .bind(id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut edits = Vec::with_capacity(rows.len());
+        for row in rows {
+            let edited_at: i64 = row.try_get("edited_at")?;
+            edits.push(MessageEdit {
+                edited_at: edited_at as u64,
+                message: row.try_get("message")?,
+            });
+        }
+
+        Ok(edits)
+    }
+
+    /// Deletes the messages of the group with ids in `since..before` along
+    /// with their edits and reactions, for disappearing messages. Returns the
+    /// ids of the deleted messages.
+    pub async fn delete_expired(
+        &self,
+        group_id: u64,
+        since: u64,
+        before: u64,
+    ) -> anyhow::Result<Vec<u64>> {
+        let mut tx = self.pool.begin().await?;
+
+        let rows = sqlx::query(
+            "SELECT rowid, id FROM group_messages WHERE group_id = ? AND id >= ? AND id < ?",
+        )
+        .bind(group_id as i64)
+        .bind(since as i64)
+        .bind(before as i64)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let mut ids = Vec::with_capacity(rows.len());
+        for row in rows {
+            let rowid: i64 = row.try_get(0)?;
+            let id: i64 = row.try_get(1)?;
+
+            sqlx::query("DELETE FROM group_messages_fts WHERE rowid = ?")
+                .bind(rowid)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query("DELETE FROM group_message_edits WHERE group_id = ? AND id = ?")
+                .bind(group_id as i64)
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query(
+                "DELETE FROM group_message_reactions WHERE group_id = ? AND message_id = ?",
+            )
+            .bind(group_id as i64)
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query("DELETE FROM group_messages WHERE rowid = ?")
+                .bind(rowid)
+                .execute(&mut *tx)
+                .await?;
+
+            ids.push(id as u64);
+        }
+
+        tx.commit().await?;
+
+        Ok(ids)
+    }
+
     pub async fn delete_by_group_id(&self, group_id: u64) -> anyhow::Result<()> {
         log::info!("store delete_by_group_id: group_id={}", group_id);
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            "DELETE FROM group_messages_fts WHERE rowid IN (SELECT rowid FROM group_messages WHERE group_id = ?)",
+        )
+        .bind(group_id as i64)
+        .execute(&mut *tx)
+        .await?;
         sqlx::query("DELETE FROM group_messages WHERE group_id = ?")
             .bind(group_id as i64)
-            .execute(&self.pool)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM group_message_edits WHERE group_id = ?")
+            .bind(group_id as i64)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM group_message_reactions WHERE group_id = ?")
+            .bind(group_id as i64)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM group_message_cursors WHERE group_id = ?")
+            .bind(group_id as i64)
+            .execute(&mut *tx)
             .await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Deletes the whole group message history, for a logout.
+    pub async fn delete_all(&self) -> anyhow::Result<()> {
+        log::info!("store delete_all: group messages");
+        let mut tx = self.pool.begin().await?;
+        for table in [
+            "group_messages_fts",
+            "group_messages",
+            "group_message_edits",
+            "group_message_reactions",
+            "group_message_cursors",
+        ] {
+            sqlx::query(&format!("DELETE FROM {}", table))
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
 
         Ok(())
     }
 }
 
+async fn select_message_in(
+    conn: &mut sqlx::SqliteConnection,
+    group_id: u64,
+    id: u64,
+    by: &str,
+) -> anyhow::Result<Option<(i64, GroupMessage)>> {
+    let row = sqlx::query(
+        r#"
+        SELECT rowid, id, by, message, channel_id, group_id, epoch, edited_at, deleted
+        FROM group_messages
+        WHERE group_id = ? AND id = ? AND by = ?
+        "#,
+    )
+    .bind(group_id as i64)
+    .bind(id as i64)
+    .bind(by)
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    let Some(row) = row else {
+        return Ok(None);
+    };
+
+    Ok(Some((row.try_get("rowid")?, GroupMessage::from_row(&row)?)))
+}
+
+/// Replaces the searchable text of a message, `None` only removes it.
+async fn reindex_message_in(
+    conn: &mut sqlx::SqliteConnection,
+    rowid: i64,
+    text: Option<String>,
+) -> anyhow::Result<()> {
+    sqlx::query("DELETE FROM group_messages_fts WHERE rowid = ?")
+        .bind(rowid)
+        .execute(&mut *conn)
+        .await?;
+
+    if let Some(text) = text {
+        sqlx::query("INSERT INTO group_messages_fts (rowid, text) VALUES (?, ?)")
+            .bind(rowid)
+            .bind(text)
+            .execute(&mut *conn)
+            .await?;
+    }
+
+    Ok(())
+}
+
 impl GroupMessagesStore {
     pub async fn update_cursor_ffi(
         &self,
         id: u64,
         group_id: u64,
         epoch: u32,
-    ) -> Result<(), crate::DumbError> {
+    ) -> Result<(), crate::FireflyError> {
         self.update_cursor(id, group_id, epoch)
             .await
-            .map_err(crate::DumbError::from_anyhow)
+            .map_err(crate::FireflyError::from_anyhow)
     }
 
     pub async fn add_ffi(
@@ -160,10 +694,10 @@ impl GroupMessagesStore {
         epoch: u32,
         by: String,
         message: Vec<u8>,
-    ) -> Result<(), crate::DumbError> {
+    ) -> Result<(), crate::FireflyError> {
         self.add(id, group_id, channel_id, epoch, &by, &message)
             .await
-            .map_err(crate::DumbError::from_anyhow)
+            .map_err(crate::FireflyError::from_anyhow)
     }
 
     pub async fn get_ffi(
@@ -171,22 +705,54 @@ impl GroupMessagesStore {
         group_id: u64,
         start_before: u64,
         limit: u32,
-    ) -> Result<Vec<GroupMessage>, crate::DumbError> {
+    ) -> Result<Vec<GroupMessage>, crate::FireflyError> {
         self.get(group_id, start_before, limit)
             .await
-            .map_err(crate::DumbError::from_anyhow)
+            .map_err(crate::FireflyError::from_anyhow)
     }
 
-    pub async fn get_all_last_messages_ffi(&self) -> Result<Vec<GroupMessage>, crate::DumbError> {
+    pub async fn get_all_last_messages_ffi(
+        &self,
+    ) -> Result<Vec<GroupMessage>, crate::FireflyError> {
         self.get_all_last_messages()
             .await
-            .map_err(crate::DumbError::from_anyhow)
+            .map_err(crate::FireflyError::from_anyhow)
+    }
+
+    pub async fn search_ffi(
+        &self,
+        query: String,
+        limit: u32,
+    ) -> Result<Vec<GroupMessageSearchHit>, crate::FireflyError> {
+        self.search(&query, limit)
+            .await
+            .map_err(crate::FireflyError::from_anyhow)
+    }
+
+    pub async fn get_edits_ffi(
+        &self,
+        group_id: u64,
+        id: u64,
+    ) -> Result<Vec<MessageEdit>, crate::FireflyError> {
+        self.get_edits(group_id, id)
+            .await
+            .map_err(crate::FireflyError::from_anyhow)
     }
 
-    pub async fn delete_by_group_id_ffi(&self, group_id: u64) -> Result<(), crate::DumbError> {
+    pub async fn get_reactions_ffi(
+        &self,
+        group_id: u64,
+        message_ids: Vec<u64>,
+    ) -> Result<Vec<ReactionCount>, crate::FireflyError> {
+        self.get_reactions(group_id, &message_ids)
+            .await
+            .map_err(crate::FireflyError::from_anyhow)
+    }
+
+    pub async fn delete_by_group_id_ffi(&self, group_id: u64) -> Result<(), crate::FireflyError> {
         self.delete_by_group_id(group_id)
             .await
-            .map_err(crate::DumbError::from_anyhow)
+            .map_err(crate::FireflyError::from_anyhow)
     }
 }
 
@@ -382,4 +948,192 @@ mod tests {
         assert_eq!(store.get(100, 10, 10).await.unwrap().len(), 0);
         assert_eq!(store.get(200, 10, 10).await.unwrap().len(), 1);
     }
+
+    #[tokio::test]
+    async fn test_cursor_is_not_a_message() {
+        let pool = setup_test_db().await;
+        let store = GroupMessagesStore::new(pool).await.unwrap();
+
+        store.add(1, 100, 1, 1, "user1", &[1]).await.unwrap();
+        store.update_cursor(2, 100, 1).await.unwrap();
+        store.update_cursor(5, 200, 1).await.unwrap();
+        // never moves back
+        store.update_cursor(4, 200, 1).await.unwrap();
+
+        let last_messages = store.get_all_last_messages().await.unwrap();
+        assert_eq!(last_messages.len(), 1);
+        assert_eq!(last_messages[0].id, 1);
+        assert_eq!(store.get(100, 10, 10).await.unwrap().len(), 1);
+        assert!(store.get(200, 10, 10).await.unwrap().is_empty());
+
+        assert_eq!(store.get_cursor(100).await.unwrap(), 2);
+        assert_eq!(store.get_cursor(200).await.unwrap(), 5);
+        assert_eq!(store.get_cursor(300).await.unwrap(), 0);
+
+        store.add(3, 100, 1, 1, "user2", &[3]).await.unwrap();
+        let mut cursors = store.get_all_cursors().await.unwrap();
+        cursors.sort();
+        assert_eq!(cursors, vec![(100, 3), (200, 5)]);
+
+        store.delete_by_group_id(200).await.unwrap();
+        assert_eq!(store.get_cursor(200).await.unwrap(), 0);
+    }
+
+    fn text_message(text: &str) -> Vec<u8> {
+        use crate::pb::firefly::firefly::{GroupMessageInner, MessagePayload, group_message_inner};
+
+        crate::utils::serialize_proto(&GroupMessageInner {
+            channel_id: 1,
+            message: Some(group_message_inner::Message::MessagePayload(
+                MessagePayload {
+                    text: text.to_string(),
+                    ..Default::default()
+                },
+            )),
+        })
+        .unwrap()
+        .to_vec()
+    }
+
+    #[tokio::test]
+    async fn test_search() {
+        let pool = setup_test_db().await;
+        let store = GroupMessagesStore::new(pool).await.unwrap();
+
+        store
+            .add(
+                1,
+                100,
+                1,
+                1,
+                "user1",
+                &text_message("release notes are ready"),
+            )
+            .await
+            .unwrap();
+        store
+            .add(2, 200, 1, 1, "user2", &text_message("ready for review"))
+            .await
+            .unwrap();
+        store.update_cursor(3, 200, 1).await.unwrap();
+
+        let hits = store.search("ready", 10).await.unwrap();
+        assert_eq!(hits.len(), 2);
+        assert!(
+            hits.iter()
+                .all(|hit| hit.snippet.contains("<mark>ready</mark>"))
+        );
+
+        let hits = store.search("release", 10).await.unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].message.group_id, 100);
+        assert_eq!(hits[0].message.by, "user1");
+
+        store.delete_by_group_id(100).await.unwrap();
+        assert!(store.search("release", 10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_edit_and_delete() {
+        let pool = setup_test_db().await;
+        let store = GroupMessagesStore::new(pool).await.unwrap();
+
+        store
+            .add(1, 100, 1, 1, "user1", &text_message("first draft"))
+            .await
+            .unwrap();
+
+        // only the sender may edit or delete
+        assert!(
+            store
+                .edit(100, 1, "user2", 2, &text_message("hijacked"))
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(store.delete(100, 1, "user2").await.unwrap().is_none());
+
+        let edited = store
+            .edit(100, 1, "user1", 3, &text_message("final version"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(edited.edited_at, 3);
+        assert!(
+            store
+                .edit(100, 1, "user1", 3, &text_message("replayed"))
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        let edits = store.get_edits(100, 1).await.unwrap();
+        assert_eq!(edits.len(), 1);
+        assert_eq!(edits[0].message, text_message("first draft"));
+        assert_eq!(store.search("final", 10).await.unwrap().len(), 1);
+        assert!(store.search("draft", 10).await.unwrap().is_empty());
+
+        let deleted = store.delete(100, 1, "user1").await.unwrap().unwrap();
+        assert!(deleted.deleted);
+        assert!(deleted.message.is_empty());
+
+        let messages = store.get(100, 10, 10).await.unwrap();
+        assert!(messages[0].deleted);
+        assert!(store.get_edits(100, 1).await.unwrap().is_empty());
+        assert!(store.search("final", 10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_reactions() {
+        let pool = setup_test_db().await;
+        let store = GroupMessagesStore::new(pool).await.unwrap();
+
+        store.add(1, 100, 1, 1, "user1", &[1]).await.unwrap();
+
+        assert!(store.set_reaction(100, 1, "user2", "🎉", 2).await.unwrap());
+        assert!(store.set_reaction(100, 1, "user3", "🎉", 3).await.unwrap());
+        assert!(store.set_reaction(100, 1, "user2", "👀", 4).await.unwrap());
+
+        let reactions = store.get_reactions(100, &[1]).await.unwrap();
+        assert_eq!(reactions.len(), 2);
+        assert_eq!(reactions[0].emoji, "🎉");
+        assert_eq!(reactions[0].reactors, vec!["user3"]);
+        assert_eq!(reactions[1].emoji, "👀");
+        assert_eq!(reactions[1].reactors, vec!["user2"]);
+
+        // reactions go with the message
+        store.delete(100, 1, "user1").await.unwrap();
+        assert!(store.get_reactions(100, &[1]).await.unwrap().is_empty());
+    }
+    #[tokio::test]
+    async fn test_delete_expired() {
+        let pool = setup_test_db().await;
+        let store = GroupMessagesStore::new(pool).await.unwrap();
+
+        for id in 1..=4 {
+            store
+                .add(id, 100, 1, 1, "user1", &[id as u8])
+                .await
+                .unwrap();
+        }
+        store.add(2, 200, 1, 1, "user1", &[2]).await.unwrap();
+        store.set_reaction(100, 2, "user2", "🎉", 5).await.unwrap();
+
+        let mut deleted = store.delete_expired(100, 2, 4).await.unwrap();
+        deleted.sort();
+        assert_eq!(deleted, vec![2, 3]);
+
+        let ids = store
+            .get(100, i64::MAX as u64, 10)
+            .await
+            .unwrap()
+            .iter()
+            .map(|message| message.id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![4, 1]);
+        assert!(store.get_reactions(100, &[2]).await.unwrap().is_empty());
+
+        // other groups are left alone
+        assert!(store.get_by_id(200, 2).await.unwrap().is_some());
+    }
 }
@@ -3,36 +3,18 @@ use std::collections::HashMap;
 use firefly_core::storage_provider::MlsGroupStateStorage;
 use firefly_core::storage_provider::MlsKeyPackageStorage;
 use firefly_core::storage_provider::MlsPreSharedKeyStorage;
-use sqlx::Executor;
 use sqlx::SqlitePool;
 use sqlx::prelude::*;
 
+use crate::db::migrations::{FIREFLY_DB_MIGRATIONS, migrate};
+
 pub struct GroupStateStore {
     pool: SqlitePool,
 }
 
 impl GroupStateStore {
     pub async fn new(pool: SqlitePool) -> anyhow::Result<Self> {
-        pool.execute(
-            r#"
-        CREATE TABLE IF NOT EXISTS group_states (
-            id BLOB PRIMARY KEY,
-            state BLOB NOT NULL
-        )"#,
-        )
-        .await?;
-
-        pool.execute(
-            r#"
-        CREATE TABLE IF NOT EXISTS group_epoch_states (
-            id BLOB NOT NULL,
-            epoch INTEGER NOT NULL,
-            state BLOB NOT NULL,
-            PRIMARY KEY (id, epoch),
-            FOREIGN KEY (id) REFERENCES group_states(id) ON DELETE CASCADE
-        )"#,
-        )
-        .await?;
+        migrate(&pool, FIREFLY_DB_MIGRATIONS).await?;
 
         Ok(Self { pool })
     }
@@ -166,15 +148,7 @@ pub struct SelfGroupKeyPackageStore {
 
 impl SelfGroupKeyPackageStore {
     pub async fn new(pool: SqlitePool) -> anyhow::Result<Self> {
-        pool.execute(
-            r#"
-            CREATE TABLE IF NOT EXISTS self_group_key_packages(
-                id INTEGER PRIMARY KEY NOT NULL,
-                key_package BLOB NOT NULL
-            )
-            "#,
-        )
-        .await?;
+        migrate(&pool, FIREFLY_DB_MIGRATIONS).await?;
 
         Ok(Self { pool })
     }
@@ -219,15 +193,7 @@ pub struct GroupKeyPackageStore {
 
 impl GroupKeyPackageStore {
     pub async fn new(pool: SqlitePool) -> anyhow::Result<Self> {
-        pool.execute(
-            r#"
-            CREATE TABLE IF NOT EXISTS group_key_packages(
-                id BLOB PRIMARY KEY NOT NULL,
-                key_package BLOB NOT NULL
-            )
-            "#,
-        )
-        .await?;
+        migrate(&pool, FIREFLY_DB_MIGRATIONS).await?;
 
         Ok(Self { pool })
     }
@@ -290,24 +256,14 @@ pub struct GroupInfo {
     pub description: String,
 }
 
-#[derive(Clone )]
+#[derive(Clone)]
 pub struct GroupInfoStore {
     pool: SqlitePool,
 }
 
 impl GroupInfoStore {
     pub async fn new(pool: SqlitePool) -> anyhow::Result<Self> {
-        pool.execute(
-            r#"
-            CREATE TABLE IF NOT EXISTS group_infos(
-                id INTEGER PRIMARY KEY NOT NULL,
-                group_state_id BLOB NOT NULL,
-                name TEXT NOT NULL,
-                description TEXT NOT NULL
-            )
-            "#,
-        )
-        .await?;
+        migrate(&pool, FIREFLY_DB_MIGRATIONS).await?;
 
         Ok(Self { pool })
     }
@@ -403,12 +359,14 @@ impl GroupInfoStore {
 }
 
 impl GroupInfoStore {
-    pub async fn get_all_ffi(&self) -> Result<Vec<GroupInfo>, crate::DumbError> {
-        self.get_all().await.map_err(crate::DumbError::from_anyhow)
+    pub async fn get_all_ffi(&self) -> Result<Vec<GroupInfo>, crate::FireflyError> {
+        self.get_all()
+            .await
+            .map_err(crate::FireflyError::from_anyhow)
     }
 
-    pub async fn get_ffi(&self, id: u64) -> Result<GroupInfo, crate::DumbError> {
-        self.get(id).await.map_err(crate::DumbError::from_anyhow)
+    pub async fn get_ffi(&self, id: u64) -> Result<GroupInfo, crate::FireflyError> {
+        self.get(id).await.map_err(crate::FireflyError::from_anyhow)
     }
 
     pub async fn set_ffi(
@@ -417,14 +375,16 @@ impl GroupInfoStore {
         name: String,
         description: String,
         group_state_id: Vec<u8>,
-    ) -> Result<(), crate::DumbError> {
+    ) -> Result<(), crate::FireflyError> {
         self.set(id, name, description, group_state_id)
             .await
-            .map_err(crate::DumbError::from_anyhow)
+            .map_err(crate::FireflyError::from_anyhow)
     }
 
-    pub async fn delete_ffi(&self, id: u64) -> Result<(), crate::DumbError> {
-        self.delete(id).await.map_err(crate::DumbError::from_anyhow)
+    pub async fn delete_ffi(&self, id: u64) -> Result<(), crate::FireflyError> {
+        self.delete(id)
+            .await
+            .map_err(crate::FireflyError::from_anyhow)
     }
 }
 
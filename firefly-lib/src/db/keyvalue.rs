@@ -1,5 +1,7 @@
 use sqlx::{SqlitePool, prelude::*};
 
+use crate::db::migrations::{FIREFLY_DB_MIGRATIONS, migrate};
+
 pub const KEY_LAST_RECEIVED_MESSAGE_ID: &str = "last_received_message_id";
 
 pub const KEY_LAST_RECEIVED_GROUP_MESSAGE_ID: &str = "last_received_group_message_id";
@@ -13,16 +15,7 @@ pub struct KeyValueStore {
 
 impl KeyValueStore {
     pub async fn new(pool: SqlitePool) -> anyhow::Result<Self> {
-        sqlx::query(
-            r#"
-            CREATE TABLE IF NOT EXISTS key_value_store (
-                key TEXT PRIMARY KEY,
-                value TEXT NOT NULL
-            )
-            "#,
-        )
-        .execute(&pool)
-        .await?;
+        migrate(&pool, FIREFLY_DB_MIGRATIONS).await?;
 
         Ok(Self { pool })
     }
@@ -1,12 +1,119 @@
-use sqlx::{SqlitePool, prelude::*};
+use sqlx::{SqliteConnection, SqlitePool, prelude::*};
 
-use crate::{DumbError, db::setup_pool_from_path};
+use crate::{
+    FireflyError,
+    db::{
+        migrations::{USER_MESSAGES_DB_MIGRATIONS, migrate},
+        search::{
+            SNIPPET_ELLIPSIS, SNIPPET_MATCH_END, SNIPPET_MATCH_START, SNIPPET_TOKENS,
+            UserMessageSearchHit, fts_query, snippet_html, user_message_text,
+        },
+        setup_pool_from_path,
+    },
+};
+
+/// For messages we sent, the furthest receipt the other side returned. For
+/// messages we received, the furthest receipt we returned. Only moves forward.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ReceiptState {
+    #[default]
+    Sent,
+    Delivered,
+    Read,
+}
+
+impl ReceiptState {
+    pub fn as_i64(self) -> i64 {
+        match self {
+            ReceiptState::Sent => 0,
+            ReceiptState::Delivered => 1,
+            ReceiptState::Read => 2,
+        }
+    }
+
+    pub fn from_i64(value: i64) -> anyhow::Result<Self> {
+        match value {
+            0 => Ok(ReceiptState::Sent),
+            1 => Ok(ReceiptState::Delivered),
+            2 => Ok(ReceiptState::Read),
+            _ => Err(anyhow::anyhow!("invalid receipt state {}", value)),
+        }
+    }
+}
 
 pub struct UserMessage {
     pub id: u64,
     pub other: String,
     pub message: Vec<u8>,
     pub sent_by_other: bool,
+    pub receipt: ReceiptState,
+    /// id of the edit that wrote the current `message`, 0 if never edited
+    pub edited_at: u64,
+    /// deleted for everyone, `message` is empty
+    pub deleted: bool,
+}
+
+impl UserMessage {
+    /// A message as it was sent, neither edited nor deleted. Messages of the
+    /// other side start out delivered, our own ones sent.
+    pub fn new(id: u64, other: String, message: Vec<u8>, sent_by_other: bool) -> Self {
+        Self {
+            id,
+            other,
+            message,
+            sent_by_other,
+            receipt: if sent_by_other {
+                ReceiptState::Delivered
+            } else {
+                ReceiptState::Sent
+            },
+            edited_at: 0,
+            deleted: false,
+        }
+    }
+}
+
+/// A replaced version of an edited message. `edited_at` is the id of the edit
+/// that wrote it, 0 for the original.
+pub struct MessageEdit {
+    pub edited_at: u64,
+    pub message: Vec<u8>,
+}
+
+/// Reactions with one emoji on a message, reactors in the order they reacted.
+pub struct ReactionCount {
+    pub message_id: u64,
+    pub emoji: String,
+    pub count: u32,
+    pub reactors: Vec<String>,
+}
+
+/// Groups `(message_id, emoji, reactor)` rows that are ordered by message and
+/// emoji.
+pub(crate) fn aggregate_reactions(
+    rows: impl IntoIterator<Item = (u64, String, String)>,
+) -> Vec<ReactionCount> {
+    let mut counts: Vec<ReactionCount> = Vec::new();
+    for (message_id, emoji, reactor) in rows {
+        match counts.last_mut() {
+            Some(last) if last.message_id == message_id && last.emoji == emoji => {
+                last.count += 1;
+                last.reactors.push(reactor);
+            }
+            _ => counts.push(ReactionCount {
+                message_id,
+                emoji,
+                count: 1,
+                reactors: vec![reactor],
+            }),
+        }
+    }
+    counts
+}
+
+/// `?, ?, ?` for binding a list in an `IN (...)` clause.
+pub(crate) fn placeholders(len: usize) -> String {
+    vec!["?"; len].join(", ")
 }
 
 pub struct LastMessageAndUnreadCount {
@@ -19,37 +126,70 @@ pub struct MessagesStore {
 }
 
 impl MessagesStore {
-    pub async fn new(pool: SqlitePool) -> Result<Self, DumbError> {
-        let sql = r#"
-CREATE TABLE IF NOT EXISTS user_messages (
-    id INTEGER NOT NULL,
-    other TEXT NOT NULL,
-    sent_by_other BOOLEAN NOT NULL,
-    message BLOB NOT NULL
-);
+    pub async fn new(pool: SqlitePool) -> Result<Self, FireflyError> {
+        migrate(&pool, USER_MESSAGES_DB_MIGRATIONS)
+            .await
+            .map_err(FireflyError::from_anyhow)?;
+
+        let store = Self { pool };
+        store.rebuild_search_index_if_pending().await?;
+
+        Ok(store)
+    }
+
+    /// Rows of the index share the rowid of the message they were extracted
+    /// from. The migration creating the index marks it for a rebuild, so the
+    /// history of existing installs gets indexed once.
+    async fn rebuild_search_index_if_pending(&self) -> Result<(), FireflyError> {
+        let mut tx = self.pool.begin().await?;
+
+        let pending = sqlx::query("SELECT name FROM pending_index_rebuilds WHERE name = ?")
+            .bind("user_messages_fts")
+            .fetch_optional(&mut *tx)
+            .await?;
+        if pending.is_none() {
+            return Ok(());
+        }
 
-CREATE INDEX IF NOT EXISTS user_messages_other_idx ON user_messages (other, id);
+        sqlx::query("DELETE FROM user_messages_fts")
+            .execute(&mut *tx)
+            .await?;
 
-CREATE TABLE IF NOT EXISTS last_seen_user_timestamps (
-    other TEXT NOT NULL PRIMARY KEY,
-    id INTEGER NOT NULL
-);
-"#;
+        let rows = sqlx::query("SELECT rowid, message FROM user_messages")
+            .fetch_all(&mut *tx)
+            .await?;
 
-        let mut conn = pool.acquire().await?;
-        for stmt in sql.split(';') {
-            conn.execute(stmt).await?;
+        let mut indexed = 0;
+        for row in rows {
+            let rowid: i64 = row.try_get("rowid")?;
+            let message: Vec<u8> = row.try_get("message")?;
+            if let Some(text) = user_message_text(&message) {
+                sqlx::query("INSERT INTO user_messages_fts (rowid, text) VALUES (?, ?)")
+                    .bind(rowid)
+                    .bind(text)
+                    .execute(&mut *tx)
+                    .await?;
+                indexed += 1;
+            }
         }
 
-        Ok(Self { pool })
+        sqlx::query("DELETE FROM pending_index_rebuilds WHERE name = ?")
+            .bind("user_messages_fts")
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        log::info!("rebuilt user messages search index, indexed: {}", indexed);
+
+        Ok(())
     }
 }
 
 impl MessagesStore {
-    pub async fn from_path(path: String) -> Result<Self, DumbError> {
+    pub async fn from_path(path: String) -> Result<Self, FireflyError> {
         let pool = setup_pool_from_path(&path, 5)
             .await
-            .map_err(DumbError::from_anyhow)?;
+            .map_err(FireflyError::from_anyhow)?;
 
         Ok(Self::new(pool).await?)
     }
@@ -59,9 +199,9 @@ impl MessagesStore {
         other: &str,
         before: i64,
         limit: i64,
-    ) -> Result<Vec<UserMessage>, DumbError> {
+    ) -> Result<Vec<UserMessage>, FireflyError> {
         let rows = sqlx::query(
-            "SELECT other, message, sent_by_other, id FROM user_messages WHERE other = ? AND id < ? ORDER BY id DESC LIMIT ?",
+            "SELECT other, message, sent_by_other, id, receipt_state, edited_at, deleted FROM user_messages WHERE other = ? AND id < ? ORDER BY id DESC LIMIT ?",
         )
         .bind(other)
         .bind(before)
@@ -71,12 +211,7 @@ impl MessagesStore {
         let mut messages = Vec::<UserMessage>::with_capacity(rows.len());
 
         for row in rows {
-            messages.push(UserMessage {
-                id: row.try_get("id")?,
-                other: row.try_get("other")?,
-                message: row.try_get("message")?,
-                sent_by_other: row.try_get("sent_by_other")?,
-            });
+            messages.push(user_message_from_row(&row)?);
         }
 
         Ok(messages)
@@ -84,7 +219,7 @@ impl MessagesStore {
 
     pub async fn get_last_message_from_all_conversations(
         &self,
-    ) -> Result<Vec<LastMessageAndUnreadCount>, DumbError> {
+    ) -> Result<Vec<LastMessageAndUnreadCount>, FireflyError> {
         let q = r#"WITH stats AS (
                     SELECT
                         um.other,
@@ -101,7 +236,10 @@ impl MessagesStore {
                     s.unread_count,
                     m.id,
                     m.sent_by_other,
-                    m.message
+                    m.message,
+                    m.receipt_state,
+                    m.edited_at,
+                    m.deleted
                 FROM stats AS s
                 JOIN user_messages AS m
                     ON m.other = s.other
@@ -111,12 +249,7 @@ impl MessagesStore {
         let mut messages = Vec::<LastMessageAndUnreadCount>::with_capacity(rows.len());
 
         for row in rows {
-            let message = UserMessage {
-                id: row.try_get("id")?,
-                other: row.try_get("other")?,
-                message: row.try_get("message")?,
-                sent_by_other: row.try_get("sent_by_other")?,
-            };
+            let message = user_message_from_row(&row)?;
 
             let count: i64 = row.try_get("unread_count")?;
             messages.push(LastMessageAndUnreadCount {
@@ -128,20 +261,77 @@ impl MessagesStore {
         Ok(messages)
     }
 
-    pub async fn insert_user_message(&self, row: UserMessage) -> Result<(), DumbError> {
-        sqlx::query("INSERT INTO user_messages (id, other, message, sent_by_other) VALUES (?, ?, ?, ?)")
-            .bind(row.id as i64)
-            .bind(row.other)
-            .bind(row.message)
-            .bind(row.sent_by_other)
-            .execute(&self.pool)
-            .await
-          ?;
+    /// Returns false if a message with the same `other` and `id` was already
+    /// stored. Server messages are replayed when the app stops before the
+    /// cursor advances, so inserts are idempotent.
+    pub async fn insert_user_message(&self, row: UserMessage) -> Result<bool, FireflyError> {
+        let mut tx = self.pool.begin().await?;
+        let inserted = insert_user_message_in(&mut tx, &row).await?;
+        tx.commit().await?;
 
-        Ok(())
+        Ok(inserted)
+    }
+
+    /// Inserts all messages in one transaction, returns whether each of them
+    /// was not stored before.
+    pub async fn insert_user_messages(
+        &self,
+        rows: &[UserMessage],
+    ) -> Result<Vec<bool>, FireflyError> {
+        let mut tx = self.pool.begin().await?;
+        let mut inserted = Vec::with_capacity(rows.len());
+        for row in rows {
+            inserted.push(insert_user_message_in(&mut tx, row).await?);
+        }
+        tx.commit().await?;
+
+        Ok(inserted)
     }
 
-    pub async fn mark_as_read_until(&self, other: &str, id: i64) -> Result<(), DumbError> {
+    /// Full-text search over every direct conversation, most relevant first.
+    pub async fn search(
+        &self,
+        query: &str,
+        limit: u32,
+    ) -> Result<Vec<UserMessageSearchHit>, FireflyError> {
+        let Some(query) = fts_query(query) else {
+            return Ok(vec![]);
+        };
+
+        let rows = sqlx::query(
+            r#"
+            SELECT m.id, m.other, m.message, m.sent_by_other, m.receipt_state, m.edited_at, m.deleted,
+                snippet(user_messages_fts, 0, ?, ?, ?, ?) AS snippet,
+                bm25(user_messages_fts) AS rank
+            FROM user_messages_fts
+            JOIN user_messages AS m ON m.rowid = user_messages_fts.rowid
+            WHERE user_messages_fts MATCH ?
+            ORDER BY rank LIMIT ?
+            "#,
+        )
+        .bind(SNIPPET_MATCH_START.to_string())
+        .bind(SNIPPET_MATCH_END.to_string())
+        .bind(SNIPPET_ELLIPSIS)
+        .bind(SNIPPET_TOKENS)
+        .bind(query)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut hits = Vec::<UserMessageSearchHit>::with_capacity(rows.len());
+
+        for row in rows {
+            hits.push(UserMessageSearchHit {
+                message: user_message_from_row(&row)?,
+                snippet: snippet_html(&row.try_get::<String, _>("snippet")?),
+                rank: row.try_get("rank")?,
+            });
+        }
+
+        Ok(hits)
+    }
+
+    pub async fn mark_as_read_until(&self, other: &str, id: i64) -> Result<(), FireflyError> {
         let q = "INSERT OR REPLACE INTO last_seen_user_timestamps (other, id) VALUES (?, ?)";
         sqlx::query(q)
             .bind(other)
@@ -151,6 +341,435 @@ impl MessagesStore {
 
         Ok(())
     }
+
+    /// Marks received messages of `other` up to `id` as read, returns the ids
+    /// that still need a read receipt.
+    pub async fn take_unread_receipts_until(
+        &self,
+        other: &str,
+        id: i64,
+    ) -> Result<Vec<u64>, FireflyError> {
+        let mut tx = self.pool.begin().await?;
+
+        let ids: Vec<i64> = sqlx::query_scalar(
+            "SELECT id FROM user_messages WHERE other = ? AND sent_by_other = 1 AND id <= ? AND receipt_state < ? ORDER BY id",
+        )
+        .bind(other)
+        .bind(id)
+        .bind(ReceiptState::Read.as_i64())
+        .fetch_all(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            "UPDATE user_messages SET receipt_state = ? WHERE other = ? AND sent_by_other = 1 AND id <= ? AND receipt_state < ?",
+        )
+        .bind(ReceiptState::Read.as_i64())
+        .bind(other)
+        .bind(id)
+        .bind(ReceiptState::Read.as_i64())
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(ids.into_iter().map(|id| id as u64).collect())
+    }
+
+    /// Applies a receipt from `other` to messages we sent them, returns the ids
+    /// whose state moved forward.
+    pub async fn apply_receipt(
+        &self,
+        other: &str,
+        ids: &[u64],
+        state: ReceiptState,
+    ) -> Result<Vec<u64>, FireflyError> {
+        let mut tx = self.pool.begin().await?;
+        let mut updated = Vec::with_capacity(ids.len());
+
+        for id in ids {
+            let result = sqlx::query(
+                "UPDATE user_messages SET receipt_state = ? WHERE other = ? AND id = ? AND sent_by_other = 0 AND receipt_state < ?",
+            )
+            .bind(state.as_i64())
+            .bind(other)
+            .bind(*id as i64)
+            .bind(state.as_i64())
+            .execute(&mut *tx)
+            .await?;
+
+            if result.rows_affected() > 0 {
+                updated.push(*id);
+            }
+        }
+
+        tx.commit().await?;
+
+        Ok(updated)
+    }
+
+    /// Replaces message `id` of `other` with `message`, keeping the replaced
+    /// version in the edit history. Only the side that sent the message
+    /// (`sent_by_other`) may edit it. Returns the updated message, `None` if
+    /// there was nothing to edit or a newer edit was already applied.
+    pub async fn edit_user_message(
+        &self,
+        other: &str,
+        id: u64,
+        sent_by_other: bool,
+        edited_at: u64,
+        message: &[u8],
+    ) -> Result<Option<UserMessage>, FireflyError> {
+        let mut tx = self.pool.begin().await?;
+
+        let Some((rowid, mut current)) =
+            select_user_message_in(&mut tx, other, id, sent_by_other).await?
+        else {
+            return Ok(None);
+        };
+        if current.deleted || current.edited_at >= edited_at {
+            return Ok(None);
+        }
+
+        sqlx::query(
+            "INSERT INTO user_message_edits (other, id, edited_at, message) VALUES (?, ?, ?, ?)",
+        )
+        .bind(other)
+        .bind(id as i64)
+        .bind(current.edited_at as i64)
+        .bind(&current.message)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query("UPDATE user_messages SET message = ?, edited_at = ? WHERE rowid = ?")
+            .bind(message)
+            .bind(edited_at as i64)
+            .bind(rowid)
+            .execute(&mut *tx)
+            .await?;
+
+        reindex_user_message_in(&mut tx, rowid, user_message_text(message)).await?;
+
+        tx.commit().await?;
+
+        current.message = message.to_vec();
+        current.edited_at = edited_at;
+        Ok(Some(current))
+    }
+
+    /// Tombstones message `id` of `other`, dropping its content and edit
+    /// history. Only the side that sent the message may delete it. Returns the
+    /// tombstone, `None` if there was nothing to delete.
+    pub async fn delete_user_message(
+        &self,
+        other: &str,
+        id: u64,
+        sent_by_other: bool,
+    ) -> Result<Option<UserMessage>, FireflyError> {
+        let mut tx = self.pool.begin().await?;
+
+        let Some((rowid, mut current)) =
+            select_user_message_in(&mut tx, other, id, sent_by_other).await?
+        else {
+            return Ok(None);
+        };
+        if current.deleted {
+            return Ok(None);
+        }
+
+        sqlx::query("DELETE FROM user_message_edits WHERE other = ? AND id = ?")
+            .bind(other)
+            .bind(id as i64)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM user_message_reactions WHERE other = ? AND message_id = ?")
+            .bind(other)
+            .bind(id as i64)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query("UPDATE user_messages SET message = ?, deleted = 1 WHERE rowid = ?")
+            .bind(Vec::<u8>::new())
+            .bind(rowid)
+            .execute(&mut *tx)
+            .await?;
+
+        reindex_user_message_in(&mut tx, rowid, None).await?;
+
+        tx.commit().await?;
+
+        current.message.clear();
+        current.deleted = true;
+        Ok(Some(current))
+    }
+
+    /// Sets the reaction of `reactor` on message `message_id` of `other`, an
+    /// empty `emoji` removes it. Every reactor has at most one reaction per
+    /// message, changes older than the stored reaction are ignored. Returns
+    /// whether anything changed.
+    pub async fn set_user_message_reaction(
+        &self,
+        other: &str,
+        message_id: u64,
+        reactor: &str,
+        emoji: &str,
+        reacted_at: u64,
+    ) -> Result<bool, FireflyError> {
+        let result = if emoji.is_empty() {
+            sqlx::query(
+                "DELETE FROM user_message_reactions WHERE other = ? AND message_id = ? AND reactor = ? AND reacted_at < ?",
+            )
+            .bind(other)
+            .bind(message_id as i64)
+            .bind(reactor)
+            .bind(reacted_at as i64)
+            .execute(&self.pool)
+            .await?
+        } else {
+            sqlx::query(
+                r#"
+                INSERT INTO user_message_reactions (other, message_id, reactor, emoji, reacted_at)
+                VALUES (?, ?, ?, ?, ?)
+                ON CONFLICT (other, message_id, reactor) DO UPDATE
+                SET emoji = excluded.emoji, reacted_at = excluded.reacted_at
+                WHERE excluded.reacted_at > user_message_reactions.reacted_at
+                "#,
+            )
+            .bind(other)
+            .bind(message_id as i64)
+            .bind(reactor)
+            .bind(emoji)
+            .bind(reacted_at as i64)
+            .execute(&self.pool)
+            .await?
+        };
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Reaction counts of the given messages of `other`, grouped by message
+    /// and emoji.
+    pub async fn get_user_message_reactions(
+        &self,
+        other: &str,
+        message_ids: &[u64],
+    ) -> Result<Vec<ReactionCount>, FireflyError> {
+        if message_ids.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let q = format!(
+            "SELECT message_id, emoji, reactor FROM user_message_reactions WHERE other = ? AND message_id IN ({}) ORDER BY message_id, emoji, reacted_at",
+            placeholders(message_ids.len())
+        );
+        let mut query = sqlx::query(&q).bind(other);
+        for message_id in message_ids {
+            query = query.bind(*message_id as i64);
+        }
+        let rows = query.fetch_all(&self.pool).await?;
+
+        let mut reactions = Vec::with_capacity(rows.len());
+        for row in rows {
+            let message_id: i64 = row.try_get("message_id")?;
+            reactions.push((
+                message_id as u64,
+                row.try_get("emoji")?,
+                row.try_get("reactor")?,
+            ));
+        }
+
+        Ok(aggregate_reactions(reactions))
+    }
+
+    /// Deletes the messages of `other` with ids in `since..before` along with
+    /// their edits and reactions, for disappearing messages. Returns the ids
+    /// of the deleted messages.
+    pub async fn delete_expired_user_messages(
+        &self,
+        other: &str,
+        since: u64,
+        before: u64,
+    ) -> Result<Vec<u64>, FireflyError> {
+        let mut tx = self.pool.begin().await?;
+
+        let rows = sqlx::query(
+            "SELECT rowid, id FROM user_messages WHERE other = ? AND id >= ? AND id < ?",
+        )
+        .bind(other)
+        .bind(since as i64)
+        .bind(before as i64)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let mut ids = Vec::with_capacity(rows.len());
+        for row in rows {
+            let rowid: i64 = row.try_get(0)?;
+            let id: i64 = row.try_get(1)?;
+
+            sqlx::query("DELETE FROM user_messages_fts WHERE rowid = ?")
+                .bind(rowid)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query("DELETE FROM user_message_edits WHERE other = ? AND id = ?")
+                .bind(other)
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query("DELETE FROM user_message_reactions WHERE other = ? AND message_id = ?")
+                .bind(other)
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query("DELETE FROM user_messages WHERE rowid = ?")
+                .bind(rowid)
+                .execute(&mut *tx)
+                .await?;
+
+            ids.push(id as u64);
+        }
+
+        tx.commit().await?;
+
+        Ok(ids)
+    }
+
+    /// Closes `user_messages.db`, the store can't be used afterwards.
+    pub async fn close(&self) {
+        self.pool.close().await;
+    }
+
+    /// Deletes the whole direct message history, for a logout.
+    pub async fn delete_all(&self) -> Result<(), FireflyError> {
+        let mut tx = self.pool.begin().await?;
+        for table in [
+            "user_messages_fts",
+            "user_messages",
+            "user_message_edits",
+            "user_message_reactions",
+            "last_seen_user_timestamps",
+        ] {
+            sqlx::query(&format!("DELETE FROM {}", table))
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Replaced versions of message `id` of `other`, oldest first.
+    pub async fn get_user_message_edits(
+        &self,
+        other: &str,
+        id: u64,
+    ) -> Result<Vec<MessageEdit>, FireflyError> {
+        let rows = sqlx::query(
+            "SELECT edited_at, message FROM user_message_edits WHERE other = ? AND id = ? ORDER BY edited_at",
+        )
+        .bind(other)
+        .bind(id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut edits = Vec::with_capacity(rows.len());
+        for row in rows {
+            let edited_at: i64 = row.try_get("edited_at")?;
+            edits.push(MessageEdit {
+                edited_at: edited_at as u64,
+                message: row.try_get("message")?,
+            });
+        }
+
+        Ok(edits)
+    }
+}
+
+async fn select_user_message_in(
+    conn: &mut SqliteConnection,
+    other: &str,
+    id: u64,
+    sent_by_other: bool,
+) -> Result<Option<(i64, UserMessage)>, FireflyError> {
+    let row = sqlx::query(
+        "SELECT rowid, other, message, sent_by_other, id, receipt_state, edited_at, deleted FROM user_messages WHERE other = ? AND id = ? AND sent_by_other = ?",
+    )
+    .bind(other)
+    .bind(id as i64)
+    .bind(sent_by_other)
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    let Some(row) = row else {
+        return Ok(None);
+    };
+
+    Ok(Some((row.try_get("rowid")?, user_message_from_row(&row)?)))
+}
+
+fn user_message_from_row(row: &sqlx::sqlite::SqliteRow) -> Result<UserMessage, FireflyError> {
+    let state: i64 = row.try_get("receipt_state")?;
+    let edited_at: i64 = row.try_get("edited_at")?;
+
+    Ok(UserMessage {
+        id: row.try_get("id")?,
+        other: row.try_get("other")?,
+        message: row.try_get("message")?,
+        sent_by_other: row.try_get("sent_by_other")?,
+        receipt: ReceiptState::from_i64(state).map_err(FireflyError::from_anyhow)?,
+        edited_at: edited_at as u64,
+        deleted: row.try_get("deleted")?,
+    })
+}
+
+/// Replaces the searchable text of a message, `None` only removes it.
+async fn reindex_user_message_in(
+    conn: &mut SqliteConnection,
+    rowid: i64,
+    text: Option<String>,
+) -> Result<(), FireflyError> {
+    sqlx::query("DELETE FROM user_messages_fts WHERE rowid = ?")
+        .bind(rowid)
+        .execute(&mut *conn)
+        .await?;
+
+    if let Some(text) = text {
+        sqlx::query("INSERT INTO user_messages_fts (rowid, text) VALUES (?, ?)")
+            .bind(rowid)
+            .bind(text)
+            .execute(&mut *conn)
+            .await?;
+    }
+
+    Ok(())
+}
+
+async fn insert_user_message_in(
+    conn: &mut SqliteConnection,
+    row: &UserMessage,
+) -> Result<bool, FireflyError> {
+    let result = sqlx::query(
+        "INSERT OR IGNORE INTO user_messages (id, other, message, sent_by_other, receipt_state) VALUES (?, ?, ?, ?, ?)",
+    )
+    .bind(row.id as i64)
+    .bind(&row.other)
+    .bind(&row.message)
+    .bind(row.sent_by_other)
+    .bind(row.receipt.as_i64())
+    .execute(&mut *conn)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+
+    if let Some(text) = user_message_text(&row.message) {
+        sqlx::query("INSERT INTO user_messages_fts (rowid, text) VALUES (?, ?)")
+            .bind(result.last_insert_rowid())
+            .bind(text)
+            .execute(&mut *conn)
+            .await?;
+    }
+
+    Ok(true)
 }
 
 #[cfg(test)]
@@ -174,14 +793,10 @@ mod tests {
         let pool = setup_pool(DB_URI, 1).await.unwrap();
         let store = MessagesStore::new(pool).await.unwrap();
 
-        let msg = UserMessage {
-            id: 0,
-            other: "alice".to_string(),
-            message: vec![1, 2, 3],
-            sent_by_other: false,
-        };
-
-        store.insert_user_message(msg).await.unwrap();
+        store
+            .insert_user_message(UserMessage::new(0, "alice".into(), vec![1, 2, 3], false))
+            .await
+            .unwrap();
 
         let messages = store
             .get_last_messages_of("alice", i64::MAX, 10)
@@ -198,22 +813,12 @@ mod tests {
         let store = MessagesStore::new(pool).await.unwrap();
 
         store
-            .insert_user_message(UserMessage {
-                id: 0,
-                other: "alice".to_string(),
-                message: vec![1],
-                sent_by_other: true,
-            })
+            .insert_user_message(UserMessage::new(0, "alice".into(), vec![1], true))
             .await
             .unwrap();
 
         store
-            .insert_user_message(UserMessage {
-                id: 0,
-                other: "bob".to_string(),
-                message: vec![2],
-                sent_by_other: false,
-            })
+            .insert_user_message(UserMessage::new(0, "bob".into(), vec![2], false))
             .await
             .unwrap();
 
@@ -230,16 +835,348 @@ mod tests {
         let store = MessagesStore::new(pool).await.unwrap();
 
         store
-            .insert_user_message(UserMessage {
-                id: 0,
-                other: "alice".to_string(),
-                message: vec![1],
-                sent_by_other: true,
-            })
+            .insert_user_message(UserMessage::new(0, "alice".into(), vec![1], true))
             .await
             .unwrap();
 
         let result = store.mark_as_read_until("alice", 1).await;
         assert!(result.is_ok());
     }
+
+    fn text_message(text: &str) -> Vec<u8> {
+        use crate::pb::firefly::firefly::{UserMessageInner, user_message_inner};
+
+        crate::utils::serialize_proto(&UserMessageInner {
+            message: Some(user_message_inner::Message::PlainText(
+                text.as_bytes().to_vec(),
+            )),
+            ..Default::default()
+        })
+        .unwrap()
+        .to_vec()
+    }
+
+    #[tokio::test]
+    async fn test_search() {
+        let pool = setup_pool(DB_URI, 1).await.unwrap();
+        let store = MessagesStore::new(pool).await.unwrap();
+
+        for (id, other, text) in [
+            (1, "alice", "see you at the station"),
+            (2, "bob", "the train is late again"),
+            (3, "bob", "station, train, station"),
+        ] {
+            store
+                .insert_user_message(UserMessage::new(id, other.into(), text_message(text), true))
+                .await
+                .unwrap();
+        }
+
+        // not indexed, but must still be stored
+        store
+            .insert_user_message(UserMessage::new(4, "bob".into(), vec![0xff], true))
+            .await
+            .unwrap();
+
+        let hits = store.search("stat", 10).await.unwrap();
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[0].message.id, 3);
+        assert!(hits[0].snippet.contains("<mark>station</mark>"));
+
+        let hits = store.search("train late", 10).await.unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].message.other, "bob");
+
+        assert!(store.search("  ", 10).await.unwrap().is_empty());
+        assert!(store.search("\"unbalanced", 10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_insert_is_idempotent() {
+        let pool = setup_pool(DB_URI, 1).await.unwrap();
+        let store = MessagesStore::new(pool).await.unwrap();
+
+        let message = |id: u64, other: &str| {
+            UserMessage::new(id, other.into(), text_message("replayed"), true)
+        };
+
+        assert!(
+            store
+                .insert_user_message(message(1, "alice"))
+                .await
+                .unwrap()
+        );
+        assert!(
+            !store
+                .insert_user_message(message(1, "alice"))
+                .await
+                .unwrap()
+        );
+
+        let inserted = store
+            .insert_user_messages(&[message(1, "alice"), message(2, "alice"), message(1, "bob")])
+            .await
+            .unwrap();
+        assert_eq!(inserted, vec![false, true, true]);
+
+        let messages = store
+            .get_last_messages_of("alice", i64::MAX, 10)
+            .await
+            .unwrap();
+        assert_eq!(messages.len(), 2);
+
+        // duplicates must not be indexed twice either
+        assert_eq!(store.search("replayed", 10).await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_receipts() {
+        let pool = setup_pool(DB_URI, 1).await.unwrap();
+        let store = MessagesStore::new(pool).await.unwrap();
+
+        store
+            .insert_user_messages(&[
+                UserMessage::new(1, "alice".into(), vec![1], false),
+                UserMessage::new(2, "alice".into(), vec![1], false),
+                UserMessage::new(3, "alice".into(), vec![1], true),
+                UserMessage::new(4, "alice".into(), vec![1], true),
+            ])
+            .await
+            .unwrap();
+
+        // receipts only apply to messages we sent
+        let updated = store
+            .apply_receipt("alice", &[1, 2, 3], ReceiptState::Delivered)
+            .await
+            .unwrap();
+        assert_eq!(updated, vec![1, 2]);
+
+        let updated = store
+            .apply_receipt("alice", &[1], ReceiptState::Read)
+            .await
+            .unwrap();
+        assert_eq!(updated, vec![1]);
+
+        // a late delivered receipt doesn't move a read message back
+        let updated = store
+            .apply_receipt("alice", &[1], ReceiptState::Delivered)
+            .await
+            .unwrap();
+        assert!(updated.is_empty());
+
+        let messages = store
+            .get_last_messages_of("alice", i64::MAX, 10)
+            .await
+            .unwrap();
+        assert_eq!(messages[3].receipt, ReceiptState::Read);
+        assert_eq!(messages[2].receipt, ReceiptState::Delivered);
+
+        let ids = store.take_unread_receipts_until("alice", 3).await.unwrap();
+        assert_eq!(ids, vec![3]);
+        let ids = store.take_unread_receipts_until("alice", 4).await.unwrap();
+        assert_eq!(ids, vec![4]);
+        assert!(
+            store
+                .take_unread_receipts_until("alice", 4)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_edit_and_delete() {
+        let pool = setup_pool(DB_URI, 1).await.unwrap();
+        let store = MessagesStore::new(pool).await.unwrap();
+
+        store
+            .insert_user_message(UserMessage::new(
+                1,
+                "alice".into(),
+                text_message("helo"),
+                true,
+            ))
+            .await
+            .unwrap();
+
+        // only alice may edit what alice sent
+        assert!(
+            store
+                .edit_user_message("alice", 1, false, 2, &text_message("hijacked"))
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        let edited = store
+            .edit_user_message("alice", 1, true, 3, &text_message("hello"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(edited.edited_at, 3);
+        assert_eq!(edited.message, text_message("hello"));
+
+        // a replayed or older edit doesn't overwrite a newer one
+        assert!(
+            store
+                .edit_user_message("alice", 1, true, 2, &text_message("stale"))
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        let edits = store.get_user_message_edits("alice", 1).await.unwrap();
+        assert_eq!(edits.len(), 1);
+        assert_eq!(edits[0].edited_at, 0);
+        assert_eq!(edits[0].message, text_message("helo"));
+
+        assert_eq!(store.search("hello", 10).await.unwrap().len(), 1);
+        assert!(store.search("helo", 10).await.unwrap().is_empty());
+
+        let deleted = store
+            .delete_user_message("alice", 1, true)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(deleted.deleted);
+        assert!(deleted.message.is_empty());
+        assert!(
+            store
+                .delete_user_message("alice", 1, true)
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        let messages = store
+            .get_last_messages_of("alice", i64::MAX, 10)
+            .await
+            .unwrap();
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].deleted);
+        assert!(
+            store
+                .get_user_message_edits("alice", 1)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        assert!(store.search("hello", 10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_delete_expired() {
+        let pool = setup_pool(DB_URI, 1).await.unwrap();
+        let store = MessagesStore::new(pool).await.unwrap();
+
+        store
+            .insert_user_messages(&[
+                UserMessage::new(1, "alice".into(), vec![1], true),
+                UserMessage::new(2, "alice".into(), vec![2], true),
+                UserMessage::new(3, "alice".into(), vec![3], true),
+                UserMessage::new(2, "bob".into(), vec![2], true),
+            ])
+            .await
+            .unwrap();
+        store
+            .set_user_message_reaction("alice", 2, "alice", "👍", 10)
+            .await
+            .unwrap();
+
+        let deleted = store
+            .delete_expired_user_messages("alice", 2, 3)
+            .await
+            .unwrap();
+        assert_eq!(deleted, vec![2]);
+
+        let ids = store
+            .get_last_messages_of("alice", i64::MAX, 10)
+            .await
+            .unwrap()
+            .iter()
+            .map(|message| message.id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![3, 1]);
+        assert!(
+            store
+                .get_user_message_reactions("alice", &[2])
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(
+            store
+                .get_last_messages_of("bob", i64::MAX, 10)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reactions() {
+        let pool = setup_pool(DB_URI, 1).await.unwrap();
+        let store = MessagesStore::new(pool).await.unwrap();
+
+        assert!(
+            store
+                .set_user_message_reaction("alice", 1, "alice", "👍", 10)
+                .await
+                .unwrap()
+        );
+        assert!(
+            store
+                .set_user_message_reaction("alice", 1, "me", "👍", 11)
+                .await
+                .unwrap()
+        );
+        assert!(
+            store
+                .set_user_message_reaction("alice", 2, "alice", "😂", 12)
+                .await
+                .unwrap()
+        );
+
+        // one reaction per reactor, an older change doesn't win
+        assert!(
+            !store
+                .set_user_message_reaction("alice", 1, "alice", "😂", 9)
+                .await
+                .unwrap()
+        );
+
+        let reactions = store
+            .get_user_message_reactions("alice", &[1, 2, 3])
+            .await
+            .unwrap();
+        assert_eq!(reactions.len(), 2);
+        assert_eq!(reactions[0].message_id, 1);
+        assert_eq!(reactions[0].emoji, "👍");
+        assert_eq!(reactions[0].count, 2);
+        assert_eq!(reactions[0].reactors, vec!["alice", "me"]);
+        assert_eq!(reactions[1].message_id, 2);
+        assert_eq!(reactions[1].count, 1);
+
+        assert!(
+            store
+                .set_user_message_reaction("alice", 1, "me", "", 13)
+                .await
+                .unwrap()
+        );
+        let reactions = store
+            .get_user_message_reactions("alice", &[1])
+            .await
+            .unwrap();
+        assert_eq!(reactions.len(), 1);
+        assert_eq!(reactions[0].reactors, vec!["alice"]);
+
+        assert!(
+            store
+                .get_user_message_reactions("bob", &[1, 2])
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
 }
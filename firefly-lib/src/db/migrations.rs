@@ -0,0 +1,650 @@
+use sqlx::{SqlitePool, prelude::*};
+
+/// A single schema change. Versions start at 1 and must be strictly increasing
+/// within a list, the applied version is kept in `PRAGMA user_version`.
+///
+/// Version 1 of every list is the schema that existed before versioning was
+/// introduced, so it uses `IF NOT EXISTS` to adopt databases of older installs
+/// that are still at version 0.
+pub struct Migration {
+    pub version: u32,
+    pub description: &'static str,
+    pub sql: &'static str,
+}
+
+/// Schema of `firefly.db`, shared by the signal, mls and client stores.
+pub const FIREFLY_DB_MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "initial schema",
+        sql: r#"
+CREATE TABLE IF NOT EXISTS key_value_store (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS addresses (id INTEGER, username TEXT, device_id INTEGER);
+CREATE INDEX IF NOT EXISTS addresses_by_username ON addresses (username);
+CREATE INDEX IF NOT EXISTS addresses_by_id ON addresses (id);
+
+CREATE TABLE IF NOT EXISTS conversations (
+    username TEXT NOT NULL PRIMARY KEY,
+    settings INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS pre_keys (
+    id INTEGER PRIMARY KEY,
+    record BLOB NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS signed_pre_keys (
+    id INTEGER PRIMARY KEY,
+    record BLOB NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS kyber_pre_keys (
+    id INTEGER PRIMARY KEY,
+    record BLOB NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS sessions (
+    address TEXT PRIMARY KEY,
+    record BLOB NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS identities (
+    address TEXT PRIMARY KEY,
+    identity_key BLOB NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS identity_keypair (
+    id INTEGER NOT NULL,
+    keypair BLOB NOT NULL,
+    registration_id INTEGER NOT NULL,
+    device_id INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS sender_keys (
+    sender_id TEXT NOT NULL,
+    distribution_id TEXT NOT NULL,
+    record BLOB NOT NULL,
+    PRIMARY KEY (sender_id, distribution_id)
+);
+
+CREATE TABLE IF NOT EXISTS group_states (
+    id BLOB PRIMARY KEY,
+    state BLOB NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS group_epoch_states (
+    id BLOB NOT NULL,
+    epoch INTEGER NOT NULL,
+    state BLOB NOT NULL,
+    PRIMARY KEY (id, epoch),
+    FOREIGN KEY (id) REFERENCES group_states(id) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS self_group_key_packages (
+    id INTEGER PRIMARY KEY NOT NULL,
+    key_package BLOB NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS group_key_packages (
+    id BLOB PRIMARY KEY NOT NULL,
+    key_package BLOB NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS group_infos (
+    id INTEGER PRIMARY KEY NOT NULL,
+    group_state_id BLOB NOT NULL,
+    name TEXT NOT NULL,
+    description TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS group_messages (
+    id INTEGER NOT NULL,
+    group_id INTEGER NOT NULL,
+    by TEXT NOT NULL,
+    message BLOB NOT NULL,
+    channel_id INTEGER NOT NULL,
+    epoch INTEGER NOT NULL DEFAULT 0,
+
+    PRIMARY KEY (group_id, id)
+);
+"#,
+    },
+    Migration {
+        version: 2,
+        description: "direct message outbox",
+        sql: r#"
+CREATE TABLE IF NOT EXISTS user_message_outbox (
+    id INTEGER NOT NULL PRIMARY KEY,
+    other TEXT NOT NULL,
+    message BLOB NOT NULL,
+    status INTEGER NOT NULL DEFAULT 0,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT
+);
+
+CREATE INDEX IF NOT EXISTS user_message_outbox_status_idx ON user_message_outbox (status, next_attempt_at);
+"#,
+    },
+    Migration {
+        version: 3,
+        description: "group messages full-text index",
+        sql: r#"
+CREATE VIRTUAL TABLE IF NOT EXISTS group_messages_fts USING fts5(text, tokenize = 'unicode61 remove_diacritics 2');
+
+CREATE TABLE IF NOT EXISTS pending_index_rebuilds (name TEXT NOT NULL PRIMARY KEY);
+INSERT OR IGNORE INTO pending_index_rebuilds (name) VALUES ('group_messages_fts');
+"#,
+    },
+    Migration {
+        version: 4,
+        description: "identity trust states",
+        sql: r#"
+ALTER TABLE identities ADD COLUMN trust_state INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE identities ADD COLUMN change_pending_notification BOOLEAN NOT NULL DEFAULT 0;
+"#,
+    },
+    Migration {
+        version: 5,
+        description: "group message edits and deletes",
+        sql: r#"
+ALTER TABLE group_messages ADD COLUMN edited_at INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE group_messages ADD COLUMN deleted BOOLEAN NOT NULL DEFAULT 0;
+
+CREATE TABLE IF NOT EXISTS group_message_edits (
+    group_id INTEGER NOT NULL,
+    id INTEGER NOT NULL,
+    edited_at INTEGER NOT NULL,
+    message BLOB NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS group_message_edits_idx ON group_message_edits (group_id, id);
+"#,
+    },
+    Migration {
+        version: 6,
+        description: "group message reactions",
+        sql: r#"
+CREATE TABLE IF NOT EXISTS group_message_reactions (
+    group_id INTEGER NOT NULL,
+    message_id INTEGER NOT NULL,
+    reactor TEXT NOT NULL,
+    emoji TEXT NOT NULL,
+    reacted_at INTEGER NOT NULL,
+
+    PRIMARY KEY (group_id, message_id, reactor)
+);
+"#,
+    },
+    Migration {
+        version: 7,
+        description: "disappearing message timers",
+        sql: r#"
+ALTER TABLE conversations ADD COLUMN disappearing_since INTEGER NOT NULL DEFAULT 0;
+
+CREATE TABLE IF NOT EXISTS group_settings (
+    group_id INTEGER NOT NULL PRIMARY KEY,
+    settings INTEGER NOT NULL,
+    disappearing_since INTEGER NOT NULL
+);
+"#,
+    },
+    Migration {
+        version: 8,
+        description: "typed conversation settings",
+        sql: r#"
+ALTER TABLE conversations ADD COLUMN muted_until INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE conversations ADD COLUMN archived BOOLEAN NOT NULL DEFAULT 0;
+ALTER TABLE conversations ADD COLUMN pinned BOOLEAN NOT NULL DEFAULT 0;
+ALTER TABLE conversations ADD COLUMN blocked BOOLEAN NOT NULL DEFAULT 0;
+ALTER TABLE conversations ADD COLUMN notification_level INTEGER NOT NULL DEFAULT 0;
+
+ALTER TABLE group_settings ADD COLUMN muted_until INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE group_settings ADD COLUMN archived BOOLEAN NOT NULL DEFAULT 0;
+ALTER TABLE group_settings ADD COLUMN pinned BOOLEAN NOT NULL DEFAULT 0;
+ALTER TABLE group_settings ADD COLUMN blocked BOOLEAN NOT NULL DEFAULT 0;
+ALTER TABLE group_settings ADD COLUMN notification_level INTEGER NOT NULL DEFAULT 0;
+"#,
+    },
+    Migration {
+        version: 9,
+        description: "outbox deliveries per address",
+        sql: r#"
+CREATE TABLE IF NOT EXISTS user_message_outbox_deliveries (
+    id INTEGER NOT NULL,
+    address_id INTEGER NOT NULL,
+
+    PRIMARY KEY (id, address_id)
+);
+"#,
+    },
+    Migration {
+        version: 10,
+        description: "inbox of decrypted direct messages",
+        sql: r#"
+CREATE TABLE IF NOT EXISTS user_message_inbox (
+    address TEXT NOT NULL,
+    id INTEGER NOT NULL,
+    message BLOB NOT NULL,
+
+    PRIMARY KEY (address, id)
+);
+"#,
+    },
+    Migration {
+        version: 11,
+        description: "stable group message rowids",
+        // the full-text index refers to messages by rowid, which VACUUM may
+        // renumber unless it is an INTEGER PRIMARY KEY, existing ones are kept
+        sql: r#"
+CREATE TABLE group_messages_new (
+    row_id INTEGER PRIMARY KEY,
+    id INTEGER NOT NULL,
+    group_id INTEGER NOT NULL,
+    by TEXT NOT NULL,
+    message BLOB NOT NULL,
+    channel_id INTEGER NOT NULL,
+    epoch INTEGER NOT NULL DEFAULT 0,
+    edited_at INTEGER NOT NULL DEFAULT 0,
+    deleted BOOLEAN NOT NULL DEFAULT 0,
+
+    UNIQUE (group_id, id)
+);
+
+INSERT INTO group_messages_new (row_id, id, group_id, by, message, channel_id, epoch, edited_at, deleted)
+SELECT rowid, id, group_id, by, message, channel_id, epoch, edited_at, deleted FROM group_messages;
+
+DROP TABLE group_messages;
+ALTER TABLE group_messages_new RENAME TO group_messages;
+"#,
+    },
+    Migration {
+        version: 12,
+        description: "group message cursors",
+        // the cursor was kept as empty placeholder messages, which showed up
+        // as the last message of a group
+        sql: r#"
+CREATE TABLE IF NOT EXISTS group_message_cursors (
+    group_id INTEGER NOT NULL PRIMARY KEY,
+    id INTEGER NOT NULL,
+    epoch INTEGER NOT NULL
+);
+
+INSERT INTO group_message_cursors (group_id, id, epoch)
+SELECT gm.group_id, gm.id, gm.epoch FROM group_messages gm
+WHERE gm.by = '' AND length(gm.message) = 0 AND gm.deleted = 0
+AND gm.id = (
+    SELECT MAX(id) FROM group_messages
+    WHERE group_id = gm.group_id AND by = '' AND length(message) = 0 AND deleted = 0
+);
+
+DELETE FROM group_messages WHERE by = '' AND length(message) = 0 AND deleted = 0;
+"#,
+    },
+];
+
+/// Schema of `user_messages.db`, the decrypted direct message history.
+pub const USER_MESSAGES_DB_MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "initial schema",
+        sql: r#"
+CREATE TABLE IF NOT EXISTS user_messages (
+    id INTEGER NOT NULL,
+    other TEXT NOT NULL,
+    sent_by_other BOOLEAN NOT NULL,
+    message BLOB NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS user_messages_other_idx ON user_messages (other, id);
+
+CREATE TABLE IF NOT EXISTS last_seen_user_timestamps (
+    other TEXT NOT NULL PRIMARY KEY,
+    id INTEGER NOT NULL
+);
+"#,
+    },
+    Migration {
+        version: 2,
+        description: "user messages full-text index",
+        sql: r#"
+CREATE VIRTUAL TABLE IF NOT EXISTS user_messages_fts USING fts5(text, tokenize = 'unicode61 remove_diacritics 2');
+
+CREATE TABLE IF NOT EXISTS pending_index_rebuilds (name TEXT NOT NULL PRIMARY KEY);
+INSERT OR IGNORE INTO pending_index_rebuilds (name) VALUES ('user_messages_fts');
+"#,
+    },
+    Migration {
+        version: 3,
+        description: "unique user messages",
+        sql: r#"
+DELETE FROM user_messages_fts WHERE rowid IN (
+    SELECT rowid FROM user_messages
+    WHERE rowid NOT IN (SELECT MIN(rowid) FROM user_messages GROUP BY other, id)
+);
+
+DELETE FROM user_messages
+WHERE rowid NOT IN (SELECT MIN(rowid) FROM user_messages GROUP BY other, id);
+
+CREATE UNIQUE INDEX IF NOT EXISTS user_messages_other_id_unique ON user_messages (other, id);
+"#,
+    },
+    Migration {
+        version: 4,
+        description: "message receipts",
+        sql: r#"
+ALTER TABLE user_messages ADD COLUMN receipt_state INTEGER NOT NULL DEFAULT 0;
+
+-- history from before receipts existed counts as read, so it is not receipted
+UPDATE user_messages SET receipt_state = 2 WHERE sent_by_other = 1;
+"#,
+    },
+    Migration {
+        version: 5,
+        description: "user message edits and deletes",
+        sql: r#"
+ALTER TABLE user_messages ADD COLUMN edited_at INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE user_messages ADD COLUMN deleted BOOLEAN NOT NULL DEFAULT 0;
+
+CREATE TABLE IF NOT EXISTS user_message_edits (
+    other TEXT NOT NULL,
+    id INTEGER NOT NULL,
+    edited_at INTEGER NOT NULL,
+    message BLOB NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS user_message_edits_idx ON user_message_edits (other, id);
+"#,
+    },
+    Migration {
+        version: 6,
+        description: "user message reactions",
+        sql: r#"
+CREATE TABLE IF NOT EXISTS user_message_reactions (
+    other TEXT NOT NULL,
+    message_id INTEGER NOT NULL,
+    reactor TEXT NOT NULL,
+    emoji TEXT NOT NULL,
+    reacted_at INTEGER NOT NULL,
+
+    PRIMARY KEY (other, message_id, reactor)
+);
+"#,
+    },
+    Migration {
+        version: 7,
+        description: "stable user message rowids",
+        // the full-text index refers to messages by rowid, which VACUUM may
+        // renumber unless it is an INTEGER PRIMARY KEY, existing ones are kept
+        sql: r#"
+CREATE TABLE user_messages_new (
+    row_id INTEGER PRIMARY KEY,
+    id INTEGER NOT NULL,
+    other TEXT NOT NULL,
+    sent_by_other BOOLEAN NOT NULL,
+    message BLOB NOT NULL,
+    receipt_state INTEGER NOT NULL DEFAULT 0,
+    edited_at INTEGER NOT NULL DEFAULT 0,
+    deleted BOOLEAN NOT NULL DEFAULT 0
+);
+
+INSERT INTO user_messages_new (row_id, id, other, sent_by_other, message, receipt_state, edited_at, deleted)
+SELECT rowid, id, other, sent_by_other, message, receipt_state, edited_at, deleted FROM user_messages;
+
+DROP TABLE user_messages;
+ALTER TABLE user_messages_new RENAME TO user_messages;
+
+CREATE INDEX user_messages_other_idx ON user_messages (other, id);
+CREATE UNIQUE INDEX user_messages_other_id_unique ON user_messages (other, id);
+"#,
+    },
+];
+
+/// Schema of the attachment cache database.
+pub const ATTACHMENT_CACHE_DB_MIGRATIONS: &[Migration] = &[Migration {
+    version: 1,
+    description: "attachment cache",
+    sql: r#"
+CREATE TABLE IF NOT EXISTS attachment_cache (
+    file_name TEXT PRIMARY KEY,
+    other TEXT NOT NULL,
+    group_id INTEGER NOT NULL,
+    message_id INTEGER NOT NULL,
+    size INTEGER NOT NULL,
+    last_access INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS attachment_cache_message_idx ON attachment_cache (other, group_id, message_id);
+CREATE INDEX IF NOT EXISTS attachment_cache_access_idx ON attachment_cache (last_access);
+
+CREATE TABLE IF NOT EXISTS attachment_cache_quota (
+    id INTEGER PRIMARY KEY CHECK (id = 0),
+    quota INTEGER NOT NULL
+);
+"#,
+}];
+
+pub async fn get_schema_version(pool: &SqlitePool) -> anyhow::Result<u32> {
+    let version: i64 = sqlx::query_scalar("PRAGMA user_version")
+        .fetch_one(pool)
+        .await?;
+    Ok(version as u32)
+}
+
+pub async fn table_exists(pool: &SqlitePool, name: &str) -> anyhow::Result<bool> {
+    let row = sqlx::query("SELECT name FROM sqlite_master WHERE type = 'table' AND name = ?")
+        .bind(name)
+        .fetch_optional(pool)
+        .await?;
+    Ok(row.is_some())
+}
+
+/// Brings the database up to the last version of `migrations`, one transaction
+/// per migration. Safe to call from every store sharing the pool, it is a
+/// single pragma read once the schema is current. Fails without touching the
+/// database if it was written by a newer version of the app.
+pub async fn migrate(pool: &SqlitePool, migrations: &[Migration]) -> anyhow::Result<()> {
+    let latest = migrations.last().map(|m| m.version).unwrap_or(0);
+
+    let current = get_schema_version(pool).await?;
+    if current > latest {
+        return Err(anyhow::anyhow!(
+            "database schema version {} is newer than the supported version {}",
+            current,
+            latest
+        ));
+    }
+    if current == latest {
+        return Ok(());
+    }
+
+    let mut conn = pool.acquire().await?;
+
+    loop {
+        // IMMEDIATE takes the write lock up front, so concurrent callers
+        // serialize here and re-read the version after the winner committed
+        conn.execute("BEGIN IMMEDIATE").await?;
+
+        let result: anyhow::Result<bool> = async {
+            let version: i64 = sqlx::query_scalar("PRAGMA user_version")
+                .fetch_one(&mut *conn)
+                .await?;
+            let version = version as u32;
+
+            let Some(migration) = migrations.iter().find(|m| m.version > version) else {
+                return Ok(false);
+            };
+
+            log::info!(
+                "applying migration {} ({}) over version {}",
+                migration.version,
+                migration.description,
+                version
+            );
+
+            for stmt in migration.sql.split(';') {
+                if stmt.trim().is_empty() {
+                    continue;
+                }
+                conn.execute(stmt).await?;
+            }
+
+            // pragmas can't be bound, the version is a trusted constant
+            conn.execute(format!("PRAGMA user_version = {}", migration.version).as_str())
+                .await?;
+
+            Ok(true)
+        }
+        .await;
+
+        match result {
+            Ok(applied) => {
+                conn.execute("COMMIT").await?;
+                if !applied {
+                    return Ok(());
+                }
+            }
+            Err(err) => {
+                if let Err(rollback_err) = conn.execute("ROLLBACK").await {
+                    log::error!("failed to rollback migration: {:?}", rollback_err);
+                }
+                return Err(err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::setup_pool;
+
+    const DB_URI: &str = ":memory:";
+
+    const TEST_MIGRATIONS: &[Migration] = &[
+        Migration {
+            version: 1,
+            description: "create items",
+            sql: "CREATE TABLE items (id INTEGER PRIMARY KEY)",
+        },
+        Migration {
+            version: 2,
+            description: "add name",
+            sql: "ALTER TABLE items ADD COLUMN name TEXT NOT NULL DEFAULT ''",
+        },
+    ];
+
+    #[tokio::test]
+    async fn test_migrate_applies_in_order() {
+        let pool = setup_pool(DB_URI, 1).await.unwrap();
+
+        migrate(&pool, &TEST_MIGRATIONS[..1]).await.unwrap();
+        assert_eq!(get_schema_version(&pool).await.unwrap(), 1);
+
+        migrate(&pool, TEST_MIGRATIONS).await.unwrap();
+        assert_eq!(get_schema_version(&pool).await.unwrap(), 2);
+
+        sqlx::query("INSERT INTO items (id, name) VALUES (1, 'a')")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        // already current, nothing is re-applied
+        migrate(&pool, TEST_MIGRATIONS).await.unwrap();
+        assert_eq!(get_schema_version(&pool).await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_migrate_refuses_newer_database() {
+        let pool = setup_pool(DB_URI, 1).await.unwrap();
+
+        migrate(&pool, TEST_MIGRATIONS).await.unwrap();
+
+        let result = migrate(&pool, &TEST_MIGRATIONS[..1]).await;
+        assert!(result.is_err());
+        assert_eq!(get_schema_version(&pool).await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_failed_migration_is_rolled_back() {
+        let pool = setup_pool(DB_URI, 1).await.unwrap();
+
+        let migrations = &[
+            Migration {
+                version: 1,
+                description: "create items",
+                sql: "CREATE TABLE items (id INTEGER PRIMARY KEY)",
+            },
+            Migration {
+                version: 2,
+                description: "broken",
+                sql: "CREATE TABLE other (id INTEGER); ALTER TABLE missing ADD COLUMN x TEXT",
+            },
+        ];
+
+        assert!(migrate(&pool, migrations).await.is_err());
+        assert_eq!(get_schema_version(&pool).await.unwrap(), 1);
+        assert!(table_exists(&pool, "items").await.unwrap());
+        assert!(!table_exists(&pool, "other").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_schemas_apply_on_empty_database() {
+        let pool = setup_pool(DB_URI, 1).await.unwrap();
+        migrate(&pool, FIREFLY_DB_MIGRATIONS).await.unwrap();
+        assert!(table_exists(&pool, "group_messages").await.unwrap());
+
+        let pool = setup_pool(DB_URI, 1).await.unwrap();
+        migrate(&pool, USER_MESSAGES_DB_MIGRATIONS).await.unwrap();
+        assert!(table_exists(&pool, "user_messages").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_user_message_rowids_survive_vacuum() {
+        let pool = setup_pool(DB_URI, 1).await.unwrap();
+        migrate(&pool, &USER_MESSAGES_DB_MIGRATIONS[..6])
+            .await
+            .unwrap();
+
+        for (id, text) in [(1, "one"), (2, "two"), (3, "three")] {
+            sqlx::query(
+                "INSERT INTO user_messages (id, other, sent_by_other, message) VALUES (?, 'alice', 1, x'')",
+            )
+            .bind(id)
+            .execute(&pool)
+            .await
+            .unwrap();
+            sqlx::query(
+                "INSERT INTO user_messages_fts (rowid, text) VALUES (last_insert_rowid(), ?)",
+            )
+            .bind(text)
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+        // a gap VACUUM would close by renumbering implicit rowids
+        sqlx::query("DELETE FROM user_messages_fts WHERE rowid = (SELECT rowid FROM user_messages WHERE id = 1)")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("DELETE FROM user_messages WHERE id = 1")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        migrate(&pool, USER_MESSAGES_DB_MIGRATIONS).await.unwrap();
+        sqlx::query("VACUUM").execute(&pool).await.unwrap();
+
+        let id: i64 = sqlx::query_scalar(
+            "SELECT m.id FROM user_messages_fts JOIN user_messages AS m ON m.rowid = user_messages_fts.rowid WHERE user_messages_fts MATCH 'three'",
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(id, 3);
+    }
+}
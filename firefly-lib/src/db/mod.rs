@@ -2,13 +2,18 @@ use std::path::PathBuf;
 
 use sqlx::{Executor, sqlite::SqlitePoolOptions};
 
+pub mod account;
 pub mod address;
+pub mod attachment_cache;
 pub mod auth;
 pub mod conversations;
 pub mod ffi_stores;
 pub mod group_stores;
 pub mod keyvalue;
 pub mod messages;
+pub mod migrations;
+pub mod outbox;
+pub mod search;
 pub mod stores;
 pub mod group_messages;
 
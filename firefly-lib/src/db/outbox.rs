@@ -0,0 +1,376 @@
+use std::collections::HashSet;
+
+use sqlx::{SqlitePool, prelude::*};
+
+use crate::db::migrations::{FIREFLY_DB_MIGRATIONS, migrate};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutboxStatus {
+    Pending,
+    Sent,
+    Failed,
+}
+
+impl OutboxStatus {
+    fn as_i64(self) -> i64 {
+        match self {
+            OutboxStatus::Pending => 0,
+            OutboxStatus::Sent => 1,
+            OutboxStatus::Failed => 2,
+        }
+    }
+
+    fn from_i64(value: i64) -> anyhow::Result<Self> {
+        match value {
+            0 => Ok(OutboxStatus::Pending),
+            1 => Ok(OutboxStatus::Sent),
+            2 => Ok(OutboxStatus::Failed),
+            _ => Err(anyhow::anyhow!("invalid outbox status {}", value)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OutboxMessage {
+    pub id: u64,
+    pub other: String,
+    pub message: Vec<u8>,
+    pub status: OutboxStatus,
+    pub attempts: u32,
+    pub next_attempt_at: u64,
+    pub last_error: Option<String>,
+}
+
+impl<'r> FromRow<'r, sqlx::sqlite::SqliteRow> for OutboxMessage {
+    fn from_row(row: &'r sqlx::sqlite::SqliteRow) -> Result<Self, sqlx::Error> {
+        let id: i64 = row.try_get("id")?;
+        let status: i64 = row.try_get("status")?;
+        let next_attempt_at: i64 = row.try_get("next_attempt_at")?;
+
+        Ok(Self {
+            id: id as u64,
+            other: row.try_get("other")?,
+            message: row.try_get("message")?,
+            status: OutboxStatus::from_i64(status).map_err(|err| sqlx::Error::ColumnDecode {
+                index: "status".into(),
+                source: err.into(),
+            })?,
+            attempts: row.try_get("attempts")?,
+            next_attempt_at: next_attempt_at as u64,
+            last_error: row.try_get("last_error")?,
+        })
+    }
+}
+
+/// Outgoing direct messages that have not been acknowledged by the server yet.
+/// Rows are keyed by the same id that is handed back to the caller as the
+/// `UserMessage` id, so status updates can be matched against the chat history.
+#[derive(Clone)]
+pub struct OutboxStore {
+    pool: SqlitePool,
+}
+
+impl OutboxStore {
+    pub async fn new(pool: SqlitePool) -> anyhow::Result<Self> {
+        migrate(&pool, FIREFLY_DB_MIGRATIONS).await?;
+
+        Ok(Self { pool })
+    }
+
+    pub async fn enqueue(&self, id: u64, other: &str, message: &[u8]) -> anyhow::Result<()> {
+        log::info!("store insert: outbox id={} other={}", id, other);
+        sqlx::query(
+            "INSERT INTO user_message_outbox (id, other, message, status, attempts, next_attempt_at) VALUES (?, ?, ?, ?, 0, 0)",
+        )
+        .bind(id as i64)
+        .bind(other)
+        .bind(message)
+        .bind(OutboxStatus::Pending.as_i64())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn get(&self, id: u64) -> anyhow::Result<Option<OutboxMessage>> {
+        let row = sqlx::query(
+            "SELECT id, other, message, status, attempts, next_attempt_at, last_error FROM user_message_outbox WHERE id = ?",
+        )
+        .bind(id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.as_ref().map(OutboxMessage::from_row).transpose()?)
+    }
+
+    /// Pending messages whose backoff has elapsed, oldest first.
+    pub async fn get_due(&self, now_millis: u64, limit: u32) -> anyhow::Result<Vec<OutboxMessage>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, other, message, status, attempts, next_attempt_at, last_error
+            FROM user_message_outbox
+            WHERE status = ? AND next_attempt_at <= ?
+            ORDER BY id ASC LIMIT ?
+            "#,
+        )
+        .bind(OutboxStatus::Pending.as_i64())
+        .bind(now_millis as i64)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .iter()
+            .map(OutboxMessage::from_row)
+            .collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /// Every message that is not yet sent, so the UI can restore its badges.
+    pub async fn get_unsent(&self) -> anyhow::Result<Vec<OutboxMessage>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, other, message, status, attempts, next_attempt_at, last_error
+            FROM user_message_outbox
+            WHERE status <> ?
+            ORDER BY id ASC
+            "#,
+        )
+        .bind(OutboxStatus::Sent.as_i64())
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .iter()
+            .map(OutboxMessage::from_row)
+            .collect::<Result<Vec<_>, _>>()?)
+    }
+
+    pub async fn mark_sent(&self, id: u64) -> anyhow::Result<()> {
+        log::info!("store update: outbox id={} sent", id);
+        sqlx::query("UPDATE user_message_outbox SET status = ?, last_error = NULL WHERE id = ?")
+            .bind(OutboxStatus::Sent.as_i64())
+            .bind(id as i64)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn schedule_retry(
+        &self,
+        id: u64,
+        attempts: u32,
+        next_attempt_at: u64,
+        error: &str,
+    ) -> anyhow::Result<()> {
+        log::info!(
+            "store update: outbox id={} attempts={} next_attempt_at={}",
+            id,
+            attempts,
+            next_attempt_at
+        );
+        sqlx::query(
+            "UPDATE user_message_outbox SET status = ?, attempts = ?, next_attempt_at = ?, last_error = ? WHERE id = ?",
+        )
+        .bind(OutboxStatus::Pending.as_i64())
+        .bind(attempts)
+        .bind(next_attempt_at as i64)
+        .bind(error)
+        .bind(id as i64)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn mark_failed(&self, id: u64, attempts: u32, error: &str) -> anyhow::Result<()> {
+        log::info!("store update: outbox id={} failed", id);
+        sqlx::query(
+            "UPDATE user_message_outbox SET status = ?, attempts = ?, last_error = ? WHERE id = ?",
+        )
+        .bind(OutboxStatus::Failed.as_i64())
+        .bind(attempts)
+        .bind(error)
+        .bind(id as i64)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Moves a failed message back to pending so it is picked up by the next flush.
+    pub async fn reset(&self, id: u64) -> anyhow::Result<bool> {
+        log::info!("store update: outbox id={} reset", id);
+        let result = sqlx::query(
+            "UPDATE user_message_outbox SET status = ?, attempts = 0, next_attempt_at = 0 WHERE id = ? AND status = ?",
+        )
+        .bind(OutboxStatus::Pending.as_i64())
+        .bind(id as i64)
+        .bind(OutboxStatus::Failed.as_i64())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Addresses that accepted the message in an earlier attempt, a retry
+    /// skips them so their devices don't get it twice.
+    pub async fn get_delivered(&self, id: u64) -> anyhow::Result<HashSet<u64>> {
+        let rows =
+            sqlx::query("SELECT address_id FROM user_message_outbox_deliveries WHERE id = ?")
+                .bind(id as i64)
+                .fetch_all(&self.pool)
+                .await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| row.try_get::<i64, _>("address_id").map(|id| id as u64))
+            .collect::<Result<HashSet<_>, _>>()?)
+    }
+
+    pub async fn add_delivered(&self, id: u64, address_ids: &HashSet<u64>) -> anyhow::Result<()> {
+        log::info!(
+            "store insert: outbox id={} delivered to {:?}",
+            id,
+            address_ids
+        );
+        let mut tx = self.pool.begin().await?;
+        for address_id in address_ids {
+            sqlx::query(
+                "INSERT OR IGNORE INTO user_message_outbox_deliveries (id, address_id) VALUES (?, ?)",
+            )
+            .bind(id as i64)
+            .bind(*address_id as i64)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Drops sent messages, their plaintext isn't needed once the server has it.
+    pub async fn delete_sent(&self) -> anyhow::Result<()> {
+        log::info!("store delete: outbox sent");
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            "DELETE FROM user_message_outbox_deliveries WHERE id IN (SELECT id FROM user_message_outbox WHERE status = ?)",
+        )
+        .bind(OutboxStatus::Sent.as_i64())
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query("DELETE FROM user_message_outbox WHERE status = ?")
+            .bind(OutboxStatus::Sent.as_i64())
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::SqlitePool;
+
+    async fn setup_test_db() -> SqlitePool {
+        SqlitePool::connect(":memory:").await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_enqueue_and_get_due() {
+        let pool = setup_test_db().await;
+        let store = OutboxStore::new(pool).await.unwrap();
+
+        store.enqueue(2, "bob", &[2]).await.unwrap();
+        store.enqueue(1, "alice", &[1]).await.unwrap();
+
+        let due = store.get_due(0, 10).await.unwrap();
+        assert_eq!(due.len(), 2);
+        assert_eq!(due[0].id, 1);
+        assert_eq!(due[0].other, "alice");
+        assert_eq!(due[0].status, OutboxStatus::Pending);
+        assert_eq!(due[1].id, 2);
+    }
+
+    #[tokio::test]
+    async fn test_schedule_retry_delays_message() {
+        let pool = setup_test_db().await;
+        let store = OutboxStore::new(pool).await.unwrap();
+
+        store.enqueue(1, "alice", &[1]).await.unwrap();
+        store
+            .schedule_retry(1, 1, 5_000, "not connected")
+            .await
+            .unwrap();
+
+        assert!(store.get_due(4_999, 10).await.unwrap().is_empty());
+
+        let due = store.get_due(5_000, 10).await.unwrap();
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].attempts, 1);
+        assert_eq!(due[0].last_error.as_deref(), Some("not connected"));
+    }
+
+    #[tokio::test]
+    async fn test_mark_sent_and_failed() {
+        let pool = setup_test_db().await;
+        let store = OutboxStore::new(pool).await.unwrap();
+
+        store.enqueue(1, "alice", &[1]).await.unwrap();
+        store.enqueue(2, "alice", &[2]).await.unwrap();
+
+        store.mark_sent(1).await.unwrap();
+        store.mark_failed(2, 5, "no addresses").await.unwrap();
+
+        assert!(store.get_due(u64::MAX >> 1, 10).await.unwrap().is_empty());
+
+        let unsent = store.get_unsent().await.unwrap();
+        assert_eq!(unsent.len(), 1);
+        assert_eq!(unsent[0].id, 2);
+        assert_eq!(unsent[0].status, OutboxStatus::Failed);
+
+        store.delete_sent().await.unwrap();
+        assert!(store.get(1).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_delivered_addresses() {
+        let pool = setup_test_db().await;
+        let store = OutboxStore::new(pool).await.unwrap();
+
+        store.enqueue(1, "alice", &[1]).await.unwrap();
+        assert!(store.get_delivered(1).await.unwrap().is_empty());
+
+        store
+            .add_delivered(1, &HashSet::from([10, 11]))
+            .await
+            .unwrap();
+        store.add_delivered(1, &HashSet::from([11])).await.unwrap();
+        assert_eq!(
+            store.get_delivered(1).await.unwrap(),
+            HashSet::from([10, 11])
+        );
+
+        store.mark_sent(1).await.unwrap();
+        store.delete_sent().await.unwrap();
+        assert!(store.get_delivered(1).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_reset_failed_message() {
+        let pool = setup_test_db().await;
+        let store = OutboxStore::new(pool).await.unwrap();
+
+        store.enqueue(1, "alice", &[1]).await.unwrap();
+        assert!(!store.reset(1).await.unwrap());
+
+        store.mark_failed(1, 5, "timeout").await.unwrap();
+        assert!(store.reset(1).await.unwrap());
+
+        let message = store.get(1).await.unwrap().unwrap();
+        assert_eq!(message.status, OutboxStatus::Pending);
+        assert_eq!(message.attempts, 0);
+    }
+}
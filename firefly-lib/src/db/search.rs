@@ -0,0 +1,262 @@
+use crate::{
+    db::{group_messages::GroupMessage, messages::UserMessage},
+    pb::firefly::firefly::{
+        GroupMessageInner, UserMessageInner, group_message_inner, user_message_inner,
+    },
+    utils::deserialize_proto,
+};
+
+pub const SNIPPET_MARK_START: &str = "<mark>";
+pub const SNIPPET_MARK_END: &str = "</mark>";
+pub const SNIPPET_ELLIPSIS: &str = "…";
+pub const SNIPPET_TOKENS: u32 = 16;
+
+/// Put around matches by `snippet()`, from the private use area so they can be
+/// told apart from the message text when it is escaped.
+pub const SNIPPET_MATCH_START: char = '\u{e000}';
+pub const SNIPPET_MATCH_END: char = '\u{e001}';
+
+pub struct UserMessageSearchHit {
+    pub message: UserMessage,
+    /// HTML, the text is escaped and only the matches are in `<mark>`
+    pub snippet: String,
+    /// bm25 score, lower is more relevant
+    pub rank: f64,
+}
+
+pub struct GroupMessageSearchHit {
+    pub message: GroupMessage,
+    /// HTML, the text is escaped and only the matches are in `<mark>`
+    pub snippet: String,
+    /// bm25 score, lower is more relevant
+    pub rank: f64,
+}
+
+pub enum SearchHit {
+    User(UserMessageSearchHit),
+    Group(GroupMessageSearchHit),
+}
+
+impl SearchHit {
+    pub fn rank(&self) -> f64 {
+        match self {
+            SearchHit::User(hit) => hit.rank,
+            SearchHit::Group(hit) => hit.rank,
+        }
+    }
+}
+
+/// Searchable text of a decrypted direct message payload, `None` for payloads
+/// without text (calls, receipts, typing, edits, reactions, block and timer
+/// updates, garbage).
+pub fn user_message_text(payload: &[u8]) -> Option<String> {
+    let inner = deserialize_proto::<UserMessageInner>(payload).ok()?;
+
+    let text = match inner.message? {
+        user_message_inner::Message::PlainText(text) => String::from_utf8(text).ok()?,
+        user_message_inner::Message::MessagePayload(payload) => payload.text,
+        user_message_inner::Message::SelfMessage(self_message) => {
+            return user_message_text(&self_message.inner);
+        }
+        user_message_inner::Message::CallMessage(_)
+        | user_message_inner::Message::Receipt(_)
+        | user_message_inner::Message::Typing(_)
+        | user_message_inner::Message::Edit(_)
+        | user_message_inner::Message::Delete(_)
+        | user_message_inner::Message::Reaction(_)
+        | user_message_inner::Message::BlockUpdate(_)
+        | user_message_inner::Message::DisappearingTimerUpdate(_) => return None,
+    };
+
+    (!text.trim().is_empty()).then_some(text)
+}
+
+/// Searchable text of a decrypted group message payload.
+pub fn group_message_text(payload: &[u8]) -> Option<String> {
+    let inner = deserialize_proto::<GroupMessageInner>(payload).ok()?;
+
+    let text = match inner.message? {
+        group_message_inner::Message::MessagePayload(payload) => payload.text,
+        group_message_inner::Message::Typing(_)
+        | group_message_inner::Message::Edit(_)
+        | group_message_inner::Message::Delete(_)
+        | group_message_inner::Message::Reaction(_) => return None,
+    };
+
+    (!text.trim().is_empty()).then_some(text)
+}
+
+/// Turns user input into an FTS5 query. Every whitespace separated term is
+/// quoted, so operators typed by the user are matched literally, and treated
+/// as a prefix, so results show up while typing.
+pub fn fts_query(query: &str) -> Option<String> {
+    let terms = query
+        .split_whitespace()
+        .map(|term| term.replace('"', ""))
+        .filter(|term| !term.is_empty())
+        .map(|term| format!("\"{}\"*", term))
+        .collect::<Vec<_>>();
+
+    if terms.is_empty() {
+        None
+    } else {
+        Some(terms.join(" "))
+    }
+}
+
+/// Turns a snippet with `SNIPPET_MATCH_START`/`SNIPPET_MATCH_END` around the
+/// matches into HTML. The text is written by peers, so everything but the
+/// `<mark>` tags is escaped, and marks are kept balanced even if the text
+/// contains the match characters itself.
+pub fn snippet_html(snippet: &str) -> String {
+    let mut html = String::with_capacity(snippet.len() + 16);
+    let mut marked = false;
+
+    for c in snippet.chars() {
+        match c {
+            SNIPPET_MATCH_START if !marked => {
+                html.push_str(SNIPPET_MARK_START);
+                marked = true;
+            }
+            SNIPPET_MATCH_END if marked => {
+                html.push_str(SNIPPET_MARK_END);
+                marked = false;
+            }
+            SNIPPET_MATCH_START | SNIPPET_MATCH_END => {}
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            c => html.push(c),
+        }
+    }
+
+    if marked {
+        html.push_str(SNIPPET_MARK_END);
+    }
+    html
+}
+
+/// Ranks hits from the direct and group indexes together. bm25 scores depend
+/// on the statistics of their own index, so each source is scaled by its best
+/// hit first: a hit scoring half as well as the best of its index ranks with
+/// the hits doing the same in the other one.
+pub fn merge_hits(
+    user_hits: Vec<UserMessageSearchHit>,
+    group_hits: Vec<GroupMessageSearchHit>,
+    limit: usize,
+) -> Vec<SearchHit> {
+    let mut hits = relevance(user_hits.into_iter().map(SearchHit::User).collect())
+        .chain(relevance(
+            group_hits.into_iter().map(SearchHit::Group).collect(),
+        ))
+        .collect::<Vec<_>>();
+
+    // stable, so on equal relevance each source keeps its own order
+    hits.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+    hits.truncate(limit);
+
+    hits.into_iter().map(|(_, hit)| hit).collect()
+}
+
+/// Pairs each hit with its score relative to the best hit of the same index,
+/// from 1 for the best down towards 0.
+fn relevance(hits: Vec<SearchHit>) -> impl Iterator<Item = (f64, SearchHit)> {
+    let best = hits.iter().map(SearchHit::rank).fold(0.0, f64::min);
+
+    hits.into_iter().map(move |hit| {
+        let relevance = if best < 0.0 {
+            (hit.rank() / best).max(0.0)
+        } else {
+            1.0
+        };
+        (relevance, hit)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{pb::firefly::firefly::MessagePayload, utils::serialize_proto};
+
+    #[test]
+    fn test_user_message_text() {
+        let plain = serialize_proto(&UserMessageInner {
+            message: Some(user_message_inner::Message::PlainText(b"hello".to_vec())),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(user_message_text(&plain).as_deref(), Some("hello"));
+
+        let payload = serialize_proto(&UserMessageInner {
+            message: Some(user_message_inner::Message::MessagePayload(
+                MessagePayload {
+                    text: "with payload".to_string(),
+                    ..Default::default()
+                },
+            )),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(user_message_text(&payload).as_deref(), Some("with payload"));
+
+        assert_eq!(user_message_text(&[0xff, 0xff]), None);
+    }
+
+    #[test]
+    fn test_fts_query() {
+        assert_eq!(fts_query("   "), None);
+        assert_eq!(fts_query("hello"), Some("\"hello\"*".to_string()));
+        assert_eq!(
+            fts_query("foo \"bar OR"),
+            Some("\"foo\"* \"bar\"* \"OR\"*".to_string())
+        );
+    }
+
+    #[test]
+    fn test_snippet_html() {
+        assert_eq!(
+            snippet_html("see \u{e000}you\u{e001} <b>\"there\"</b> & 'here'"),
+            "see <mark>you</mark> &lt;b&gt;&quot;there&quot;&lt;/b&gt; &amp; &#39;here&#39;"
+        );
+
+        // match characters typed by a peer don't unbalance the marks
+        assert_eq!(
+            snippet_html("\u{e001}a \u{e000}b\u{e000}c"),
+            "a <mark>bc</mark>"
+        );
+    }
+
+    #[test]
+    fn test_merge_hits_ranks_across_sources() {
+        let user_hit = |id: u64, rank: f64| UserMessageSearchHit {
+            message: UserMessage::new(id, "alice".into(), vec![], true),
+            snippet: String::new(),
+            rank,
+        };
+        let group_hit = |id: u64, rank: f64| GroupMessageSearchHit {
+            message: GroupMessage::new(id, 1, "bob".into(), vec![], 0, 0),
+            snippet: String::new(),
+            rank,
+        };
+
+        // group scores are on another scale, they are compared to their best
+        let hits = merge_hits(
+            vec![user_hit(1, -30.0), user_hit(2, -27.0), user_hit(3, -24.0)],
+            vec![group_hit(4, -2.0), group_hit(5, -1.0)],
+            4,
+        );
+
+        let ids = hits
+            .iter()
+            .map(|hit| match hit {
+                SearchHit::User(hit) => hit.message.id,
+                SearchHit::Group(hit) => hit.message.id,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![1, 4, 2, 3]);
+
+        assert!(merge_hits(vec![], vec![group_hit(4, -2.0)], 0).is_empty());
+    }
+}
@@ -1,12 +1,17 @@
-use std::time::SystemTime;
+use std::{collections::HashMap, time::SystemTime};
 
+use anyhow::Context;
 use libsignal_protocol::{kem::KeyType, *};
 use rand::RngCore;
 use sqlx::{SqlitePool, prelude::*};
 
 use crate::{
     EncryptedMessage, FfiPreKeyBundle,
-    db::{address::AddressStore, conversations::ConversationStore},
+    db::{
+        address::AddressStore,
+        conversations::ConversationStore,
+        migrations::{FIREFLY_DB_MIGRATIONS, migrate},
+    },
     utils::{self, get_current_timestamp_millis_since_epoch},
 };
 
@@ -17,11 +22,7 @@ pub struct PreKeyDb {
 
 impl PreKeyDb {
     pub async fn new(pool: SqlitePool) -> anyhow::Result<Self> {
-        let sql = "CREATE TABLE IF NOT EXISTS pre_keys (\n    id INTEGER PRIMARY KEY,\n    record BLOB NOT NULL\n)";
-        let mut connection = pool.acquire().await?;
-        for stmt in sql.split(';') {
-            connection.execute(stmt).await?;
-        }
+        migrate(&pool, FIREFLY_DB_MIGRATIONS).await?;
 
         Ok(Self { pool })
     }
@@ -93,11 +94,7 @@ pub struct SignedPreKeyDb {
 
 impl SignedPreKeyDb {
     pub async fn new(pool: SqlitePool) -> anyhow::Result<Self> {
-        let sql = "CREATE TABLE IF NOT EXISTS signed_pre_keys (\n    id INTEGER PRIMARY KEY,\n    record BLOB NOT NULL\n)";
-        let mut connection = pool.acquire().await?;
-        for stmt in sql.split(';') {
-            connection.execute(stmt).await?;
-        }
+        migrate(&pool, FIREFLY_DB_MIGRATIONS).await?;
         Ok(Self { pool })
     }
 
@@ -160,11 +157,7 @@ pub struct SessionDb {
 
 impl SessionDb {
     pub async fn new(pool: SqlitePool) -> anyhow::Result<Self> {
-        let sql = "CREATE TABLE IF NOT EXISTS sessions (\n    address TEXT PRIMARY KEY,\n    record BLOB NOT NULL\n)";
-        let mut connection = pool.acquire().await?;
-        for stmt in sql.split(';') {
-            connection.execute(stmt).await?;
-        }
+        migrate(&pool, FIREFLY_DB_MIGRATIONS).await?;
         Ok(Self { pool })
     }
 
@@ -236,6 +229,51 @@ pub struct IdentityKeyPairRow {
     pub device_id: u8,
 }
 
+/// Trust on first use: the first identity key seen for an address is stored
+/// as `Unverified`, a different key later on moves it to `Changed` until the
+/// user acknowledges it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdentityTrustState {
+    Unverified,
+    Verified,
+    Changed,
+}
+
+impl IdentityTrustState {
+    fn as_i64(self) -> i64 {
+        match self {
+            IdentityTrustState::Unverified => 0,
+            IdentityTrustState::Verified => 1,
+            IdentityTrustState::Changed => 2,
+        }
+    }
+
+    fn from_i64(value: i64) -> Self {
+        match value {
+            1 => IdentityTrustState::Verified,
+            2 => IdentityTrustState::Changed,
+            _ => IdentityTrustState::Unverified,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct IdentityTrust {
+    pub username: String,
+    pub device_id: u32,
+    pub state: IdentityTrustState,
+}
+
+/// Splits a stored `ProtocolAddress` string (`name.device_id`) back into parts.
+fn parse_address(address: &str) -> Option<(String, u32)> {
+    let (name, device_id) = address.rsplit_once('.')?;
+    Some((name.to_string(), device_id.parse().ok()?))
+}
+
+/// Matches every device address of `username` without `LIKE` wildcards,
+/// usernames may contain `_`.
+const ADDRESS_OF_USER: &str = "substr(address, 1, length(?) + 1) = ? || '.'";
+
 #[derive(Clone)]
 pub struct IdentityDb {
     pool: SqlitePool,
@@ -243,19 +281,16 @@ pub struct IdentityDb {
 
 impl IdentityDb {
     pub async fn new(pool: SqlitePool) -> anyhow::Result<Self> {
-        let mut connection = pool.acquire().await?;
-        let sql = r#"CREATE TABLE IF NOT EXISTS identities (
-                address TEXT PRIMARY KEY,
-                identity_key BLOB NOT NULL
-            )"#;
-        connection.execute(sql).await?;
-        let sql = r#"CREATE TABLE IF NOT EXISTS identity_keypair (
-                id INTEGER NOT NULL,
-                keypair BLOB NOT NULL,
-                registration_id INTEGER NOT NULL,
-                device_id INTEGER NOT NULL
-            )"#;
-        connection.execute(sql).await?;
+        migrate(&pool, FIREFLY_DB_MIGRATIONS).await?;
+        let store = Self { pool };
+        store.create_key_pair_if_missing().await?;
+        Ok(store)
+    }
+
+    /// Generates the identity of this device unless it has one, also after a
+    /// logout deleted it.
+    pub async fn create_key_pair_if_missing(&self) -> anyhow::Result<()> {
+        let mut connection = self.pool.acquire().await?;
 
         {
             connection
@@ -287,7 +322,7 @@ impl IdentityDb {
                 .await?;
         }
 
-        Ok(Self { pool })
+        Ok(())
     }
 
     pub async fn update_id_for_keypair(&self, id: i64) -> anyhow::Result<()> {
@@ -360,27 +395,249 @@ impl IdentityDb {
             .await
             .map_err(|err| SignalProtocolError::FfiBindingError(err.to_string()))?;
 
-        sqlx::query("INSERT OR REPLACE INTO identities (address, identity_key) VALUES (?, ?)")
+        let changed = match existing {
+            Some(row) => {
+                let key: &[u8] = row
+                    .try_get(0)
+                    .map_err(|err| SignalProtocolError::FfiBindingError(err.to_string()))?;
+                key != identity.serialize().as_ref()
+            }
+            None => false,
+        };
+
+        if changed {
+            log::warn!("identity key changed for address={}", address);
+            sqlx::query(
+                "UPDATE identities SET identity_key = ?, trust_state = ?, change_pending_notification = 1 WHERE address = ?",
+            )
+            .bind(identity.serialize())
+            .bind(IdentityTrustState::Changed.as_i64())
+            .bind(address.to_string())
+            .execute(&mut *tx)
+            .await
+            .map_err(|err| SignalProtocolError::FfiBindingError(err.to_string()))?;
+        } else {
+            sqlx::query(
+                "INSERT OR IGNORE INTO identities (address, identity_key, trust_state) VALUES (?, ?, ?)",
+            )
             .bind(address.to_string())
             .bind(identity.serialize())
+            .bind(IdentityTrustState::Unverified.as_i64())
             .execute(&mut *tx)
             .await
             .map_err(|err| SignalProtocolError::FfiBindingError(err.to_string()))?;
+        }
 
         tx.commit()
             .await
             .map_err(|err| SignalProtocolError::FfiBindingError(err.to_string()))?;
 
-        Ok(IdentityChange::from_changed(existing.is_none()))
+        Ok(IdentityChange::from_changed(changed))
     }
 
+    /// Incoming messages are always accepted, a new key is recorded as
+    /// `Changed` by `save_identity`. Outgoing messages are refused while the
+    /// identity is `Changed`, a new key seen while sending (from a pre key
+    /// bundle) is recorded here so it can be acknowledged.
     pub async fn is_trusted_identity(
         &self,
-        _address: &ProtocolAddress,
-        _identity: &IdentityKey,
-        _direction: Direction,
+        address: &ProtocolAddress,
+        identity: &IdentityKey,
+        direction: Direction,
     ) -> Result<bool, SignalProtocolError> {
-        Ok(true)
+        if let Direction::Receiving = direction {
+            return Ok(true);
+        }
+
+        let row = sqlx::query("SELECT identity_key, trust_state FROM identities WHERE address = ?")
+            .bind(address.to_string())
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|err| SignalProtocolError::FfiBindingError(err.to_string()))?;
+
+        let Some(row) = row else {
+            return Ok(true);
+        };
+
+        let key: &[u8] = row
+            .try_get(0)
+            .map_err(|err| SignalProtocolError::FfiBindingError(err.to_string()))?;
+        let state: i64 = row
+            .try_get(1)
+            .map_err(|err| SignalProtocolError::FfiBindingError(err.to_string()))?;
+
+        if key != identity.serialize().as_ref() {
+            log::warn!(
+                "refusing to send, identity key changed for address={}",
+                address
+            );
+            sqlx::query(
+                "UPDATE identities SET identity_key = ?, trust_state = ?, change_pending_notification = 1 WHERE address = ?",
+            )
+            .bind(identity.serialize())
+            .bind(IdentityTrustState::Changed.as_i64())
+            .bind(address.to_string())
+            .execute(&self.pool)
+            .await
+            .map_err(|err| SignalProtocolError::FfiBindingError(err.to_string()))?;
+            return Ok(false);
+        }
+
+        Ok(IdentityTrustState::from_i64(state) != IdentityTrustState::Changed)
+    }
+
+    pub async fn get_trust(&self, username: &str) -> anyhow::Result<Vec<IdentityTrust>> {
+        let rows = sqlx::query(&format!(
+            "SELECT address, trust_state FROM identities WHERE {}",
+            ADDRESS_OF_USER
+        ))
+        .bind(username)
+        .bind(username)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut trusts = Vec::with_capacity(rows.len());
+        for row in rows {
+            let address: String = row.try_get(0)?;
+            let state: i64 = row.try_get(1)?;
+            let Some((username, device_id)) = parse_address(&address) else {
+                continue;
+            };
+            trusts.push(IdentityTrust {
+                username,
+                device_id,
+                state: IdentityTrustState::from_i64(state),
+            });
+        }
+
+        Ok(trusts)
+    }
+
+    pub async fn has_changed_identity(&self, username: &str) -> anyhow::Result<bool> {
+        let row = sqlx::query(&format!(
+            "SELECT 1 FROM identities WHERE {} AND trust_state = ? LIMIT 1",
+            ADDRESS_OF_USER
+        ))
+        .bind(username)
+        .bind(username)
+        .bind(IdentityTrustState::Changed.as_i64())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.is_some())
+    }
+
+    /// Accepts the new keys of every changed device of `username`, they
+    /// become `Unverified` and sending is allowed again.
+    pub async fn acknowledge_identity_change(&self, username: &str) -> anyhow::Result<()> {
+        log::info!(
+            "store update: identities username={} acknowledged",
+            username
+        );
+        sqlx::query(&format!(
+            "UPDATE identities SET trust_state = ?, change_pending_notification = 0 WHERE {} AND trust_state = ?",
+            ADDRESS_OF_USER
+        ))
+        .bind(IdentityTrustState::Unverified.as_i64())
+        .bind(username)
+        .bind(username)
+        .bind(IdentityTrustState::Changed.as_i64())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Marks the current key of a device as verified out of band, or reverts
+    /// it to unverified. A changed key has to be acknowledged first.
+    pub async fn set_verified(
+        &self,
+        address: &ProtocolAddress,
+        verified: bool,
+    ) -> anyhow::Result<()> {
+        log::info!(
+            "store update: identities address={} verified={}",
+            address,
+            verified
+        );
+        let state = if verified {
+            IdentityTrustState::Verified
+        } else {
+            IdentityTrustState::Unverified
+        };
+        let result = sqlx::query(
+            "UPDATE identities SET trust_state = ? WHERE address = ? AND trust_state <> ?",
+        )
+        .bind(state.as_i64())
+        .bind(address.to_string())
+        .bind(IdentityTrustState::Changed.as_i64())
+        .execute(&self.pool)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(anyhow::anyhow!(
+                "no acknowledged identity for address {}",
+                address
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Usernames with a key change the user was not told about yet, clears the
+    /// flag so every change is reported once.
+    pub async fn take_pending_identity_changes(&self) -> anyhow::Result<Vec<String>> {
+        let mut tx = self.pool.begin().await?;
+        let rows =
+            sqlx::query("SELECT address FROM identities WHERE change_pending_notification = 1")
+                .fetch_all(&mut *tx)
+                .await?;
+
+        if rows.is_empty() {
+            return Ok(vec![]);
+        }
+
+        sqlx::query("UPDATE identities SET change_pending_notification = 0 WHERE change_pending_notification = 1")
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        let mut usernames = Vec::with_capacity(rows.len());
+        for row in rows {
+            let address: String = row.try_get(0)?;
+            if let Some((username, _)) = parse_address(&address) {
+                if !usernames.contains(&username) {
+                    usernames.push(username);
+                }
+            }
+        }
+
+        Ok(usernames)
+    }
+
+    /// Displayable safety number of our identity and the one stored for
+    /// `address`, both sides compute the same 60 digits.
+    pub async fn get_safety_number(
+        &self,
+        local_username: &str,
+        address: &ProtocolAddress,
+    ) -> anyhow::Result<String> {
+        let local = self.get_identity_key_pair().await?;
+        let remote = self
+            .get_identity(address)
+            .await?
+            .context("no identity key stored for address")?;
+
+        let fingerprint = Fingerprint::new(
+            2,
+            5200,
+            local_username.as_bytes(),
+            local.identity_key(),
+            address.name().as_bytes(),
+            &remote,
+        )?;
+
+        Ok(fingerprint.display_string()?)
     }
 
     pub async fn get_identity(
@@ -445,11 +702,7 @@ pub struct SenderKeyDb {
 
 impl SenderKeyDb {
     pub async fn new(pool: SqlitePool) -> anyhow::Result<Self> {
-        let sql = "CREATE TABLE IF NOT EXISTS sender_keys (\n    sender_id TEXT NOT NULL,\n    distribution_id TEXT NOT NULL,\n    record BLOB NOT NULL,\n    PRIMARY KEY (sender_id, distribution_id)\n)";
-        let mut connection = pool.acquire().await?;
-        for stmt in sql.split(';') {
-            connection.execute(stmt).await?;
-        }
+        migrate(&pool, FIREFLY_DB_MIGRATIONS).await?;
         Ok(Self { pool })
     }
 }
@@ -461,11 +714,7 @@ pub struct KyberPreKeyDb {
 
 impl KyberPreKeyDb {
     pub async fn new(pool: SqlitePool) -> anyhow::Result<Self> {
-        let sql = "CREATE TABLE IF NOT EXISTS kyber_pre_keys (\n    id INTEGER PRIMARY KEY,\n    record BLOB NOT NULL\n)";
-        let mut connection = pool.acquire().await?;
-        for stmt in sql.split(';') {
-            connection.execute(stmt).await?;
-        }
+        migrate(&pool, FIREFLY_DB_MIGRATIONS).await?;
         Ok(Self { pool })
     }
 
@@ -587,46 +836,99 @@ impl KeyStores {
         cipher_text: Vec<u8>,
         ty: u8,
     ) -> anyhow::Result<Vec<u8>> {
-        let cipher_text_type = CiphertextMessageType::try_from(ty)?;
+        decrypt_message(
+            &mut self.session_store,
+            &mut self.identity_store,
+            &mut self.prekey_store,
+            &mut self.signed_prekey_store,
+            &mut self.kyber_key_store,
+            other,
+            cipher_text,
+            ty,
+        )
+        .await
+    }
 
-        let remote_address = other;
-        let mut rng = utils::rng();
+    /// Decrypts server message `id` and stores its plaintext in the inbox in
+    /// the same transaction as the sessions and used pre keys, so a ratchet
+    /// step is never stored without the message it decrypted. A message
+    /// already in the inbox is taken from there, it can't be decrypted twice.
+    /// Senders pick the id, so the inbox is keyed by the sender's address too.
+    pub async fn decrypt_to_inbox(
+        &mut self,
+        id: u64,
+        other: ProtocolAddress,
+        cipher_text: Vec<u8>,
+        ty: u8,
+    ) -> anyhow::Result<Vec<u8>> {
+        let pool = self.session_store.pool.clone();
+        let address = other.to_string();
 
-        match cipher_text_type {
-            CiphertextMessageType::Whisper => {
-                let message = SignalMessage::try_from(cipher_text.as_ref())?;
+        let stored: Option<Vec<u8>> = sqlx::query_scalar(
+            "SELECT message FROM user_message_inbox WHERE address = ? AND id = ?",
+        )
+        .bind(&address)
+        .bind(id as i64)
+        .fetch_optional(&pool)
+        .await?;
+        if let Some(message) = stored {
+            return Ok(message);
+        }
 
-                let decrypted = message_decrypt_signal(
-                    &message,
-                    &remote_address,
-                    &mut self.session_store,
-                    &mut self.identity_store,
-                    &mut rng,
-                )
-                .await?;
+        let mut sessions = PendingSessions {
+            db: &self.session_store,
+            records: HashMap::new(),
+        };
+        let mut prekeys = PendingPreKeys {
+            db: &mut self.prekey_store,
+            removed: vec![],
+        };
+        let decrypted = decrypt_message(
+            &mut sessions,
+            &mut self.identity_store,
+            &mut prekeys,
+            &mut self.signed_prekey_store,
+            &mut self.kyber_key_store,
+            other,
+            cipher_text,
+            ty,
+        )
+        .await?;
 
-                return Ok(decrypted);
-            }
-            CiphertextMessageType::PreKey => {
-                let message = PreKeySignalMessage::try_from(cipher_text.as_ref())?;
-
-                let decrypted = message_decrypt_prekey(
-                    &message,
-                    &remote_address,
-                    &mut self.session_store,
-                    &mut self.identity_store,
-                    &mut self.prekey_store,
-                    &mut self.signed_prekey_store,
-                    &mut self.kyber_key_store,
-                    &mut rng,
-                )
+        let mut tx = pool.begin().await?;
+        for (address, record) in sessions.records {
+            sqlx::query("INSERT OR REPLACE INTO sessions (address, record) VALUES (?, ?)")
+                .bind(address)
+                .bind(record)
+                .execute(&mut *tx)
+                .await?;
+        }
+        for prekey_id in prekeys.removed {
+            sqlx::query("DELETE FROM pre_keys WHERE id = ?")
+                .bind(u32::from(prekey_id))
+                .execute(&mut *tx)
                 .await?;
+        }
+        sqlx::query("INSERT INTO user_message_inbox (address, id, message) VALUES (?, ?, ?)")
+            .bind(address)
+            .bind(id as i64)
+            .bind(&decrypted)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
 
-                return Ok(decrypted);
-            }
+        Ok(decrypted)
+    }
 
-            _ => return Err(anyhow::anyhow!("Invalid message type")),
-        }
+    /// Drops the inbox plaintexts up to and including `id`, once the cursor
+    /// moved past them they're stored wherever they belong.
+    pub async fn clear_inbox_through(&self, id: u64) -> anyhow::Result<()> {
+        sqlx::query("DELETE FROM user_message_inbox WHERE id <= ?")
+            .bind(id as i64)
+            .execute(&self.session_store.pool)
+            .await?;
+
+        Ok(())
     }
 
     pub async fn encrypt(
@@ -771,6 +1073,115 @@ impl KeyStores {
     }
 }
 
+async fn decrypt_message(
+    session_store: &mut dyn SessionStore,
+    identity_store: &mut IdentityDb,
+    prekey_store: &mut dyn PreKeyStore,
+    signed_prekey_store: &mut SignedPreKeyDb,
+    kyber_key_store: &mut KyberPreKeyDb,
+    other: ProtocolAddress,
+    cipher_text: Vec<u8>,
+    ty: u8,
+) -> anyhow::Result<Vec<u8>> {
+    let cipher_text_type = CiphertextMessageType::try_from(ty)?;
+
+    let remote_address = other;
+    let mut rng = utils::rng();
+
+    match cipher_text_type {
+        CiphertextMessageType::Whisper => {
+            let message = SignalMessage::try_from(cipher_text.as_ref())?;
+
+            let decrypted = message_decrypt_signal(
+                &message,
+                &remote_address,
+                session_store,
+                identity_store,
+                &mut rng,
+            )
+            .await?;
+
+            return Ok(decrypted);
+        }
+        CiphertextMessageType::PreKey => {
+            let message = PreKeySignalMessage::try_from(cipher_text.as_ref())?;
+
+            let decrypted = message_decrypt_prekey(
+                &message,
+                &remote_address,
+                session_store,
+                identity_store,
+                prekey_store,
+                signed_prekey_store,
+                kyber_key_store,
+                &mut rng,
+            )
+            .await?;
+
+            return Ok(decrypted);
+        }
+
+        _ => return Err(anyhow::anyhow!("Invalid message type")),
+    }
+}
+
+/// Keeps the sessions a decryption stores in memory, see
+/// [`KeyStores::decrypt_to_inbox`].
+struct PendingSessions<'a> {
+    db: &'a SessionDb,
+    records: HashMap<String, Vec<u8>>,
+}
+
+#[async_trait::async_trait(?Send)]
+impl SessionStore for PendingSessions<'_> {
+    async fn load_session(
+        &self,
+        address: &ProtocolAddress,
+    ) -> Result<Option<SessionRecord>, SignalProtocolError> {
+        match self.records.get(&address.to_string()) {
+            Some(record) => Ok(Some(SessionRecord::deserialize(record)?)),
+            None => self.db.load_session(address).await,
+        }
+    }
+
+    async fn store_session(
+        &mut self,
+        address: &ProtocolAddress,
+        record: &SessionRecord,
+    ) -> Result<(), SignalProtocolError> {
+        self.records
+            .insert(address.to_string(), record.serialize()?);
+        Ok(())
+    }
+}
+
+/// Keeps the one-time pre keys a decryption uses up until its sessions are
+/// stored, see [`KeyStores::decrypt_to_inbox`].
+struct PendingPreKeys<'a> {
+    db: &'a mut PreKeyDb,
+    removed: Vec<PreKeyId>,
+}
+
+#[async_trait::async_trait(?Send)]
+impl PreKeyStore for PendingPreKeys<'_> {
+    async fn get_pre_key(&self, prekey_id: PreKeyId) -> Result<PreKeyRecord, SignalProtocolError> {
+        self.db.get_pre_key(prekey_id).await
+    }
+
+    async fn save_pre_key(
+        &mut self,
+        prekey_id: PreKeyId,
+        record: &PreKeyRecord,
+    ) -> Result<(), SignalProtocolError> {
+        self.db.save_pre_key(prekey_id, record).await
+    }
+
+    async fn remove_pre_key(&mut self, prekey_id: PreKeyId) -> Result<(), SignalProtocolError> {
+        self.removed.push(prekey_id);
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::{db::setup_pool, utils::get_current_timestamp_millis_since_epoch};
@@ -837,6 +1248,111 @@ mod tests {
         let _ = store.get_full_identity_key_pair().await.unwrap();
     }
 
+    #[tokio::test]
+    async fn test_identity_trust_on_first_use() {
+        let pool = setup_pool(DB_URI, 1).await.unwrap();
+        let mut store = IdentityDb::new(pool.clone()).await.unwrap();
+
+        let address = ProtocolAddress::new("bob_1".to_string(), DeviceId::new(2).unwrap());
+        let first = *IdentityKeyPair::generate(&mut utils::rng()).identity_key();
+        let second = *IdentityKeyPair::generate(&mut utils::rng()).identity_key();
+
+        assert!(
+            store
+                .is_trusted_identity(&address, &first, Direction::Sending)
+                .await
+                .unwrap()
+        );
+        let change = store.save_identity(&address, &first).await.unwrap();
+        assert!(matches!(change, IdentityChange::NewOrUnchanged));
+
+        let trust = store.get_trust("bob_1").await.unwrap();
+        assert_eq!(trust.len(), 1);
+        assert_eq!(trust[0].device_id, 2);
+        assert_eq!(trust[0].state, IdentityTrustState::Unverified);
+
+        // a new key is accepted for incoming messages but blocks sending
+        let change = store.save_identity(&address, &second).await.unwrap();
+        assert!(matches!(change, IdentityChange::ReplacedExisting));
+        assert!(store.has_changed_identity("bob_1").await.unwrap());
+        assert!(!store.has_changed_identity("bob").await.unwrap());
+        assert!(
+            !store
+                .is_trusted_identity(&address, &second, Direction::Sending)
+                .await
+                .unwrap()
+        );
+        assert!(
+            store
+                .is_trusted_identity(&address, &second, Direction::Receiving)
+                .await
+                .unwrap()
+        );
+        assert!(store.set_verified(&address, true).await.is_err());
+
+        assert_eq!(
+            store.take_pending_identity_changes().await.unwrap(),
+            vec!["bob_1".to_string()]
+        );
+        assert!(
+            store
+                .take_pending_identity_changes()
+                .await
+                .unwrap()
+                .is_empty()
+        );
+
+        store.acknowledge_identity_change("bob_1").await.unwrap();
+        assert!(
+            store
+                .is_trusted_identity(&address, &second, Direction::Sending)
+                .await
+                .unwrap()
+        );
+
+        store.set_verified(&address, true).await.unwrap();
+        assert_eq!(
+            store.get_trust("bob_1").await.unwrap()[0].state,
+            IdentityTrustState::Verified
+        );
+    }
+
+    #[tokio::test]
+    async fn test_safety_number_matches_on_both_sides() {
+        let alice = IdentityDb::new(setup_pool(DB_URI, 1).await.unwrap())
+            .await
+            .unwrap();
+        let mut bob = IdentityDb::new(setup_pool(DB_URI, 1).await.unwrap())
+            .await
+            .unwrap();
+        let mut alice_mut = alice.clone();
+
+        let alice_row = alice.get_full_identity_key_pair().await.unwrap();
+        let bob_row = bob.get_full_identity_key_pair().await.unwrap();
+        let alice_address = ProtocolAddress::new(
+            "alice".to_string(),
+            DeviceId::new(alice_row.device_id).unwrap(),
+        );
+        let bob_address =
+            ProtocolAddress::new("bob".to_string(), DeviceId::new(bob_row.device_id).unwrap());
+
+        alice_mut
+            .save_identity(&bob_address, bob_row.keypair.identity_key())
+            .await
+            .unwrap();
+        bob.save_identity(&alice_address, alice_row.keypair.identity_key())
+            .await
+            .unwrap();
+
+        let from_alice = alice
+            .get_safety_number("alice", &bob_address)
+            .await
+            .unwrap();
+        let from_bob = bob.get_safety_number("bob", &alice_address).await.unwrap();
+        assert_eq!(from_alice.len(), 60);
+        assert_eq!(from_alice, from_bob);
+    }
+
     #[tokio::test]
     async fn test_kyber_prekey_store() {
         let pool = setup_pool(DB_URI, 1).await.unwrap();
@@ -962,4 +1478,116 @@ mod tests {
         test_encryption(&mut charles, "charles", &mut bob, "bob", bob_bundle.clone()).await;
         test_encryption(&mut alice, "alice", &mut bob, "bob", bob_bundle2.clone()).await;
     }
+
+    #[tokio::test]
+    async fn test_decrypt_to_inbox() {
+        let mut alice = KeyStores::new(setup_pool(DB_URI, 1).await.unwrap())
+            .await
+            .unwrap();
+        let mut bob = KeyStores::new(setup_pool(DB_URI, 1).await.unwrap())
+            .await
+            .unwrap();
+
+        let alice_row = alice
+            .identity_store
+            .get_full_identity_key_pair()
+            .await
+            .unwrap();
+        let bob_row = bob
+            .identity_store
+            .get_full_identity_key_pair()
+            .await
+            .unwrap();
+        let alice_address = ProtocolAddress::new(
+            "alice".to_string(),
+            DeviceId::new(alice_row.device_id).unwrap(),
+        );
+        let bob_address =
+            ProtocolAddress::new("bob".to_string(), DeviceId::new(bob_row.device_id).unwrap());
+
+        let bob_bundle = bob.generate_prekey_bundle().await.unwrap();
+        let pre_key_id = PreKeyId::from(bob_bundle.pre_key_id);
+        alice
+            .process_pre_key_bundle("bob".into(), bob_bundle)
+            .await
+            .unwrap();
+
+        let first = alice
+            .encrypt(bob_address.clone(), b"first".to_vec())
+            .await
+            .unwrap();
+        let decrypted = bob
+            .decrypt_to_inbox(
+                1,
+                alice_address.clone(),
+                first.cipher_text.clone(),
+                first.ty,
+            )
+            .await
+            .unwrap();
+        assert_eq!(decrypted, b"first");
+        // the one-time pre key went with the session
+        assert!(bob.prekey_store.get_pre_key(pre_key_id).await.is_err());
+
+        // replayed before the cursor moved, the ratchet already did
+        let replayed = bob
+            .decrypt_to_inbox(
+                1,
+                alice_address.clone(),
+                first.cipher_text.clone(),
+                first.ty,
+            )
+            .await
+            .unwrap();
+        assert_eq!(replayed, b"first");
+
+        // another sender picking the same id isn't answered from the inbox
+        let mut charles = KeyStores::new(setup_pool(DB_URI, 1).await.unwrap())
+            .await
+            .unwrap();
+        let charles_row = charles
+            .identity_store
+            .get_full_identity_key_pair()
+            .await
+            .unwrap();
+        let charles_address = ProtocolAddress::new(
+            "charles".to_string(),
+            DeviceId::new(charles_row.device_id).unwrap(),
+        );
+        charles
+            .process_pre_key_bundle("bob".into(), bob.generate_prekey_bundle().await.unwrap())
+            .await
+            .unwrap();
+        let from_charles = charles
+            .encrypt(bob_address.clone(), b"from charles".to_vec())
+            .await
+            .unwrap();
+        let decrypted = bob
+            .decrypt_to_inbox(
+                1,
+                charles_address,
+                from_charles.cipher_text,
+                from_charles.ty,
+            )
+            .await
+            .unwrap();
+        assert_eq!(decrypted, b"from charles");
+
+        bob.clear_inbox_through(1).await.unwrap();
+        assert!(
+            bob.decrypt_to_inbox(1, alice_address.clone(), first.cipher_text, first.ty)
+                .await
+                .is_err()
+        );
+
+        let second = alice
+            .encrypt(bob_address, b"second".to_vec())
+            .await
+            .unwrap();
+        let decrypted = bob
+            .decrypt_to_inbox(2, alice_address, second.cipher_text, second.ty)
+            .await
+            .unwrap();
+        assert_eq!(decrypted, b"second");
+    }
 }
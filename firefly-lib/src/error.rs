@@ -1,44 +1,252 @@
-use std::{backtrace::Backtrace, error::Error, fmt};
-use serde::Serialize;
+use std::{error::Error, fmt};
 
-#[derive(Debug, Serialize)]
-pub enum DumbError {
-    Generic(String, String),
+use libsignal_protocol::SignalProtocolError;
+use serde::{Serialize, ser::SerializeStruct};
+
+/// Errors crossing the FFI boundary. Serializes to
+/// `{kind, message, retryable}`, plus `code` for server errors.
+///
+/// Deliberately not a `std::error::Error`, so any error converts into it with
+/// `?`. Inside the library it travels in an `anyhow::Error` and is recovered by
+/// `from_anyhow`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FireflyError {
+    /// The server couldn't be reached, the request timed out or there is no
+    /// connection.
+    Network(String),
+    /// The session can't be refreshed anymore, the user has to log in again.
+    AuthExpired(String),
+    /// No tokens were stored yet.
+    NotLoggedIn(String),
+    /// The server rejected the request, `code` is the HTTP status or the
+    /// `error_code` of a websocket response.
+    Server {
+        code: u32,
+        message: String,
+    },
+    Crypto(String),
+    /// The identity key of a contact changed and wasn't acknowledged.
+    UntrustedIdentity(String),
+    Storage(String),
+    NotFound(String),
+    InvalidInput(String),
+    Other(String),
+}
+
+impl FireflyError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Network(_) => "network",
+            Self::AuthExpired(_) => "authExpired",
+            Self::NotLoggedIn(_) => "notLoggedIn",
+            Self::Server { .. } => "server",
+            Self::Crypto(_) => "crypto",
+            Self::UntrustedIdentity(_) => "untrustedIdentity",
+            Self::Storage(_) => "storage",
+            Self::NotFound(_) => "notFound",
+            Self::InvalidInput(_) => "invalidInput",
+            Self::Other(_) => "other",
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            Self::Network(message)
+            | Self::AuthExpired(message)
+            | Self::NotLoggedIn(message)
+            | Self::Server { message, .. }
+            | Self::Crypto(message)
+            | Self::UntrustedIdentity(message)
+            | Self::Storage(message)
+            | Self::NotFound(message)
+            | Self::InvalidInput(message)
+            | Self::Other(message) => message,
+        }
+    }
+
+    fn message_mut(&mut self) -> &mut String {
+        match self {
+            Self::Network(message)
+            | Self::AuthExpired(message)
+            | Self::NotLoggedIn(message)
+            | Self::Server { message, .. }
+            | Self::Crypto(message)
+            | Self::UntrustedIdentity(message)
+            | Self::Storage(message)
+            | Self::NotFound(message)
+            | Self::InvalidInput(message)
+            | Self::Other(message) => message,
+        }
+    }
+
+    /// Whether the same call may succeed later without the user doing
+    /// anything.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Network(_) => true,
+            Self::Server { code, .. } => *code >= 500 || *code == 408 || *code == 429,
+            _ => false,
+        }
+    }
+
+    /// Prefixes the message with `context`, the kind stays.
+    pub fn context(mut self, context: impl fmt::Display) -> Self {
+        let message = self.message_mut();
+        *message = format!("{}: {}", context, message);
+        self
+    }
+
+    /// Picks the kind from the first error in the chain that tells it, the
+    /// message keeps the whole chain.
+    pub fn from_anyhow(err: anyhow::Error) -> Self {
+        let message = format!("{:#}", err);
+
+        let mut error = match err.downcast_ref::<FireflyError>() {
+            Some(error) => error.clone(),
+            None => err
+                .chain()
+                .find_map(classify)
+                .unwrap_or(Self::Other(String::new())),
+        };
+        *error.message_mut() = message;
+        error
+    }
+}
+
+fn classify(err: &(dyn Error + 'static)) -> Option<FireflyError> {
+    if let Some(err) = err.downcast_ref::<reqwest::Error>() {
+        return Some(match err.status() {
+            Some(status) => FireflyError::Server {
+                code: status.as_u16() as u32,
+                message: String::new(),
+            },
+            None if err.is_decode() || err.is_builder() => return None,
+            None => FireflyError::Network(String::new()),
+        });
+    }
+
+    if err.is::<tokio_tungstenite::tungstenite::Error>() || err.is::<tokio::time::error::Elapsed>()
+    {
+        return Some(FireflyError::Network(String::new()));
+    }
+
+    if let Some(err) = err.downcast_ref::<sqlx::Error>() {
+        return Some(match err {
+            sqlx::Error::RowNotFound => FireflyError::NotFound(String::new()),
+            _ => FireflyError::Storage(String::new()),
+        });
+    }
+
+    if let Some(err) = err.downcast_ref::<SignalProtocolError>() {
+        return Some(match err {
+            SignalProtocolError::UntrustedIdentity(_) => {
+                FireflyError::UntrustedIdentity(String::new())
+            }
+            _ => FireflyError::Crypto(String::new()),
+        });
+    }
+
+    None
 }
 
 // Catch-all: anything that implements Display (and Error)
-impl<E> From<E> for DumbError
+impl<E> From<E> for FireflyError
 where
     E: Error + Send + Sync + 'static,
 {
     fn from(err: E) -> Self {
-        Self::new(err.to_string())
+        let mut error = classify(&err).unwrap_or(Self::Other(String::new()));
+        *error.message_mut() = err.to_string();
+        error
     }
 }
 
-impl DumbError {
-    pub fn new(arg: impl Into<String>) -> Self {
-        let bt = Backtrace::force_capture();
-
-        let bt_str = bt.to_string();
-
-        Self::Generic(arg.into(), bt_str)
+impl From<FireflyError> for anyhow::Error {
+    fn from(err: FireflyError) -> Self {
+        anyhow::Error::msg(err)
     }
+}
 
-    pub fn from_anyhow(err: anyhow::Error) -> Self {
-        Self::Generic(err.to_string(), err.backtrace().to_string())
+impl fmt::Display for FireflyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.message())
     }
 }
 
-impl fmt::Display for DumbError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            DumbError::Generic(err, bt) => {
-                f.write_str(&err)?;
-                f.write_str("\nBacktrace: \n")?;
-                f.write_str(&bt)?;
-                Ok(())
-            }
+impl Serialize for FireflyError {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let code = match self {
+            Self::Server { code, .. } => Some(*code),
+            _ => None,
+        };
+
+        let mut state = serializer.serialize_struct("FireflyError", 3 + code.is_some() as usize)?;
+        state.serialize_field("kind", self.kind())?;
+        state.serialize_field("message", self.message())?;
+        state.serialize_field("retryable", &self.is_retryable())?;
+        if let Some(code) = code {
+            state.serialize_field("code", &code)?;
         }
+        state.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Context;
+
+    use super::*;
+
+    #[test]
+    fn test_kind_survives_context() {
+        let err = anyhow::Error::from(FireflyError::Network("not connected".to_string()))
+            .context("failed to send");
+
+        let err = FireflyError::from_anyhow(err);
+        assert_eq!(
+            err,
+            FireflyError::Network("failed to send: not connected".to_string())
+        );
+        assert!(err.is_retryable());
+    }
+
+    #[test]
+    fn test_kind_from_source_error() {
+        let err = FireflyError::from_anyhow(
+            Err::<(), _>(sqlx::Error::RowNotFound)
+                .context("no group 4")
+                .unwrap_err(),
+        );
+        assert_eq!(err.kind(), "notFound");
+
+        let err = FireflyError::from_anyhow(anyhow::anyhow!("something else"));
+        assert_eq!(err, FireflyError::Other("something else".to_string()));
+    }
+
+    #[test]
+    fn test_serialize() {
+        let err = FireflyError::Server {
+            code: 503,
+            message: "unavailable".to_string(),
+        };
+        assert_eq!(
+            serde_json::to_value(&err).unwrap(),
+            serde_json::json!({
+                "kind": "server",
+                "message": "unavailable",
+                "retryable": true,
+                "code": 503,
+            })
+        );
+
+        let err = FireflyError::InvalidInput("bad".to_string()).context("Failed to react");
+        assert_eq!(
+            serde_json::to_value(&err).unwrap(),
+            serde_json::json!({
+                "kind": "invalidInput",
+                "message": "Failed to react: bad",
+                "retryable": false,
+            })
+        );
     }
 }
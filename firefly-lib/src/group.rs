@@ -23,7 +23,7 @@ use crate::{
         group_stores::{GroupInfoStore, GroupKeyPackageStore, GroupPskStore, GroupStateStore},
         keyvalue::KeyValueStore,
     },
-    error::DumbError,
+    error::FireflyError,
 };
 
 pub struct EncryptedGroupMessage {
@@ -175,9 +175,11 @@ impl FfiMlsClient {
         self.client.get_identity().signing_identity()
     }
 
-    pub async fn create_group(&self, group_name: String) -> Result<Arc<FfiMlsGroup>, DumbError> {
+    pub async fn create_group(&self, group_name: String) -> Result<Arc<FfiMlsGroup>, FireflyError> {
         let Some(username) = self.username() else {
-            return Err(DumbError::new("user not authenticated"));
+            return Err(FireflyError::NotLoggedIn(
+                "user not authenticated".to_string(),
+            ));
         };
 
         let mut ext = FireflyGroupExtensionWrapper::new(Default::default());
@@ -199,7 +201,7 @@ impl FfiMlsClient {
             .client
             .create_group(ext.inner().clone())
             .await
-            .map_err(DumbError::from_anyhow)?;
+            .map_err(FireflyError::from_anyhow)?;
 
         self.group_info_state
             .set(
@@ -209,10 +211,10 @@ impl FfiMlsClient {
                 group
                     .group_identifier()
                     .await
-                    .map_err(DumbError::from_anyhow)?,
+                    .map_err(FireflyError::from_anyhow)?,
             )
             .await
-            .map_err(DumbError::from_anyhow)?;
+            .map_err(FireflyError::from_anyhow)?;
         let group = FfiMlsGroup {
             group,
             base_url: self.base_url.clone(),
@@ -228,23 +230,23 @@ impl FfiMlsClient {
         Ok(group)
     }
 
-    pub async fn generate_key_package(&self) -> Result<Vec<u8>, DumbError> {
+    pub async fn generate_key_package(&self) -> Result<Vec<u8>, FireflyError> {
         self.client
             .generate_key_package()
             .await
-            .map_err(DumbError::from_anyhow)
+            .map_err(FireflyError::from_anyhow)
     }
 
     pub async fn join_group(
         &self,
         group_id: u64,
         welcome_message: Vec<u8>,
-    ) -> Result<Arc<FfiMlsGroup>, DumbError> {
+    ) -> Result<Arc<FfiMlsGroup>, FireflyError> {
         let group = self
             .client
             .join_group(group_id, welcome_message)
             .await
-            .map_err(DumbError::from_anyhow)?;
+            .map_err(FireflyError::from_anyhow)?;
 
         let group = Arc::new(FfiMlsGroup {
             group,
@@ -264,7 +266,7 @@ impl FfiMlsClient {
         &self,
         group_id: u64,
         group_identifier: Vec<u8>,
-    ) -> Result<Arc<FfiMlsGroup>, DumbError> {
+    ) -> Result<Arc<FfiMlsGroup>, FireflyError> {
         if let Some(group) = self.loaded_groups.lock().unwrap().get(&group_id).cloned() {
             return Ok(group);
         }
@@ -273,7 +275,7 @@ impl FfiMlsClient {
             .client
             .load_group(group_id, group_identifier)
             .await
-            .map_err(DumbError::from_anyhow)?;
+            .map_err(FireflyError::from_anyhow)?;
         let group = Arc::new(FfiMlsGroup {
             group,
             base_url: self.base_url.clone(),
@@ -287,47 +289,53 @@ impl FfiMlsClient {
         Ok(group)
     }
 
-    pub async fn is_valid_until_secs(&self) -> Result<u64, DumbError> {
+    pub async fn is_valid_until_secs(&self) -> Result<u64, FireflyError> {
         self.client
             .is_valid_until_secs()
             .await
-            .map_err(DumbError::from_anyhow)
+            .map_err(FireflyError::from_anyhow)
     }
 }
 
 impl FfiMlsGroup {
-    pub async fn state(&self) -> Result<Vec<u8>, DumbError> {
-        self.group.state().await.map_err(DumbError::from_anyhow)
+    pub async fn state(&self) -> Result<Vec<u8>, FireflyError> {
+        self.group.state().await.map_err(FireflyError::from_anyhow)
     }
 
-    pub async fn extension(&self) -> Result<Vec<u8>, DumbError> {
-        self.group.extension().await.map_err(DumbError::from_anyhow)
+    pub async fn extension(&self) -> Result<Vec<u8>, FireflyError> {
+        self.group
+            .extension()
+            .await
+            .map_err(FireflyError::from_anyhow)
     }
 
-    pub async fn save(&self) -> Result<(), DumbError> {
-        self.group.save().await.map_err(DumbError::from_anyhow)
+    pub async fn save(&self) -> Result<(), FireflyError> {
+        self.group.save().await.map_err(FireflyError::from_anyhow)
     }
 
-    pub async fn encrypt(&self, data: Vec<u8>) -> Result<Vec<u8>, DumbError> {
+    pub async fn encrypt(&self, data: Vec<u8>) -> Result<Vec<u8>, FireflyError> {
         self.group
             .encrypt(&data)
             .await
-            .map_err(DumbError::from_anyhow)
+            .map_err(FireflyError::from_anyhow)
     }
 
-    pub async fn re_add_member(&self, username: String, address: u64) -> Result<u64, DumbError> {
+    pub async fn re_add_member(&self, username: String, address: u64) -> Result<u64, FireflyError> {
         self.group
             .re_add_member(username, address)
             .await
-            .map_err(DumbError::from_anyhow)
+            .map_err(FireflyError::from_anyhow)
     }
 
-    pub async fn process(&self, message: Vec<u8>) -> Result<FireflyMlsReceivedMessage, DumbError> {
+    pub async fn process(
+        &self,
+        message: Vec<u8>,
+    ) -> Result<FireflyMlsReceivedMessage, FireflyError> {
         let result = self
             .group
             .process(&message)
             .await
-            .map_err(DumbError::from_anyhow)?;
+            .map_err(FireflyError::from_anyhow)?;
         Ok(match result {
             firefly_core::FireflyMlsReceivedMessage::Message(msg) => {
                 FireflyMlsReceivedMessage::Message(EncryptedGroupMessage {
@@ -336,7 +344,7 @@ impl FfiMlsGroup {
                 })
             }
             firefly_core::FireflyMlsReceivedMessage::Commit => {
-                self.group.save().await.map_err(DumbError::from_anyhow)?;
+                self.group.save().await.map_err(FireflyError::from_anyhow)?;
                 FireflyMlsReceivedMessage::Commit
             }
 
@@ -368,14 +376,17 @@ impl FfiMlsGroup {
         name: String,
         channel_ty: u8,
         default_permissions: u32,
-    ) -> Result<u64, DumbError> {
+    ) -> Result<u64, FireflyError> {
         self.group
             .update_channel(id, delete, name, channel_ty, default_permissions)
             .await
-            .map_err(DumbError::from_anyhow)
+            .map_err(FireflyError::from_anyhow)
     }
 
-    pub async fn update_roles(&self, roles: Vec<UpdateRoleProposalFfi>) -> Result<u64, DumbError> {
+    pub async fn update_roles(
+        &self,
+        roles: Vec<UpdateRoleProposalFfi>,
+    ) -> Result<u64, FireflyError> {
         self.group
             .update_roles(roles.into_iter().map(|role| UpdateRoleProposal {
                 role_id: role.role_id,
@@ -384,24 +395,27 @@ impl FfiMlsGroup {
                 delete: role.delete,
             }))
             .await
-            .map_err(DumbError::from_anyhow)
+            .map_err(FireflyError::from_anyhow)
     }
 
-    pub async fn update_users(&self, users: Vec<UpdateUserProposalFfi>) -> Result<u64, DumbError> {
+    pub async fn update_users(
+        &self,
+        users: Vec<UpdateUserProposalFfi>,
+    ) -> Result<u64, FireflyError> {
         self.group
             .update_users(users.into_iter().map(|user| UpdateUserProposal {
                 username: user.username,
                 role_id: user.role_id,
             }))
             .await
-            .map_err(DumbError::from_anyhow)
+            .map_err(FireflyError::from_anyhow)
     }
 
     pub async fn update_roles_in_channel(
         &self,
         channel_id: u32,
         roles: Vec<UpdateRoleProposalFfi>,
-    ) -> Result<u64, DumbError> {
+    ) -> Result<u64, FireflyError> {
         self.group
             .update_roles_in_channel(roles.into_iter().map(|x| UpdateRoleInChannelProposal {
                 channel_id,
@@ -413,14 +427,14 @@ impl FfiMlsGroup {
                 },
             }))
             .await
-            .map_err(DumbError::from_anyhow)
+            .map_err(FireflyError::from_anyhow)
     }
 
-    pub async fn group_identifier(&self) -> Result<Vec<u8>, DumbError> {
+    pub async fn group_identifier(&self) -> Result<Vec<u8>, FireflyError> {
         self.group
             .group_identifier()
             .await
-            .map_err(DumbError::from_anyhow)
+            .map_err(FireflyError::from_anyhow)
     }
 
     pub fn group_id(&self) -> u64 {
@@ -1,23 +1,32 @@
+use std::sync::Arc;
+
 use log::LevelFilter;
 
-use crate::{error::DumbError, logger::TeeLogger, pb::firefly::firefly};
+use crate::{api::FireflyApi, error::FireflyError, logger::TeeLogger, pb::firefly::firefly};
 
+pub mod api;
+pub mod attachments;
+pub mod backoff;
 pub mod db;
 pub mod error;
 pub mod group;
 pub mod logger;
+pub mod media;
+#[cfg(test)]
+mod mock_server;
 pub mod pb;
 pub mod schema;
+pub mod transport;
 pub mod utils;
 pub mod websocket;
 
-#[derive( Debug)]
+#[derive(Debug)]
 pub struct EncryptedMessage {
     pub cipher_text: Vec<u8>,
     pub ty: u8,
 }
 
-#[derive( Clone)]
+#[derive(Clone)]
 pub struct FfiPreKeyBundle {
     registration_id: u32,
     device_id: u8,
@@ -112,22 +121,26 @@ fn set_panic_handler() {
 
 pub struct FfiFileServer {
     server: tokio::sync::Mutex<shfs::FileServer>,
+    base_path: std::path::PathBuf,
 }
 
 impl FfiFileServer {
     pub fn create(base_path: String, token: String) -> Self {
-        let server = tokio::sync::Mutex::new(shfs::FileServer::new(base_path, token));
+        let server = tokio::sync::Mutex::new(shfs::FileServer::new(base_path.clone(), token));
 
-        Self { server }
+        Self {
+            server,
+            base_path: base_path.into(),
+        }
     }
 
-    pub async fn start_serving(&self, port: Option<u16>) -> Result<u16, DumbError> {
+    pub async fn start_serving(&self, port: Option<u16>) -> Result<u16, FireflyError> {
         self.server
             .lock()
             .await
             .serve(port)
             .await
-            .map_err(|e| DumbError::new(e.to_string()))
+            .map_err(|e| FireflyError::Network(e.to_string()))
     }
 
     pub async fn token(&self) -> String {
@@ -137,4 +150,19 @@ impl FfiFileServer {
     pub async fn port(&self) -> String {
         self.server.lock().await.port().to_string()
     }
+
+    /// Downloads and decrypts an attachment through `api` into the served
+    /// directory, returns `file_name`, the path it is served under.
+    pub async fn download_attachment(
+        &self,
+        api: Arc<dyn FireflyApi>,
+        file: firefly::EncryptedFile,
+        file_name: String,
+    ) -> Result<String, FireflyError> {
+        attachments::download_attachment(api.as_ref(), &file, &self.base_path, &file_name)
+            .await
+            .map_err(FireflyError::from_anyhow)?;
+
+        Ok(file_name)
+    }
 }
@@ -0,0 +1,407 @@
+use std::{
+    io::{Cursor, Read, Seek, SeekFrom},
+    path::PathBuf,
+};
+
+use image::{DynamicImage, ImageReader, codecs::jpeg::JpegEncoder};
+
+use crate::pb::firefly::firefly::{MediaMetadata, MessagePayload};
+
+/// Longest side of a generated thumbnail.
+pub const THUMBNAIL_MAX_DIMENSION: u32 = 320;
+/// Thumbnails travel inside the message, larger ones are dropped on receive.
+pub const MAX_THUMBNAIL_LEN: usize = 48 * 1024;
+const THUMBNAIL_JPEG_QUALITY: u8 = 60;
+/// Images above this size only get their dimensions read, not decoded.
+const MAX_THUMBNAIL_SOURCE_LEN: u64 = 32 * 1024 * 1024;
+const MAX_MIME_TYPE_LEN: usize = 128;
+const MAX_MP4_BOX_DEPTH: u32 = 8;
+
+/// MIME type of a file from its first bytes, 16 are enough for every
+/// recognized format.
+pub fn sniff_mime_type(header: &[u8]) -> Option<&'static str> {
+    let at = |offset: usize, magic: &[u8]| header.get(offset..offset + magic.len()) == Some(magic);
+
+    let mime_type = if at(0, &[0xFF, 0xD8, 0xFF]) {
+        "image/jpeg"
+    } else if at(0, b"\x89PNG\r\n\x1a\n") {
+        "image/png"
+    } else if at(0, b"GIF87a") || at(0, b"GIF89a") {
+        "image/gif"
+    } else if at(0, b"RIFF") && at(8, b"WEBP") {
+        "image/webp"
+    } else if at(4, b"ftypqt  ") {
+        "video/quicktime"
+    } else if at(4, b"ftyp") {
+        "video/mp4"
+    } else if at(0, &[0x1A, 0x45, 0xDF, 0xA3]) {
+        "video/webm"
+    } else if at(0, b"%PDF-") {
+        "application/pdf"
+    } else {
+        return None;
+    };
+    Some(mime_type)
+}
+
+pub fn is_image(mime_type: &str) -> bool {
+    mime_type.starts_with("image/")
+}
+
+/// Small JPEG of `image`, `None` if even a reduced one doesn't fit
+/// `MAX_THUMBNAIL_LEN`.
+pub fn make_thumbnail(image: &DynamicImage) -> anyhow::Result<Option<Vec<u8>>> {
+    for max_dimension in [THUMBNAIL_MAX_DIMENSION, THUMBNAIL_MAX_DIMENSION / 2] {
+        let thumbnail = image.thumbnail(max_dimension, max_dimension).to_rgb8();
+
+        let mut jpeg = Vec::new();
+        JpegEncoder::new_with_quality(&mut jpeg, THUMBNAIL_JPEG_QUALITY)
+            .encode_image(&thumbnail)?;
+        if jpeg.len() <= MAX_THUMBNAIL_LEN {
+            return Ok(Some(jpeg));
+        }
+    }
+    Ok(None)
+}
+
+/// Dimensions and a thumbnail of the encoded image in `bytes`.
+pub fn image_metadata(bytes: &[u8], mime_type: &str) -> anyhow::Result<MediaMetadata> {
+    let image = ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()?
+        .decode()?;
+
+    Ok(MediaMetadata {
+        mime_type: mime_type.to_string(),
+        width: image.width(),
+        height: image.height(),
+        duration_millis: 0,
+        thumbnail: make_thumbnail(&image)?.unwrap_or_default(),
+    })
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct VideoInfo {
+    pub width: u32,
+    pub height: u32,
+    pub duration_millis: u32,
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(bytes.get(at..at + 8)?.try_into().ok()?))
+}
+
+/// Duration and dimensions from the `mvhd` and the first visual `tkhd` box of
+/// an MP4 or QuickTime file, the boxes are found by seeking, so `moov` may be
+/// anywhere in the file.
+pub fn mp4_info(reader: &mut (impl Read + Seek)) -> anyhow::Result<VideoInfo> {
+    let end = reader.seek(SeekFrom::End(0))?;
+    let mut info = VideoInfo::default();
+    walk_mp4_boxes(reader, 0, end, 0, &mut info)?;
+    Ok(info)
+}
+
+fn walk_mp4_boxes(
+    reader: &mut (impl Read + Seek),
+    start: u64,
+    end: u64,
+    depth: u32,
+    info: &mut VideoInfo,
+) -> anyhow::Result<()> {
+    anyhow::ensure!(depth <= MAX_MP4_BOX_DEPTH, "mp4 boxes nested too deep");
+
+    let mut pos = start;
+    while pos + 8 <= end {
+        reader.seek(SeekFrom::Start(pos))?;
+        let mut header = [0u8; 8];
+        reader.read_exact(&mut header)?;
+
+        let size = read_u32(&header, 0).unwrap_or_default() as u64;
+        let kind = &header[4..8];
+        let (size, header_len) = match size {
+            0 => (end - pos, 8),
+            1 => {
+                let mut large = [0u8; 8];
+                reader.read_exact(&mut large)?;
+                (u64::from_be_bytes(large), 16)
+            }
+            size => (size, 8),
+        };
+        // a crafted 64 bit size must not wrap around past `end`
+        let box_end = pos
+            .checked_add(size)
+            .filter(|box_end| size >= header_len && *box_end <= end)
+            .ok_or_else(|| anyhow::anyhow!("invalid mp4 box size {} at {}", size, pos))?;
+
+        let body_start = pos + header_len;
+        let body_len = size - header_len;
+        match kind {
+            b"moov" | b"trak" => {
+                walk_mp4_boxes(reader, body_start, box_end, depth + 1, info)?;
+            }
+            b"mvhd" => {
+                let body = read_box_body(reader, body_len, 32)?;
+                let (timescale, duration) = if body.first() == Some(&1) {
+                    (read_u32(&body, 20), read_u64(&body, 24))
+                } else {
+                    (read_u32(&body, 12), read_u32(&body, 16).map(u64::from))
+                };
+                if let (Some(timescale), Some(duration)) = (timescale, duration)
+                    && timescale > 0
+                {
+                    let millis = duration as u128 * 1000 / timescale as u128;
+                    info.duration_millis = millis.min(u32::MAX as u128) as u32;
+                }
+            }
+            b"tkhd" if info.width == 0 => {
+                let body = read_box_body(reader, body_len, 96)?;
+                let at = if body.first() == Some(&1) { 88 } else { 76 };
+                // 16.16 fixed point, audio tracks have no size
+                if let (Some(width), Some(height)) = (read_u32(&body, at), read_u32(&body, at + 4))
+                {
+                    info.width = width >> 16;
+                    info.height = height >> 16;
+                }
+            }
+            _ => {}
+        }
+
+        pos = box_end;
+    }
+
+    Ok(())
+}
+
+fn read_box_body(
+    reader: &mut impl Read,
+    body_len: u64,
+    max_len: usize,
+) -> std::io::Result<Vec<u8>> {
+    let mut body = vec![0u8; body_len.min(max_len as u64) as usize];
+    reader.read_exact(&mut body)?;
+    Ok(body)
+}
+
+fn extract_media_metadata_blocking(path: PathBuf) -> anyhow::Result<Option<MediaMetadata>> {
+    let mut file = std::fs::File::open(&path)?;
+    let len = file.metadata()?.len();
+
+    let mut header = Vec::with_capacity(16);
+    (&mut file).take(16).read_to_end(&mut header)?;
+    let Some(mime_type) = sniff_mime_type(&header) else {
+        return Ok(None);
+    };
+    file.seek(SeekFrom::Start(0))?;
+
+    let metadata = if is_image(mime_type) {
+        if len <= MAX_THUMBNAIL_SOURCE_LEN {
+            let mut bytes = Vec::with_capacity(len as usize);
+            file.read_to_end(&mut bytes)?;
+            image_metadata(&bytes, mime_type)?
+        } else {
+            let (width, height) = ImageReader::new(std::io::BufReader::new(file))
+                .with_guessed_format()?
+                .into_dimensions()?;
+            MediaMetadata {
+                mime_type: mime_type.to_string(),
+                width,
+                height,
+                ..Default::default()
+            }
+        }
+    } else if mime_type == "video/mp4" || mime_type == "video/quicktime" {
+        let info = mp4_info(&mut std::io::BufReader::new(file))?;
+        MediaMetadata {
+            mime_type: mime_type.to_string(),
+            width: info.width,
+            height: info.height,
+            duration_millis: info.duration_millis,
+            thumbnail: vec![],
+        }
+    } else {
+        MediaMetadata {
+            mime_type: mime_type.to_string(),
+            ..Default::default()
+        }
+    };
+
+    Ok(Some(metadata))
+}
+
+/// Metadata of the file at `path`, `None` if its type isn't recognized.
+/// Images get a thumbnail. Video thumbnails are out of scope: decoding a frame
+/// needs a video codec the library doesn't ship, so videos only get their
+/// duration and dimensions.
+pub async fn extract_media_metadata(path: PathBuf) -> anyhow::Result<Option<MediaMetadata>> {
+    tokio::task::spawn_blocking(move || extract_media_metadata_blocking(path)).await?
+}
+
+/// Drops what a sender put into the metadata of received files that we
+/// don't show: oversized or non image thumbnails and bogus MIME types.
+/// Returns whether anything changed.
+pub fn sanitize_payload(payload: &mut MessagePayload) -> bool {
+    let Some(files) = payload.files.as_mut() else {
+        return false;
+    };
+
+    let mut changed = false;
+    for metadata in files
+        .files
+        .iter_mut()
+        .filter_map(|file| file.metadata.as_mut())
+    {
+        if !metadata.thumbnail.is_empty()
+            && (metadata.thumbnail.len() > MAX_THUMBNAIL_LEN
+                || !sniff_mime_type(&metadata.thumbnail).is_some_and(is_image))
+        {
+            metadata.thumbnail.clear();
+            changed = true;
+        }
+
+        if metadata.mime_type.len() > MAX_MIME_TYPE_LEN
+            || metadata
+                .mime_type
+                .chars()
+                .any(|c| c.is_control() || c.is_whitespace())
+        {
+            metadata.mime_type.clear();
+            changed = true;
+        }
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pb::firefly::firefly::{EncryptedFile, EncryptedFiles};
+
+    fn mp4_box(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut bytes = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+        bytes.extend_from_slice(kind);
+        bytes.extend_from_slice(body);
+        bytes
+    }
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let image = DynamicImage::new_rgb8(width, height);
+        let mut bytes = Vec::new();
+        image
+            .write_to(&mut Cursor::new(&mut bytes), image::ImageFormat::Png)
+            .unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_sniff_mime_type() {
+        assert_eq!(sniff_mime_type(&png(1, 1)), Some("image/png"));
+        assert_eq!(
+            sniff_mime_type(&[0xFF, 0xD8, 0xFF, 0xE0]),
+            Some("image/jpeg")
+        );
+        assert_eq!(
+            sniff_mime_type(b"\x00\x00\x00\x20ftypisom"),
+            Some("video/mp4")
+        );
+        assert_eq!(
+            sniff_mime_type(b"\x00\x00\x00\x14ftypqt  "),
+            Some("video/quicktime")
+        );
+        assert_eq!(
+            sniff_mime_type(b"RIFF\x00\x00\x00\x00WEBPVP8 "),
+            Some("image/webp")
+        );
+        assert_eq!(sniff_mime_type(b"hello"), None);
+        assert_eq!(sniff_mime_type(&[]), None);
+    }
+
+    #[test]
+    fn test_image_metadata_has_small_thumbnail() {
+        let metadata = image_metadata(&png(1280, 640), "image/png").unwrap();
+
+        assert_eq!((metadata.width, metadata.height), (1280, 640));
+        assert!(metadata.thumbnail.len() <= MAX_THUMBNAIL_LEN);
+        assert_eq!(sniff_mime_type(&metadata.thumbnail), Some("image/jpeg"));
+
+        let thumbnail = image::load_from_memory(&metadata.thumbnail).unwrap();
+        assert_eq!(thumbnail.width(), THUMBNAIL_MAX_DIMENSION);
+        assert_eq!(thumbnail.height(), THUMBNAIL_MAX_DIMENSION / 2);
+    }
+
+    #[test]
+    fn test_mp4_info() {
+        let mut mvhd = vec![0u8; 100];
+        mvhd[12..16].copy_from_slice(&1000u32.to_be_bytes());
+        mvhd[16..20].copy_from_slice(&4500u32.to_be_bytes());
+
+        let audio_tkhd = vec![0u8; 84];
+        let mut video_tkhd = vec![0u8; 84];
+        video_tkhd[76..80].copy_from_slice(&(1920u32 << 16).to_be_bytes());
+        video_tkhd[80..84].copy_from_slice(&(1080u32 << 16).to_be_bytes());
+
+        let mut moov_body = mp4_box(b"mvhd", &mvhd);
+        moov_body.extend(mp4_box(b"trak", &mp4_box(b"tkhd", &audio_tkhd)));
+        moov_body.extend(mp4_box(b"trak", &mp4_box(b"tkhd", &video_tkhd)));
+
+        // moov after the media data, like most recorders write it
+        let mut file = mp4_box(b"ftyp", b"isom\x00\x00\x02\x00");
+        file.extend(mp4_box(b"mdat", &[0u8; 64]));
+        file.extend(mp4_box(b"moov", &moov_body));
+
+        let info = mp4_info(&mut Cursor::new(&file)).unwrap();
+        assert_eq!(
+            info,
+            VideoInfo {
+                width: 1920,
+                height: 1080,
+                duration_millis: 4500,
+            }
+        );
+
+        let mut truncated = file.clone();
+        truncated.truncate(file.len() - 10);
+        assert!(mp4_info(&mut Cursor::new(&truncated)).is_err());
+
+        // a 64 bit size that would wrap around errors instead of panicking
+        let mut huge = mp4_box(b"ftyp", b"isom\x00\x00\x02\x00");
+        huge.extend(1u32.to_be_bytes());
+        huge.extend(b"mdat");
+        huge.extend(u64::MAX.to_be_bytes());
+        assert!(mp4_info(&mut Cursor::new(&huge)).is_err());
+    }
+
+    #[test]
+    fn test_sanitize_payload() {
+        let valid = image_metadata(&png(64, 64), "image/png").unwrap();
+        let file = |metadata: MediaMetadata| EncryptedFile {
+            metadata: Some(metadata),
+            ..Default::default()
+        };
+
+        let mut payload = MessagePayload {
+            files: Some(EncryptedFiles {
+                files: vec![
+                    file(valid.clone()),
+                    file(MediaMetadata {
+                        thumbnail: vec![0u8; 16],
+                        mime_type: "image/png\n".to_string(),
+                        ..Default::default()
+                    }),
+                ],
+            }),
+            ..Default::default()
+        };
+
+        assert!(sanitize_payload(&mut payload));
+        let files = payload.files.as_ref().unwrap();
+        assert_eq!(files.files[0].metadata.as_ref(), Some(&valid));
+        let sanitized = files.files[1].metadata.as_ref().unwrap();
+        assert!(sanitized.thumbnail.is_empty());
+        assert!(sanitized.mime_type.is_empty());
+
+        assert!(!sanitize_payload(&mut payload));
+    }
+}
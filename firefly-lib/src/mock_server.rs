@@ -0,0 +1,1289 @@
+//! In-process stand-in for the Firefly backend, so the networked paths of
+//! `FireflyWsClient` can be tested offline.
+//!
+//! Serves the REST routes and the `ClientMessage`/`ServerMessage` websocket
+//! framing the client uses, backed by in memory state. Routes `firefly_core`
+//! calls on its own, like issuing group credentials, are not known here: they
+//! are answered with 501 and recorded in `MockState::unhandled`, and
+//! `MockFirefly::start_with` mounts extra routes for them.
+
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use axum::{
+    Json, Router,
+    extract::{
+        Path, Query, State,
+        ws::{Message, WebSocket, WebSocketUpgrade},
+    },
+    http::{HeaderMap, Method, StatusCode, Uri, header},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use base64::{Engine, prelude::BASE64_URL_SAFE_NO_PAD};
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use tokio::sync::mpsc;
+
+use crate::{
+    db::auth::get_claims_from_token,
+    pb::firefly::firefly,
+    utils::{deserialize_proto, get_current_timestamp_seconds_since_epoch, serialize_proto},
+};
+
+pub type SharedState = Arc<Mutex<MockState>>;
+
+type Reply = Result<Response, (StatusCode, String)>;
+
+/// Everything the mock server knows, tests inspect and seed it directly.
+#[derive(Default)]
+pub struct MockState {
+    base_url: String,
+    last_id: u64,
+    pub addresses: Vec<firefly::Address>,
+    pub pre_key_bundles: Vec<firefly::PreKeyBundleEntry>,
+    pub conversations: Vec<firefly::Conversation>,
+    pub user_messages: Vec<firefly::UserMessage>,
+    pub groups: HashMap<u64, firefly::Group>,
+    /// address ids of the devices in each group
+    pub group_members: HashMap<u64, HashSet<u64>>,
+    pub group_messages: Vec<firefly::GroupMessage>,
+    /// last reported state of each device in each group, by (address, group)
+    pub group_member_updates: HashMap<(u64, u64), firefly::GroupMemberUpdate>,
+    pub key_packages: Vec<firefly::GroupKeyPackage>,
+    /// pending invites by invitee address
+    pub invites: HashMap<u64, Vec<firefly::GroupInvite>>,
+    pub re_adds: Vec<firefly::GroupReAddRequest>,
+    pub files: HashMap<String, Bytes>,
+    /// `METHOD path?query` of every request no route matched
+    pub unhandled: Vec<String>,
+    connections: HashMap<u64, mpsc::UnboundedSender<firefly::ServerMessage>>,
+}
+
+impl MockState {
+    fn next_id(&mut self) -> u64 {
+        self.last_id += 1;
+        self.last_id
+    }
+
+    pub fn address(&self, id: u64) -> Option<&firefly::Address> {
+        self.addresses.iter().find(|address| address.id == id)
+    }
+
+    pub fn addresses_of(&self, username: &str) -> Vec<firefly::Address> {
+        self.addresses
+            .iter()
+            .filter(|address| address.username == username)
+            .cloned()
+            .collect()
+    }
+
+    pub fn pre_key_bundles_of(&self, address_id: u64) -> Vec<&firefly::PreKeyBundleEntry> {
+        self.pre_key_bundles
+            .iter()
+            .filter(|entry| entry.address == address_id)
+            .collect()
+    }
+
+    /// Creates a group with `members` as its devices, for routes mounted with
+    /// `MockFirefly::start_with` and for tests.
+    pub fn add_group(&mut self, group: firefly::Group, members: impl IntoIterator<Item = u64>) {
+        self.group_members
+            .entry(group.id)
+            .or_default()
+            .extend(members);
+        self.groups.insert(group.id, group);
+    }
+
+    fn own_address(
+        &self,
+        username: &str,
+        address_id: u64,
+    ) -> Result<&firefly::Address, (StatusCode, String)> {
+        match self.address(address_id) {
+            Some(address) if address.username == username => Ok(address),
+            Some(_) => Err((
+                StatusCode::FORBIDDEN,
+                format!("address {} is not of {}", address_id, username),
+            )),
+            None => Err((
+                StatusCode::NOT_FOUND,
+                format!("address {} not found", address_id),
+            )),
+        }
+    }
+
+    fn take_pre_key_bundle(&mut self, address: &firefly::Address) -> firefly::PreKeyBundleEntry {
+        match self
+            .pre_key_bundles
+            .iter()
+            .position(|entry| entry.address == address.id)
+        {
+            Some(index) => self.pre_key_bundles.remove(index),
+            None => firefly::PreKeyBundleEntry {
+                id: 0,
+                address: address.id,
+                bundle: None,
+                username: address.username.clone(),
+                device_id: address.device_id,
+            },
+        }
+    }
+
+    fn send(&self, address_id: u64, message: firefly::server_message::Message) {
+        if let Some(connection) = self.connections.get(&address_id) {
+            let _ = connection.send(firefly::ServerMessage {
+                message: Some(message),
+            });
+        }
+    }
+
+    /// Stores and forwards the messages of an upload. Devices of the addressed
+    /// users that were left out are answered with id 0, so the sender fetches
+    /// their bundles and sends again.
+    fn upload_user_messages(
+        &mut self,
+        from: &firefly::Address,
+        upload: firefly::UploadUserMessage,
+    ) -> firefly::UserMessageUploaded {
+        let mut uploaded = firefly::UserMessageUploaded::default();
+        let mut usernames = HashSet::new();
+        let mut addressed = HashSet::from([from.id]);
+
+        for mut message in upload.messages {
+            let Some(to) = self.address(message.to_id).cloned() else {
+                continue;
+            };
+            message.from_id = from.id;
+            message.from_username = from.username.clone();
+            message.from_device_id = from.device_id;
+
+            self.user_messages.push(message.clone());
+            self.send(
+                to.id,
+                firefly::server_message::Message::UserMessage(message.clone()),
+            );
+
+            uploaded.message_ids.push(firefly::MessageIdAndTo {
+                id: message.id,
+                to: to.id,
+                is_self: to.username == from.username,
+            });
+            addressed.insert(to.id);
+            usernames.insert(to.username);
+        }
+
+        for address in &self.addresses {
+            if usernames.contains(&address.username) && !addressed.contains(&address.id) {
+                uploaded.message_ids.push(firefly::MessageIdAndTo {
+                    id: 0,
+                    to: address.id,
+                    is_self: address.username == from.username,
+                });
+            }
+        }
+
+        uploaded
+    }
+
+    fn upload_group_message(
+        &mut self,
+        from: &firefly::Address,
+        mut message: firefly::GroupMessage,
+    ) -> Result<firefly::GroupMessage, String> {
+        let members = self
+            .group_members
+            .get(&message.group_id)
+            .cloned()
+            .unwrap_or_default();
+        if !members.contains(&from.id) {
+            return Err(format!(
+                "address {} is not a member of group {}",
+                from.id, message.group_id
+            ));
+        }
+
+        message.id = self.next_id();
+        self.group_messages.push(message.clone());
+        for member in members {
+            if member != from.id {
+                self.send(
+                    member,
+                    firefly::server_message::Message::GroupMessage(message.clone()),
+                );
+            }
+        }
+
+        Ok(message)
+    }
+}
+
+pub struct MockFirefly {
+    pub base_url: String,
+    pub ws_url: String,
+    state: SharedState,
+    server: tokio::task::JoinHandle<()>,
+}
+
+impl Drop for MockFirefly {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+impl MockFirefly {
+    pub async fn start() -> anyhow::Result<Self> {
+        Self::start_with(Router::new()).await
+    }
+
+    /// Starts the server with `extra` routes added to the known ones.
+    pub async fn start_with(extra: Router<SharedState>) -> anyhow::Result<Self> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let local_addr = listener.local_addr()?;
+        let base_url = format!("http://{}", local_addr);
+        let ws_url = format!("ws://{}/ws", local_addr);
+
+        let state: SharedState = Arc::new(Mutex::new(MockState {
+            base_url: base_url.clone(),
+            ..Default::default()
+        }));
+
+        let fallback_state = state.clone();
+        let router = Router::new()
+            .route("/oauth/token", post(refresh_token))
+            .route("/ws", get(connect))
+            .route(
+                "/user/device",
+                post(register_device).delete(deregister_device),
+            )
+            .route(
+                "/user/preKeyBundles",
+                get(get_pre_key_bundles)
+                    .post(upload_pre_key_bundles)
+                    .delete(delete_pre_key_bundles),
+            )
+            .route("/user/conversation", post(update_conversation))
+            .route("/user/conversations", get(get_conversations))
+            .route("/groups", get(get_groups))
+            .route("/group", get(get_group))
+            .route("/group/sync", post(sync_group_messages))
+            .route("/group/syncUpdate", post(sync_group_updates))
+            .route("/group/member", post(add_group_member))
+            .route(
+                "/group/keyPackages",
+                get(get_key_packages)
+                    .post(upload_key_packages)
+                    .delete(delete_key_packages),
+            )
+            .route("/group/invites", get(get_invites).delete(delete_invites))
+            .route("/group/reAdd", post(request_re_adds).delete(delete_re_add))
+            .route("/group/reAdds", get(get_re_adds))
+            .route("/file", post(upload_file))
+            .route("/file/{name}", get(download_file))
+            .merge(extra)
+            .fallback(move |method: Method, uri: Uri| {
+                log::warn!("mock firefly: no route for {} {}", method, uri);
+                fallback_state
+                    .lock()
+                    .unwrap()
+                    .unhandled
+                    .push(format!("{} {}", method, uri));
+                async { StatusCode::NOT_IMPLEMENTED }
+            })
+            .with_state(state.clone());
+
+        let server = tokio::spawn(async move {
+            if let Err(err) = axum::serve(listener, router).await {
+                log::error!("mock firefly server stopped: {}", err);
+            }
+        });
+
+        Ok(Self {
+            base_url,
+            ws_url,
+            state,
+            server,
+        })
+    }
+
+    pub fn state(&self) -> MutexGuard<'_, MockState> {
+        self.state.lock().unwrap()
+    }
+
+    /// An unsigned access token with the claims the client reads, valid for
+    /// an hour. The mock accepts any token that decodes.
+    pub fn token(username: &str) -> String {
+        let claims = serde_json::json!({
+            "uname": username,
+            "perms": 0,
+            "exp": get_current_timestamp_seconds_since_epoch() + 3600,
+        });
+        format!(
+            "{}.{}.mock",
+            BASE64_URL_SAFE_NO_PAD.encode(br#"{"alg":"none"}"#),
+            BASE64_URL_SAFE_NO_PAD.encode(claims.to_string())
+        )
+    }
+}
+
+fn proto<T: prost::Message>(message: &T) -> Reply {
+    serialize_proto(message)
+        .map(IntoResponse::into_response)
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))
+}
+
+fn decode<T: prost::Message + Default>(body: &[u8]) -> Result<T, (StatusCode, String)> {
+    deserialize_proto(body).map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))
+}
+
+fn username_of_token(token: &str) -> Result<String, (StatusCode, String)> {
+    get_claims_from_token(token)
+        .map(|claims| claims.uname)
+        .map_err(|err| (StatusCode::UNAUTHORIZED, err.to_string()))
+}
+
+fn username(headers: &HeaderMap) -> Result<String, (StatusCode, String)> {
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or((StatusCode::UNAUTHORIZED, "missing token".to_string()))?;
+
+    username_of_token(token)
+}
+
+fn param<T: FromStr>(
+    params: &HashMap<String, String>,
+    name: &str,
+) -> Result<T, (StatusCode, String)> {
+    params
+        .get(name)
+        .and_then(|value| value.parse().ok())
+        .ok_or((StatusCode::BAD_REQUEST, format!("invalid {}", name)))
+}
+
+/// Comma separated list, the client encodes the commas as `%2C`.
+fn param_list<T: FromStr>(
+    params: &HashMap<String, String>,
+    name: &str,
+) -> Result<Vec<T>, (StatusCode, String)> {
+    let value = params
+        .get(name)
+        .ok_or((StatusCode::BAD_REQUEST, format!("missing {}", name)))?;
+
+    value
+        .split(',')
+        .filter(|item| !item.is_empty())
+        .map(|item| {
+            item.parse()
+                .map_err(|_| (StatusCode::BAD_REQUEST, format!("invalid {}", name)))
+        })
+        .collect()
+}
+
+/// The refresh token handed out is the username, refreshing just issues a new
+/// access token for it.
+async fn refresh_token(Json(body): Json<serde_json::Value>) -> Reply {
+    let username = body["refresh_token"]
+        .as_str()
+        .ok_or((StatusCode::BAD_REQUEST, "missing refresh_token".to_string()))?;
+
+    Ok(Json(serde_json::json!({
+        "access_token": MockFirefly::token(username),
+        "refresh_token": username,
+    }))
+    .into_response())
+}
+
+async fn register_device(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Reply {
+    let username = username(&headers)?;
+    let address = decode::<firefly::Address>(&body)?;
+    let mut state = state.lock().unwrap();
+
+    if let Some(existing) = state
+        .addresses
+        .iter_mut()
+        .find(|existing| existing.id == address.id && existing.username == username)
+    {
+        existing.fcm_token = address.fcm_token;
+        return proto(&*existing);
+    }
+
+    let id = if address.id != 0 && state.address(address.id).is_none() {
+        address.id
+    } else {
+        state.next_id()
+    };
+    let address = firefly::Address {
+        id,
+        username,
+        device_id: address.device_id,
+        fcm_token: address.fcm_token,
+    };
+    state.addresses.push(address.clone());
+
+    proto(&address)
+}
+
+/// Drops the address with everything stored for it, nothing is delivered to
+/// its connection anymore.
+async fn deregister_device(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> Reply {
+    let username = username(&headers)?;
+    let address_id = param::<u64>(&params, "id")?;
+    let mut state = state.lock().unwrap();
+    state.own_address(&username, address_id)?;
+
+    state.addresses.retain(|address| address.id != address_id);
+    state
+        .pre_key_bundles
+        .retain(|entry| entry.address != address_id);
+    state
+        .key_packages
+        .retain(|package| package.address != address_id);
+    state.invites.remove(&address_id);
+    for members in state.group_members.values_mut() {
+        members.remove(&address_id);
+    }
+    state.connections.remove(&address_id);
+
+    Ok(StatusCode::OK.into_response())
+}
+
+/// `other` takes a bundle of every device of a user, `ids` one of every listed
+/// address, and `id` with `onlyIds` lists the ids an address has left.
+async fn get_pre_key_bundles(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> Reply {
+    username(&headers)?;
+    let mut state = state.lock().unwrap();
+    let mut entries = firefly::PreKeyBundleEntries::default();
+
+    if let Some(other) = params.get("other") {
+        for address in state.addresses_of(other) {
+            let entry = state.take_pre_key_bundle(&address);
+            entries.entries.push(entry);
+        }
+    } else if params.contains_key("ids") {
+        for id in param_list::<u64>(&params, "ids")? {
+            if let Some(address) = state.address(id).cloned() {
+                let entry = state.take_pre_key_bundle(&address);
+                entries.entries.push(entry);
+            }
+        }
+    } else {
+        let id = param::<u64>(&params, "id")?;
+        entries.entries = state
+            .pre_key_bundles_of(id)
+            .into_iter()
+            .map(|entry| firefly::PreKeyBundleEntry {
+                bundle: None,
+                ..entry.clone()
+            })
+            .collect();
+    }
+
+    proto(&entries)
+}
+
+async fn upload_pre_key_bundles(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Reply {
+    let username = username(&headers)?;
+    let entries = decode::<firefly::PreKeyBundleEntries>(&body)?;
+    let mut state = state.lock().unwrap();
+
+    for mut entry in entries.entries {
+        let address = state.own_address(&username, entry.address)?;
+        entry.username = address.username.clone();
+        entry.device_id = address.device_id;
+        state.pre_key_bundles.push(entry);
+    }
+
+    Ok(StatusCode::OK.into_response())
+}
+
+async fn delete_pre_key_bundles(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> Reply {
+    let username = username(&headers)?;
+    let address_id = param::<u64>(&params, "addressId")?;
+    let ids = param_list::<u32>(&params, "ids")?;
+    let mut state = state.lock().unwrap();
+    state.own_address(&username, address_id)?;
+
+    state
+        .pre_key_bundles
+        .retain(|entry| entry.address != address_id || !ids.contains(&entry.id));
+
+    Ok(StatusCode::OK.into_response())
+}
+
+async fn update_conversation(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> Reply {
+    let username = username(&headers)?;
+    let other = param::<String>(&params, "other")?;
+    let settings = param::<u64>(&params, "settings")?;
+    let merge = param::<bool>(&params, "merge")?;
+
+    let (user1, user2) = if username < other {
+        (username, other)
+    } else {
+        (other, username)
+    };
+    let mut state = state.lock().unwrap();
+
+    match state
+        .conversations
+        .iter_mut()
+        .find(|conversation| conversation.user1 == user1 && conversation.user2 == user2)
+    {
+        Some(conversation) if merge => conversation.settings |= settings,
+        Some(conversation) => conversation.settings = settings,
+        None => state.conversations.push(firefly::Conversation {
+            user1,
+            user2,
+            settings,
+        }),
+    }
+
+    Ok(StatusCode::OK.into_response())
+}
+
+async fn get_conversations(State(state): State<SharedState>, headers: HeaderMap) -> Reply {
+    let username = username(&headers)?;
+    let state = state.lock().unwrap();
+
+    proto(&firefly::Conversations {
+        conversations: state
+            .conversations
+            .iter()
+            .filter(|conversation| conversation.user1 == username || conversation.user2 == username)
+            .cloned()
+            .collect(),
+    })
+}
+
+/// Groups any device of the user is a member of.
+async fn get_groups(State(state): State<SharedState>, headers: HeaderMap) -> Reply {
+    let username = username(&headers)?;
+    let state = state.lock().unwrap();
+    let own_addresses = state
+        .addresses_of(&username)
+        .into_iter()
+        .map(|address| address.id)
+        .collect::<HashSet<_>>();
+
+    let mut groups = firefly::Groups::default();
+    for (group_id, members) in &state.group_members {
+        if !members.is_disjoint(&own_addresses)
+            && let Some(group) = state.groups.get(group_id)
+        {
+            groups.groups.push(group.clone());
+        }
+    }
+
+    proto(&groups)
+}
+
+async fn get_group(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> Reply {
+    username(&headers)?;
+    let id = param::<u64>(&params, "id")?;
+    let state = state.lock().unwrap();
+
+    match state.groups.get(&id) {
+        Some(group) => proto(group),
+        None => Err((StatusCode::NOT_FOUND, format!("group {} not found", id))),
+    }
+}
+
+async fn sync_group_messages(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+    body: Bytes,
+) -> Reply {
+    let username = username(&headers)?;
+    let address_id = param::<u64>(&params, "address")?;
+    let limit = param::<usize>(&params, "limit")?;
+    let requests = decode::<firefly::GroupSyncRequests>(&body)?;
+    let state = state.lock().unwrap();
+    state.own_address(&username, address_id)?;
+
+    let mut messages = firefly::GroupMessages::default();
+    for request in requests.requests {
+        let is_member = state
+            .group_members
+            .get(&request.group_id)
+            .is_some_and(|members| members.contains(&address_id));
+        if !is_member {
+            continue;
+        }
+
+        let group_messages = state.group_messages.iter().filter(|message| {
+            message.group_id == request.group_id
+                && message.id > request.start_after
+                && (request.until == 0 || message.id <= request.until)
+        });
+        let group_limit = match request.limit {
+            0 => usize::MAX,
+            limit => limit as usize,
+        };
+        messages
+            .messages
+            .extend(group_messages.take(group_limit).cloned());
+    }
+    messages.messages.sort_by_key(|message| message.id);
+    messages.messages.truncate(limit);
+
+    proto(&messages)
+}
+
+async fn sync_group_updates(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+    body: Bytes,
+) -> Reply {
+    let username = username(&headers)?;
+    let address_id = param::<u64>(&params, "address")?;
+    let updates = decode::<firefly::GroupMemberUpdates>(&body)?;
+    let mut state = state.lock().unwrap();
+    state.own_address(&username, address_id)?;
+
+    for update in updates.updates {
+        state
+            .group_member_updates
+            .insert((address_id, update.group_id), update);
+    }
+
+    Ok(StatusCode::OK.into_response())
+}
+
+/// Called by a device after joining through an invite.
+async fn add_group_member(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+    body: Bytes,
+) -> Reply {
+    let username = username(&headers)?;
+    let group_id = param::<u64>(&params, "groupId")?;
+    let address_id = param::<u64>(&params, "address")?;
+    let update = decode::<firefly::GroupMemberUpdate>(&body)?;
+    let mut state = state.lock().unwrap();
+    state.own_address(&username, address_id)?;
+
+    if !state.groups.contains_key(&group_id) {
+        return Err((
+            StatusCode::NOT_FOUND,
+            format!("group {} not found", group_id),
+        ));
+    }
+    state
+        .group_members
+        .entry(group_id)
+        .or_default()
+        .insert(address_id);
+    state
+        .group_member_updates
+        .insert((address_id, group_id), update);
+
+    Ok(StatusCode::OK.into_response())
+}
+
+async fn get_key_packages(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> Reply {
+    let username = username(&headers)?;
+    let address_id = param::<u64>(&params, "address_id")?;
+    let state = state.lock().unwrap();
+    state.own_address(&username, address_id)?;
+
+    proto(&firefly::GroupKeyPackages {
+        packages: state
+            .key_packages
+            .iter()
+            .filter(|package| package.address == address_id)
+            .cloned()
+            .collect(),
+    })
+}
+
+async fn upload_key_packages(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+    body: Bytes,
+) -> Reply {
+    let username = username(&headers)?;
+    let address_id = param::<u64>(&params, "address")?;
+    let packages = decode::<firefly::GroupKeyPackages>(&body)?;
+    let mut state = state.lock().unwrap();
+    state.own_address(&username, address_id)?;
+
+    for mut package in packages.packages {
+        package.address = address_id;
+        package.username = username.clone();
+        state.key_packages.push(package);
+    }
+
+    Ok(StatusCode::OK.into_response())
+}
+
+async fn delete_key_packages(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> Reply {
+    let username = username(&headers)?;
+    let address_id = param::<u64>(&params, "address")?;
+    let ids = param_list::<i32>(&params, "ids")?;
+    let mut state = state.lock().unwrap();
+    state.own_address(&username, address_id)?;
+
+    state
+        .key_packages
+        .retain(|package| package.address != address_id || !ids.contains(&package.id));
+
+    Ok(StatusCode::OK.into_response())
+}
+
+async fn get_invites(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> Reply {
+    let username = username(&headers)?;
+    let address_id = param::<u64>(&params, "address")?;
+    let state = state.lock().unwrap();
+    state.own_address(&username, address_id)?;
+
+    proto(&firefly::GroupInvites {
+        invites: state.invites.get(&address_id).cloned().unwrap_or_default(),
+    })
+}
+
+async fn delete_invites(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> Reply {
+    let username = username(&headers)?;
+    let address_id = param::<u64>(&params, "address")?;
+    let group_ids = param_list::<u64>(&params, "groupIds")?;
+    let mut state = state.lock().unwrap();
+    state.own_address(&username, address_id)?;
+
+    if let Some(invites) = state.invites.get_mut(&address_id) {
+        invites.retain(|invite| !group_ids.contains(&invite.group_id));
+    }
+
+    Ok(StatusCode::OK.into_response())
+}
+
+/// A device that lost a group asks the other members to add it again.
+async fn request_re_adds(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> Reply {
+    let username = username(&headers)?;
+    let address_id = param::<u64>(&params, "address")?;
+    let group_ids = param_list::<u64>(&params, "groupIds")?;
+    let mut state = state.lock().unwrap();
+    state.own_address(&username, address_id)?;
+
+    for group_id in group_ids {
+        let request = firefly::GroupReAddRequest {
+            group_id,
+            address_id,
+            username: username.clone(),
+        };
+        if !state.re_adds.contains(&request) {
+            state.re_adds.push(request);
+        }
+    }
+
+    Ok(StatusCode::OK.into_response())
+}
+
+async fn get_re_adds(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> Reply {
+    let username = username(&headers)?;
+    let address_id = param::<u64>(&params, "address")?;
+    let group_ids = param_list::<u64>(&params, "groupIds")?;
+    let state = state.lock().unwrap();
+    state.own_address(&username, address_id)?;
+
+    proto(&firefly::GroupReAddRequests {
+        requests: state
+            .re_adds
+            .iter()
+            .filter(|request| {
+                group_ids.contains(&request.group_id) && request.address_id != address_id
+            })
+            .cloned()
+            .collect(),
+    })
+}
+
+async fn delete_re_add(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> Reply {
+    let username = username(&headers)?;
+    let group_id = param::<u64>(&params, "groupId")?;
+    let address_id = param::<u64>(&params, "address")?;
+    let my_address = param::<u64>(&params, "myAddress")?;
+    let mut state = state.lock().unwrap();
+    state.own_address(&username, my_address)?;
+
+    state
+        .re_adds
+        .retain(|request| request.group_id != group_id || request.address_id != address_id);
+
+    Ok(StatusCode::OK.into_response())
+}
+
+/// Answers with the url the file is downloaded from, like the real server.
+async fn upload_file(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+    body: Bytes,
+) -> Reply {
+    username(&headers)?;
+    let length = param::<usize>(&params, "length")?;
+    if length != body.len() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("expected {} bytes, received {}", length, body.len()),
+        ));
+    }
+
+    let mut state = state.lock().unwrap();
+    let name = format!("{:x}", state.next_id());
+    let url = format!("{}/file/{}", state.base_url, name);
+    state.files.insert(name, body);
+
+    Ok(url.into_response())
+}
+
+async fn download_file(State(state): State<SharedState>, Path(name): Path<String>) -> Reply {
+    match state.lock().unwrap().files.get(&name) {
+        Some(file) => Ok(file.clone().into_response()),
+        None => Err((StatusCode::NOT_FOUND, format!("file {} not found", name))),
+    }
+}
+
+async fn connect(
+    State(state): State<SharedState>,
+    Query(params): Query<HashMap<String, String>>,
+    upgrade: WebSocketUpgrade,
+) -> Reply {
+    let username = username_of_token(&param::<String>(&params, "token")?)?;
+    let address_id = param::<u64>(&params, "uid")?;
+    let last_synced_upto = param::<u64>(&params, "last_synced_upto")?;
+    let address = state
+        .lock()
+        .unwrap()
+        .own_address(&username, address_id)?
+        .clone();
+
+    Ok(
+        upgrade
+            .on_upgrade(move |socket| serve_connection(state, socket, address, last_synced_upto)),
+    )
+}
+
+/// Replays what the device missed as one batch, then relays frames until the
+/// socket closes.
+async fn serve_connection(
+    state: SharedState,
+    socket: WebSocket,
+    address: firefly::Address,
+    last_synced_upto: u64,
+) {
+    let (mut sink, mut stream) = socket.split();
+    let (sender, mut receiver) = mpsc::unbounded_channel::<firefly::ServerMessage>();
+
+    {
+        let mut state = state.lock().unwrap();
+        let missed = state
+            .user_messages
+            .iter()
+            .filter(|message| message.to_id == address.id && message.id > last_synced_upto)
+            .cloned()
+            .collect::<Vec<_>>();
+        if !missed.is_empty() {
+            let _ = sender.send(firefly::ServerMessage {
+                message: Some(firefly::server_message::Message::UserMessages(
+                    firefly::UserMessages { messages: missed },
+                )),
+            });
+        }
+        state.connections.insert(address.id, sender.clone());
+    }
+
+    let writer = tokio::spawn(async move {
+        while let Some(message) = receiver.recv().await {
+            let Ok(bytes) = serialize_proto(&message) else {
+                continue;
+            };
+            if sink.send(Message::Binary(bytes)).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    while let Some(Ok(message)) = stream.next().await {
+        let bytes = match message {
+            Message::Binary(bytes) => bytes,
+            Message::Close(_) => break,
+            _ => continue,
+        };
+
+        let message = match deserialize_proto::<firefly::ClientMessage>(&bytes) {
+            Ok(firefly::ClientMessage {
+                message: Some(message),
+            }) => message,
+            Ok(_) => continue,
+            Err(err) => {
+                log::error!("mock firefly: invalid client message: {}", err);
+                continue;
+            }
+        };
+
+        let reply = match message {
+            firefly::client_message::Message::Ping(ping) => {
+                firefly::server_message::Message::Pong(ping)
+            }
+            firefly::client_message::Message::Request(request) => {
+                firefly::server_message::Message::Response(on_request(&state, &address, request))
+            }
+            other => {
+                log::warn!("mock firefly: unhandled client message {:?}", other);
+                continue;
+            }
+        };
+        let _ = sender.send(firefly::ServerMessage {
+            message: Some(reply),
+        });
+    }
+
+    {
+        let mut state = state.lock().unwrap();
+        if state
+            .connections
+            .get(&address.id)
+            .is_some_and(|connection| connection.same_channel(&sender))
+        {
+            state.connections.remove(&address.id);
+        }
+    }
+    writer.abort();
+}
+
+fn on_request(
+    state: &SharedState,
+    from: &firefly::Address,
+    request: firefly::Request,
+) -> firefly::Response {
+    let mut state = state.lock().unwrap();
+    let mut response = firefly::Response {
+        id: request.id,
+        ..Default::default()
+    };
+
+    match request.payload {
+        Some(firefly::request::Payload::UploadUserMessage(upload)) => {
+            response.body = Some(firefly::response::Body::UserMessageUploaded(
+                state.upload_user_messages(from, upload),
+            ));
+        }
+        Some(firefly::request::Payload::UploadGroupMessage(message)) => {
+            match state.upload_group_message(from, message) {
+                Ok(message) => {
+                    response.body = Some(firefly::response::Body::GroupMessageUploaded(message));
+                }
+                Err(error) => {
+                    response.error = Some(firefly::Error {
+                        error,
+                        error_code: StatusCode::FORBIDDEN.as_u16() as u32,
+                    });
+                }
+            }
+        }
+        _ => {
+            response.error = Some(firefly::Error {
+                error: "unsupported request".to_string(),
+                error_code: StatusCode::BAD_REQUEST.as_u16() as u32,
+            });
+        }
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio_tungstenite::tungstenite;
+
+    use super::*;
+
+    type Socket = tokio_tungstenite::WebSocketStream<
+        tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
+    >;
+
+    async fn register(mock: &MockFirefly, username: &str, device_id: u32) -> firefly::Address {
+        let response = reqwest::Client::new()
+            .post(format!("{}/user/device", mock.base_url))
+            .bearer_auth(MockFirefly::token(username))
+            .body(
+                serialize_proto(&firefly::Address {
+                    id: 0,
+                    username: username.to_string(),
+                    device_id,
+                    fcm_token: String::new(),
+                })
+                .unwrap(),
+            )
+            .send()
+            .await
+            .unwrap();
+        assert!(response.status().is_success());
+
+        deserialize_proto(&response.bytes().await.unwrap()).unwrap()
+    }
+
+    async fn open(mock: &MockFirefly, address: &firefly::Address, last_synced_upto: u64) -> Socket {
+        let url = format!(
+            "{}?uid={}&device_id={}&last_synced_upto={}&token={}",
+            mock.ws_url,
+            address.id,
+            address.device_id,
+            last_synced_upto,
+            MockFirefly::token(&address.username)
+        );
+        tokio_tungstenite::connect_async(&url).await.unwrap().0
+    }
+
+    async fn send(socket: &mut Socket, message: firefly::client_message::Message) {
+        let bytes = serialize_proto(&firefly::ClientMessage {
+            message: Some(message),
+        })
+        .unwrap();
+        socket
+            .send(tungstenite::Message::Binary(bytes))
+            .await
+            .unwrap();
+    }
+
+    async fn receive(socket: &mut Socket) -> firefly::server_message::Message {
+        loop {
+            let message = tokio::time::timeout(Duration::from_secs(5), socket.next())
+                .await
+                .expect("no message within 5s")
+                .unwrap()
+                .unwrap();
+            if let tungstenite::Message::Binary(bytes) = message {
+                return deserialize_proto::<firefly::ServerMessage>(&bytes)
+                    .unwrap()
+                    .message
+                    .unwrap();
+            }
+        }
+    }
+
+    fn user_message(id: u64, to: &firefly::Address) -> firefly::UserMessage {
+        firefly::UserMessage {
+            id,
+            to_id: to.id,
+            text: vec![1, 2, 3],
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ws_framing() {
+        let mock = MockFirefly::start().await.unwrap();
+        let alice = register(&mock, "alice", 1).await;
+        let bob1 = register(&mock, "bob", 1).await;
+        let bob2 = register(&mock, "bob", 2).await;
+
+        let mut alice_socket = open(&mock, &alice, 0).await;
+        let mut bob1_socket = open(&mock, &bob1, 0).await;
+
+        send(
+            &mut alice_socket,
+            firefly::client_message::Message::Ping(vec![9]),
+        )
+        .await;
+        assert_eq!(
+            receive(&mut alice_socket).await,
+            firefly::server_message::Message::Pong(vec![9])
+        );
+
+        // only one of bob's devices is addressed
+        send(
+            &mut alice_socket,
+            firefly::client_message::Message::Request(firefly::Request {
+                id: 7,
+                payload: Some(firefly::request::Payload::UploadUserMessage(
+                    firefly::UploadUserMessage {
+                        messages: vec![user_message(100, &bob1)],
+                    },
+                )),
+            }),
+        )
+        .await;
+
+        let firefly::server_message::Message::Response(response) = receive(&mut alice_socket).await
+        else {
+            panic!("expected a response");
+        };
+        assert_eq!(response.id, 7);
+        let Some(firefly::response::Body::UserMessageUploaded(uploaded)) = response.body else {
+            panic!("expected uploaded message ids");
+        };
+        assert_eq!(
+            uploaded.message_ids,
+            vec![
+                firefly::MessageIdAndTo {
+                    id: 100,
+                    to: bob1.id,
+                    is_self: false,
+                },
+                firefly::MessageIdAndTo {
+                    id: 0,
+                    to: bob2.id,
+                    is_self: false,
+                },
+            ]
+        );
+
+        let firefly::server_message::Message::UserMessage(received) =
+            receive(&mut bob1_socket).await
+        else {
+            panic!("expected a user message");
+        };
+        assert_eq!(received.id, 100);
+        assert_eq!(received.from_id, alice.id);
+        assert_eq!(received.from_username, "alice");
+        assert_eq!(received.from_device_id, 1);
+
+        // a device that was offline catches up in one batch
+        let mut bob1_socket = open(&mock, &bob1, 0).await;
+        let firefly::server_message::Message::UserMessages(missed) =
+            receive(&mut bob1_socket).await
+        else {
+            panic!("expected a batch");
+        };
+        assert_eq!(missed.messages.len(), 1);
+        assert_eq!(missed.messages[0].id, 100);
+    }
+
+    #[tokio::test]
+    async fn test_group_messages_reach_members_only() {
+        let mock = MockFirefly::start().await.unwrap();
+        let alice = register(&mock, "alice", 1).await;
+        let bob = register(&mock, "bob", 1).await;
+        let charlie = register(&mock, "charlie", 1).await;
+        mock.state().add_group(
+            firefly::Group {
+                id: 5,
+                name: "team".to_string(),
+                ..Default::default()
+            },
+            [alice.id, bob.id],
+        );
+
+        let mut alice_socket = open(&mock, &alice, 0).await;
+        let mut bob_socket = open(&mock, &bob, 0).await;
+        let mut charlie_socket = open(&mock, &charlie, 0).await;
+
+        let upload = |id| {
+            firefly::client_message::Message::Request(firefly::Request {
+                id,
+                payload: Some(firefly::request::Payload::UploadGroupMessage(
+                    firefly::GroupMessage {
+                        id: 0,
+                        group_id: 5,
+                        message: vec![4, 5, 6],
+                        epoch: 1,
+                    },
+                )),
+            })
+        };
+
+        send(&mut alice_socket, upload(1)).await;
+        let firefly::server_message::Message::Response(response) = receive(&mut alice_socket).await
+        else {
+            panic!("expected a response");
+        };
+        let Some(firefly::response::Body::GroupMessageUploaded(uploaded)) = response.body else {
+            panic!("expected the uploaded message");
+        };
+        assert_ne!(uploaded.id, 0);
+
+        assert_eq!(
+            receive(&mut bob_socket).await,
+            firefly::server_message::Message::GroupMessage(uploaded)
+        );
+
+        send(&mut charlie_socket, upload(2)).await;
+        let firefly::server_message::Message::Response(response) =
+            receive(&mut charlie_socket).await
+        else {
+            panic!("expected a response");
+        };
+        assert!(response.error.is_some());
+        assert_eq!(mock.state().group_messages.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_routes_are_recorded() {
+        let mock = MockFirefly::start().await.unwrap();
+
+        let response = reqwest::Client::new()
+            .post(format!("{}/group/unknown?x=1", mock.base_url))
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status().as_u16(), 501);
+        assert_eq!(mock.state().unhandled, vec!["POST /group/unknown?x=1"]);
+    }
+}
@@ -465,6 +465,20 @@ pub struct Conversations {
     pub conversations: ::prost::alloc::vec::Vec<Conversation>,
 }
 #[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct MediaMetadata {
+    #[prost(string, tag="1")]
+    pub mime_type: ::prost::alloc::string::String,
+    #[prost(uint32, tag="2")]
+    pub width: u32,
+    #[prost(uint32, tag="3")]
+    pub height: u32,
+    #[prost(uint32, tag="4")]
+    pub duration_millis: u32,
+    /// small JPEG preview, shown before the file is downloaded
+    #[prost(bytes="vec", tag="5")]
+    pub thumbnail: ::prost::alloc::vec::Vec<u8>,
+}
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
 pub struct EncryptedFile {
     #[prost(string, tag="1")]
     pub url: ::prost::alloc::string::String,
@@ -474,6 +488,8 @@ pub struct EncryptedFile {
     pub content_type: u32,
     #[prost(uint32, tag="4")]
     pub content_length: u32,
+    #[prost(message, optional, tag="5")]
+    pub metadata: ::core::option::Option<MediaMetadata>,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct EncryptedFiles {
@@ -507,10 +523,66 @@ pub struct SelfUserMessage {
     /// UserMessageInner encrypted
     #[prost(bytes="vec", tag="2")]
     pub inner: ::prost::alloc::vec::Vec<u8>,
+    /// id of the sender's local copy, same for every device
+    #[prost(fixed64, tag="3")]
+    pub id: u64,
+}
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct Receipt {
+    #[prost(enumeration="ReceiptType", tag="1")]
+    pub r#type: i32,
+    #[prost(fixed64, repeated, tag="2")]
+    pub message_ids: ::prost::alloc::vec::Vec<u64>,
+}
+/// Ephemeral, never stored, receivers drop it after a few seconds
+#[derive(Clone, Copy, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct TypingIndicator {
+    #[prost(bool, tag="1")]
+    pub typing: bool,
+}
+/// Replaces the payload of an earlier message of the same sender
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct EditMessage {
+    #[prost(fixed64, tag="1")]
+    pub message_id: u64,
+    #[prost(message, optional, tag="2")]
+    pub payload: ::core::option::Option<MessagePayload>,
+}
+/// Deletes an earlier message of the same sender for everyone
+#[derive(Clone, Copy, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct DeleteMessage {
+    #[prost(fixed64, tag="1")]
+    pub message_id: u64,
+}
+/// Sets the reaction of the sender on a message, an empty emoji removes it
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct Reaction {
+    #[prost(fixed64, tag="1")]
+    pub message_id: u64,
+    #[prost(string, tag="2")]
+    pub emoji: ::prost::alloc::string::String,
+}
+/// Syncs a change of our block list to our other devices, only sent to ourselves
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct BlockUpdate {
+    #[prost(string, tag="1")]
+    pub username: ::prost::alloc::string::String,
+    #[prost(bool, tag="2")]
+    pub blocked: bool,
+}
+/// The sender set the disappearing message timer of the conversation, it
+/// applies to messages from the id of this message on
+#[derive(Clone, Copy, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct DisappearingTimerUpdate {
+    #[prost(uint32, tag="1")]
+    pub timer_secs: u32,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct UserMessageInner {
-    #[prost(oneof="user_message_inner::Message", tags="1, 2, 3, 4")]
+    /// id of the sender's local copy, the envelope id changes per attempt
+    #[prost(fixed64, tag="12")]
+    pub id: u64,
+    #[prost(oneof="user_message_inner::Message", tags="1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11")]
     pub message: ::core::option::Option<user_message_inner::Message>,
 }
 /// Nested message and enum types in `UserMessageInner`.
@@ -525,13 +597,27 @@ pub mod user_message_inner {
         MessagePayload(super::MessagePayload),
         #[prost(message, tag="4")]
         SelfMessage(super::SelfUserMessage),
+        #[prost(message, tag="5")]
+        Receipt(super::Receipt),
+        #[prost(message, tag="6")]
+        Typing(super::TypingIndicator),
+        #[prost(message, tag="7")]
+        Edit(super::EditMessage),
+        #[prost(message, tag="8")]
+        Delete(super::DeleteMessage),
+        #[prost(message, tag="9")]
+        Reaction(super::Reaction),
+        #[prost(message, tag="10")]
+        BlockUpdate(super::BlockUpdate),
+        #[prost(message, tag="11")]
+        DisappearingTimerUpdate(super::DisappearingTimerUpdate),
     }
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct GroupMessageInner {
     #[prost(uint32, tag="1")]
     pub channel_id: u32,
-    #[prost(oneof="group_message_inner::Message", tags="2")]
+    #[prost(oneof="group_message_inner::Message", tags="2, 3, 4, 5, 6")]
     pub message: ::core::option::Option<group_message_inner::Message>,
 }
 /// Nested message and enum types in `GroupMessageInner`.
@@ -540,6 +626,14 @@ pub mod group_message_inner {
     pub enum Message {
         #[prost(message, tag="2")]
         MessagePayload(super::MessagePayload),
+        #[prost(message, tag="3")]
+        Typing(super::TypingIndicator),
+        #[prost(message, tag="4")]
+        Edit(super::EditMessage),
+        #[prost(message, tag="5")]
+        Delete(super::DeleteMessage),
+        #[prost(message, tag="6")]
+        Reaction(super::Reaction),
     }
 }
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
@@ -591,4 +685,30 @@ impl CallMessageType {
         }
     }
 }
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum ReceiptType {
+    Delivered = 0,
+    Read = 1,
+}
+impl ReceiptType {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            Self::Delivered => "delivered",
+            Self::Read => "read",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "delivered" => Some(Self::Delivered),
+            "read" => Some(Self::Read),
+            _ => None,
+        }
+    }
+}
 // @@protoc_insertion_point(module)
@@ -0,0 +1,140 @@
+use std::pin::Pin;
+
+use bytes::Bytes;
+use futures::{Sink, SinkExt, Stream, StreamExt, future};
+use tokio_tungstenite::tungstenite::Message;
+
+/// Serialized `ClientMessage`s to the server.
+pub type FrameSink = Pin<Box<dyn Sink<Bytes, Error = anyhow::Error> + Send>>;
+
+/// Serialized `ServerMessage`s from the server, ends when the connection
+/// closes.
+pub type FrameStream = Pin<Box<dyn Stream<Item = Bytes> + Send>>;
+
+/// Opens the realtime connection of a device, `last_synced_upto` is the id of
+/// the last user message received so the server only sends newer ones.
+#[async_trait::async_trait]
+pub trait FireflyTransport: Send + Sync {
+    async fn connect(
+        &self,
+        address_id: u64,
+        device_id: u8,
+        last_synced_upto: u64,
+        token: &str,
+    ) -> anyhow::Result<(FrameSink, FrameStream)>;
+}
+
+/// `FireflyTransport` over a websocket, frames are binary messages.
+pub struct WsTransport {
+    ws_url: String,
+}
+
+impl WsTransport {
+    pub fn new(ws_url: String) -> Self {
+        Self { ws_url }
+    }
+}
+
+#[async_trait::async_trait]
+impl FireflyTransport for WsTransport {
+    async fn connect(
+        &self,
+        address_id: u64,
+        device_id: u8,
+        last_synced_upto: u64,
+        token: &str,
+    ) -> anyhow::Result<(FrameSink, FrameStream)> {
+        let url = format!(
+            "{}?uid={}&device_id={}&last_synced_upto={}&token={}",
+            self.ws_url, address_id, device_id, last_synced_upto, token
+        );
+
+        log::info!("connecting to {}", url);
+
+        let (stream, response) = match tokio_tungstenite::connect_async(&url).await {
+            Ok(v) => v,
+            Err(err) => {
+                log::error!("connection request failed {:?}", err);
+                return Err(err.into());
+            }
+        };
+
+        log::info!(
+            "connected successfully to {}, Headers: {:?} ",
+            url,
+            response.headers()
+        );
+
+        let (sink, stream) = stream.split();
+
+        let sink = sink
+            .sink_map_err(anyhow::Error::from)
+            .with(|frame: Bytes| future::ok::<_, anyhow::Error>(Message::Binary(frame)));
+
+        let stream = stream
+            .scan((), |_, message| {
+                future::ready(match message {
+                    Ok(Message::Close(close_frame)) => {
+                        log::info!("ws closed: {:?}", close_frame);
+                        None
+                    }
+                    Ok(message) => Some(message),
+                    Err(err) => {
+                        log::error!("ws receive failed: {}", err);
+                        None
+                    }
+                })
+            })
+            .filter_map(|message| {
+                future::ready(match message {
+                    Message::Binary(frame) => Some(frame),
+                    message => {
+                        log::warn!("unhandled ws message type {:?}", message);
+                        None
+                    }
+                })
+            });
+
+        Ok((Box::pin(sink), Box::pin(stream)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        mock_server::MockFirefly,
+        pb::firefly::firefly,
+        utils::{deserialize_proto, serialize_proto},
+    };
+
+    #[tokio::test]
+    async fn test_ws_transport_exchanges_frames() {
+        let mock = MockFirefly::start().await.unwrap();
+        let address = firefly::Address {
+            id: 1,
+            username: "alice".to_string(),
+            device_id: 1,
+            fcm_token: String::new(),
+        };
+        mock.state().addresses.push(address.clone());
+
+        let (mut sink, mut stream) = WsTransport::new(mock.ws_url.clone())
+            .connect(address.id, 1, 0, &MockFirefly::token("alice"))
+            .await
+            .unwrap();
+
+        let ping = firefly::ClientMessage {
+            message: Some(firefly::client_message::Message::Ping(vec![7])),
+        };
+        sink.send(serialize_proto(&ping).unwrap()).await.unwrap();
+
+        let frame = stream.next().await.expect("connection closed");
+        assert_eq!(
+            deserialize_proto::<firefly::ServerMessage>(&frame)
+                .unwrap()
+                .message,
+            Some(firefly::server_message::Message::Pong(vec![7]))
+        );
+    }
+}
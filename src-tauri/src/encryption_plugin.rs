@@ -1,19 +1,23 @@
 use base64::{engine::general_purpose, Engine as _};
 use firefly_signal::{
     db::{
+        attachment_cache::AttachmentOwner,
         auth::TokenResponse,
+        conversations::{ConversationSettings, NotificationLevel},
         group_messages::GroupMessage,
         group_stores::GroupInfo,
-        messages::{MessagesStore, UserMessage},
-        setup_pool_from_path,
+        messages::{MessageEdit, ReactionCount, ReceiptState, UserMessage},
+        outbox::{OutboxMessage, OutboxStatus},
+        search::{merge_hits, SearchHit},
+        stores::{IdentityTrust, IdentityTrustState},
     },
+    error::FireflyError,
     group::{UpdateRoleProposalFfi, UpdateUserProposalFfi},
-    websocket::{FfiFireflyWsClient, FireflyWsClientCallback},
+    utils::get_current_timestamp_millis_since_epoch,
+    websocket::{ConnectionState, FireflyWsClientCallback, TypingConversation},
     *,
 };
-use rand::{distributions::Alphanumeric, Rng};
 use serde::{Deserialize, Serialize};
-use sqlx::SqlitePool;
 use std::path::PathBuf;
 use std::sync::Arc;
 use tauri::{command, AppHandle, Emitter, Manager, Runtime, State};
@@ -26,41 +30,53 @@ use jni::objects::{JClass, JString};
 use jni::JNIEnv;
 
 use crate::notification::{NotificationHandler, NotificationStore};
+use crate::profiles::{Profile, ProfileInfo, ProfileManager};
 
-type FireflyClient = Arc<FfiFireflyWsClient>;
-type MessageStore = Arc<MessagesStore>;
-type FileServer = Arc<FfiFileServer>;
-type Database = SqlitePool;
+type Profiles = Arc<ProfileManager>;
 
-pub static DATABASE: OnceCell<Database> = OnceCell::const_new();
-pub static MESSAGE_STORE: OnceCell<MessageStore> = OnceCell::const_new();
-pub static FIREFLY_CLIENT: OnceCell<FireflyClient> = OnceCell::const_new();
-pub static FILE_SERVER: OnceCell<FileServer> = OnceCell::const_new();
-
-// Signals that the file server is bound and ready to accept connections
-pub static FILE_SERVER_READY: tokio::sync::OnceCell<u16> = tokio::sync::OnceCell::const_new();
+pub static PROFILES: OnceCell<Profiles> = OnceCell::const_new();
 
 // Using once_cell::sync::Lazy for the event channel as it doesn't require async initialization
 // and is simple to use for this purpose.
 use once_cell::sync::Lazy;
 
-static EVENT_CHANNEL: Lazy<broadcast::Sender<FireflyEvent>> = Lazy::new(|| {
+static EVENT_CHANNEL: Lazy<broadcast::Sender<ProfileEvent>> = Lazy::new(|| {
     let (tx, _) = broadcast::channel(100);
     tx
 });
 
+/// An event of the client of profile `profile`.
+#[derive(Clone)]
+pub struct ProfileEvent {
+    profile: Arc<str>,
+    event: FireflyEvent,
+}
+
 #[derive(Clone)]
 pub enum FireflyEvent {
     UserMessage(Arc<UserMessage>),
     GroupMessage(Arc<GroupMessage>),
+    UserMessageUpdated(Arc<UserMessage>),
+    GroupMessageUpdated(Arc<GroupMessage>),
+    UserMessageReaction(String, u64, String, String),
+    GroupMessageReaction(u64, u64, String, String),
+    UserMessagesExpired(String, Arc<Vec<u64>>),
+    GroupMessagesExpired(u64, Arc<Vec<u64>>),
+    BlockedChanged(String, bool),
+    MessagesBatch(Arc<Vec<UserMessage>>, Arc<Vec<GroupMessage>>),
+    Receipts(String, ReceiptState, Arc<Vec<u64>>),
+    OutboxStatus(Arc<OutboxMessage>),
+    IdentityChanged(String),
+    Typing(TypingConversation, String, bool),
+    ConnectionState(ConnectionState),
 }
 
-struct Constants;
+pub(crate) struct Constants;
 impl Constants {
-    const FIREFLY_API_URL: &'static str = env!("NEXT_PUBLIC_JS_ENV_CHAT_API_URL");
-    const FIREFLY_WS_URL: &'static str = env!("NEXT_PUBLIC_JS_ENV_CHAT_WEBSOCKET_URL");
-    const AUTH0_DOMAIN: &'static str = env!("NEXT_PUBLIC_JS_ENV_AUTH0_DOMAIN");
-    const AUTH0_CLIENT_ID: &'static str = env!("NEXT_PUBLIC_JS_ENV_AUTH0_CLIENT_ID");
+    pub(crate) const FIREFLY_API_URL: &'static str = env!("NEXT_PUBLIC_JS_ENV_CHAT_API_URL");
+    pub(crate) const FIREFLY_WS_URL: &'static str = env!("NEXT_PUBLIC_JS_ENV_CHAT_WEBSOCKET_URL");
+    pub(crate) const AUTH0_DOMAIN: &'static str = env!("NEXT_PUBLIC_JS_ENV_AUTH0_DOMAIN");
+    pub(crate) const AUTH0_CLIENT_ID: &'static str = env!("NEXT_PUBLIC_JS_ENV_AUTH0_CLIENT_ID");
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -83,6 +99,43 @@ pub struct UpdateUserProposal {
 pub struct Conversation {
     other: String,
     settings: u64,
+    #[serde(rename = "conversationSettings")]
+    conversation_settings: BConversationSettings,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BConversationSettings {
+    flags: u32,
+    #[serde(rename = "disappearingTimerSecs")]
+    disappearing_timer_secs: u32,
+    #[serde(rename = "mutedUntil")]
+    muted_until: u64,
+    archived: bool,
+    pinned: bool,
+    blocked: bool,
+    /// "all", "mentions" or "none"
+    #[serde(rename = "notificationLevel")]
+    notification_level: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BlockedChangedEvent {
+    username: String,
+    blocked: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BlockedUsersResponse {
+    result: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MessagesExpiredEvent {
+    other: Option<String>,
+    #[serde(rename = "groupId")]
+    group_id: Option<u64>,
+    #[serde(rename = "messageIds")]
+    message_ids: Vec<u64>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -93,6 +146,12 @@ pub struct BUserMessage {
     sent_by_other: bool,
     #[serde(rename = "textB64")]
     text_b64: String,
+    #[serde(default)]
+    receipt: String,
+    #[serde(rename = "editedAt", default)]
+    edited_at: u64,
+    #[serde(default)]
+    deleted: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -106,6 +165,39 @@ pub struct BGroupMessage {
     #[serde(rename = "channelId")]
     channel_id: u32,
     epoch: u32,
+    #[serde(rename = "editedAt", default)]
+    edited_at: u64,
+    #[serde(default)]
+    deleted: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BMessageEdit {
+    #[serde(rename = "editedAt")]
+    edited_at: u64,
+    #[serde(rename = "textB64")]
+    text_b64: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BReactionCount {
+    #[serde(rename = "messageId")]
+    message_id: u64,
+    emoji: String,
+    count: u32,
+    reactors: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BOutboxMessage {
+    id: u64,
+    other: String,
+    status: String,
+    attempts: u32,
+    #[serde(rename = "nextAttemptAt")]
+    next_attempt_at: u64,
+    #[serde(rename = "lastError")]
+    last_error: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -116,6 +208,42 @@ pub struct BGroupInfo {
     description: String,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BEncryptedFile {
+    url: String,
+    #[serde(rename = "secretKeyB64")]
+    secret_key_b64: String,
+    #[serde(rename = "contentType")]
+    content_type: u32,
+    #[serde(rename = "contentLength")]
+    content_length: u32,
+    #[serde(default)]
+    metadata: Option<BMediaMetadata>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BMediaMetadata {
+    #[serde(rename = "mimeType", default)]
+    mime_type: String,
+    #[serde(default)]
+    width: u32,
+    #[serde(default)]
+    height: u32,
+    #[serde(rename = "durationMillis", default)]
+    duration_millis: u32,
+    /// JPEG preview, empty if there is none
+    #[serde(rename = "thumbnailB64", default)]
+    thumbnail_b64: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AttachmentCacheUsageResponse {
+    files: u64,
+    bytes: u64,
+    #[serde(rename = "quotaBytes")]
+    quota_bytes: u64,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct FileServerResponse {
     url: String,
@@ -154,6 +282,16 @@ pub struct LastGroupMessagesResponse {
     result: Vec<BGroupMessage>,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MessageEditsResponse {
+    result: Vec<BMessageEdit>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MessageReactionsResponse {
+    result: Vec<BReactionCount>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ConversationWithCount {
     message: BUserMessage,
@@ -175,158 +313,580 @@ pub struct ConversationsResponse {
     result: Vec<Conversation>,
 }
 
-pub async fn initialize_firefly_client(app_data_dir: String) -> Result<(), String> {
-    let app_data_dir = PathBuf::from(app_data_dir);
-    let app_dbs_dir = app_data_dir.join("dbs");
-    tokio::fs::create_dir_all(&app_dbs_dir)
-        .await
-        .map_err(|e| e.to_string())?;
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReceiptsEvent {
+    other: String,
+    state: String,
+    #[serde(rename = "messageIds")]
+    message_ids: Vec<u64>,
+}
 
-    log::info!("created dir: {:?}", app_dbs_dir);
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct IdentityChangedEvent {
+    username: String,
+}
 
-    // Initialize Database
-    DATABASE
-        .get_or_try_init(|| async {
-            let db_path = app_dbs_dir.join("app.db");
-            log::info!("using app db path: {}", db_path.display());
-            setup_pool_from_path(&db_path.display().to_string(), 5)
-                .await
-                .map_err(|e| e.to_string())
-        })
-        .await?;
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ProfileMessagesEvent {
+    profile: String,
+    messages: usize,
+}
 
-    // Initialize Message Store
-    MESSAGE_STORE
-        .get_or_try_init(|| async {
-            let messages_db_path = app_dbs_dir.join("user_messages.db");
-            let store = MessagesStore::from_path(messages_db_path.to_string_lossy().to_string())
-                .await
-                .map_err(|e| format!("Failed to create messages store: {}", e))?;
-            Ok::<Arc<MessagesStore>, String>(Arc::new(store))
-        })
-        .await?;
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProfilesResponse {
+    result: Vec<ProfileInfo>,
+    #[serde(rename = "backgroundSync")]
+    background_sync: bool,
+}
 
-    // Initialize Firefly Client
-    let client = FIREFLY_CLIENT
-        .get_or_try_init(|| async {
-            let firefly_db_path = app_dbs_dir.join("firefly.db");
-            let callback = GlobalFireflyCallback;
-
-            let client = FfiFireflyWsClient::create(
-                Constants::FIREFLY_API_URL.to_string(),
-                Constants::FIREFLY_WS_URL.to_string(),
-                1000,
-                Box::new(callback),
-                firefly_db_path.to_string_lossy().to_string(),
-                5000,
-                Constants::AUTH0_CLIENT_ID.to_string(),
-                Constants::AUTH0_DOMAIN.to_string(),
-            )
-            .await
-            .map_err(|e| format!("Failed to create firefly client: {}", e))?;
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TypingEvent {
+    other: Option<String>,
+    #[serde(rename = "groupId")]
+    group_id: Option<u64>,
+    #[serde(rename = "channelId")]
+    channel_id: Option<u32>,
+    username: String,
+    typing: bool,
+}
 
-            Ok::<Arc<FfiFireflyWsClient>, String>(Arc::new(client))
-        })
-        .await?;
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ReactionEvent {
+    other: Option<String>,
+    #[serde(rename = "groupId")]
+    group_id: Option<u64>,
+    #[serde(rename = "messageId")]
+    message_id: u64,
+    reactor: String,
+    /// empty when the reaction was removed
+    emoji: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BConnectionState {
+    state: String,
+    since: Option<u64>,
+    #[serde(rename = "rttMs")]
+    rtt_ms: Option<u64>,
+    #[serde(rename = "nextAttemptAt")]
+    next_attempt_at: Option<u64>,
+    attempt: Option<u32>,
+    reason: Option<String>,
+}
 
-    // Initialize File Server
-    FILE_SERVER
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BIdentityTrust {
+    username: String,
+    #[serde(rename = "deviceId")]
+    device_id: u32,
+    state: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IdentityTrustResponse {
+    result: Vec<BIdentityTrust>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SafetyNumberResponse {
+    #[serde(rename = "safetyNumber")]
+    safety_number: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BSearchHit {
+    snippet: String,
+    rank: f64,
+    #[serde(rename = "userMessage")]
+    user_message: Option<BUserMessage>,
+    #[serde(rename = "groupMessage")]
+    group_message: Option<BGroupMessage>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SearchMessagesResponse {
+    result: Vec<BSearchHit>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OutboxMessagesResponse {
+    result: Vec<BOutboxMessage>,
+}
+
+pub async fn initialize_firefly_client(app_data_dir: String) -> Result<(), String> {
+    PROFILES
         .get_or_try_init(|| async {
-            let file_server_token: String = rand::thread_rng()
-                .sample_iter(&Alphanumeric)
-                .take(32)
-                .map(char::from)
-                .collect();
-
-            let file_server_dir = app_data_dir.join("files");
-            tokio::fs::create_dir_all(&file_server_dir)
+            let manager = ProfileManager::load(PathBuf::from(app_data_dir))
                 .await
-                .map_err(|e| e.to_string())?;
-
-            log::info!("created dir: {:?}", file_server_dir);
-            let file_server = FfiFileServer::create(
-                file_server_dir.to_string_lossy().to_string(),
-                file_server_token,
-            );
-
-            let file_server = Arc::new(file_server);
-            let server_clone = file_server.clone();
-            tokio::spawn(async move {
-                match server_clone.start_serving(None).await {
-                    Ok(port) => {
-                        let _ = FILE_SERVER_READY.set(port);
-                        log::info!("file server ready on port {}", port);
-                    }
-                    Err(e) => log::error!("file server failed: {}", e),
-                }
-            });
-
-            Ok::<Arc<FfiFileServer>, String>(file_server)
+                .map_err(|e| format!("Failed to load profiles: {}", e))?;
+            Ok::<Profiles, String>(Arc::new(manager))
         })
         .await?;
 
-    // Initialize client connection
-    let client_clone = client.clone();
-    tokio::spawn(async move {
-        if let Err(err) = client_clone.initialize_with_retrying().await {
-            log::error!("retry initing failed: {:?}", err);
-        }
-    });
-
     Ok(())
 }
 
+async fn active_profile<R: Runtime>(app: &AppHandle<R>) -> Result<Arc<Profile>, FireflyError> {
+    let profiles: State<Profiles> = app.state();
+    profiles.active().await
+}
+
 pub fn register_state<R: Runtime>(app: &AppHandle<R>) {
-    // Register Database
-    if let Some(db) = DATABASE.get() {
-        app.manage(db.clone());
+    // Register Profiles
+    if let Some(profiles) = PROFILES.get() {
+        app.manage(profiles.clone());
     }
 
-    // Register Message Store
-    if let Some(store) = MESSAGE_STORE.get() {
-        app.manage(store.clone());
-    }
+    // Spawn event listener
+    let mut rx = EVENT_CHANNEL.subscribe();
+    let app_handle = app.clone();
+
+    tauri::async_runtime::spawn(async move {
+        while let Ok(ProfileEvent { profile, event }) = rx.recv().await {
+            let Some(profiles) = PROFILES.get() else {
+                continue;
+            };
+            // Closed while the event was queued
+            let Some(profile) = profiles.get(&profile).await else {
+                continue;
+            };
+
+            remove_expired_state(&profile, &event).await;
+
+            if profiles.is_active(&profile.id).await {
+                emit_event(&app_handle, event);
+            } else {
+                notify_background_event(&app_handle, &profile, &event).await;
+            }
+        }
+    });
+}
 
-    // Register Firefly Client
-    if let Some(client) = FIREFLY_CLIENT.get() {
-        app.manage(client.clone());
+/// Drops attachments and notifications of messages that are gone.
+async fn remove_expired_state(profile: &Profile, event: &FireflyEvent) {
+    match event {
+        FireflyEvent::UserMessageUpdated(user_message) if user_message.deleted => {
+            if let Ok(notification_store) = NotificationStore::new(profile.database.clone()).await {
+                let _ = notification_store
+                    .delete_of_sender(&user_message.other, &[user_message.id as i64])
+                    .await;
+            }
+            let _ = profile
+                .attachments
+                .remove_user_messages(user_message.other.clone(), vec![user_message.id])
+                .await;
+        }
+        FireflyEvent::GroupMessageUpdated(group_message) if group_message.deleted => {
+            let _ = profile
+                .attachments
+                .remove_group_messages(group_message.group_id, vec![group_message.id])
+                .await;
+        }
+        FireflyEvent::UserMessagesExpired(other, message_ids) => {
+            if let Ok(notification_store) = NotificationStore::new(profile.database.clone()).await {
+                let ids = message_ids.iter().map(|id| *id as i64).collect::<Vec<_>>();
+                let _ = notification_store.delete_of_sender(other, &ids).await;
+            }
+            let _ = profile
+                .attachments
+                .remove_user_messages(other.clone(), message_ids.to_vec())
+                .await;
+        }
+        FireflyEvent::GroupMessagesExpired(group_id, message_ids) => {
+            let _ = profile
+                .attachments
+                .remove_group_messages(*group_id, message_ids.to_vec())
+                .await;
+        }
+        _ => {}
     }
+}
 
-    // Register File Server
-    if let Some(server) = FILE_SERVER.get() {
-        app.manage(server.clone());
+/// Whether a new message from `other` notifies, following the mute and
+/// notification level settings of the conversation.
+async fn user_message_notifies(profile: &Profile, other: &str) -> bool {
+    match profile
+        .client
+        .get_conversation_settings(other.to_string())
+        .await
+    {
+        Ok(settings) => settings
+            .unwrap_or_default()
+            .notifies(get_current_timestamp_millis_since_epoch(), true),
+        Err(err) => {
+            log::warn!("failed to get settings of conversation {}: {}", other, err);
+            true
+        }
     }
+}
 
-    // Spawn event listener
-    let mut rx = EVENT_CHANNEL.subscribe();
-    let app_handle = app.clone();
+/// Group counterpart of `user_message_notifies`.
+async fn group_message_notifies(profile: &Profile, group_id: u64) -> bool {
+    match profile.client.get_group_settings(group_id).await {
+        Ok(settings) => settings
+            .unwrap_or_default()
+            .notifies(get_current_timestamp_millis_since_epoch(), false),
+        Err(err) => {
+            log::warn!("failed to get settings of group {}: {}", group_id, err);
+            true
+        }
+    }
+}
 
-    tauri::async_runtime::spawn(async move {
-        while let Ok(event) = rx.recv().await {
-            match event {
-                FireflyEvent::UserMessage(user_message) => {
-                    let b_message = user_message_to_b_user_message(&user_message);
-                    let _ = app_handle.emit("onUserMessage", &b_message);
+/// The frontend only shows the active profile, inactive ones with background
+/// sync just get a notification tagged with the account for new messages of
+/// conversations that aren't muted.
+async fn notify_background_event<R: Runtime>(
+    app_handle: &AppHandle<R>,
+    profile: &Profile,
+    event: &FireflyEvent,
+) {
+    let (title, messages) = match event {
+        FireflyEvent::UserMessage(user_message)
+            if user_message.sent_by_other
+                && user_message_notifies(profile, &user_message.other).await =>
+        {
+            (format!("Message from {}", user_message.other), 1)
+        }
+        FireflyEvent::GroupMessage(group_message)
+            if group_message_notifies(profile, group_message.group_id).await =>
+        {
+            ("New group message".to_string(), 1)
+        }
+        FireflyEvent::MessagesBatch(user_messages, group_messages) => {
+            let mut count = 0;
+            for user_message in user_messages.iter().filter(|m| m.sent_by_other) {
+                if user_message_notifies(profile, &user_message.other).await {
+                    count += 1;
                 }
-                FireflyEvent::GroupMessage(group_message) => {
-                    let b_message = group_message_to_b_group_message(&group_message);
-                    let _ = app_handle.emit("onGroupMessage", &b_message);
+            }
+            for group_message in group_messages.iter() {
+                if group_message_notifies(profile, group_message.group_id).await {
+                    count += 1;
                 }
             }
+            (format!("{} new messages", count), count)
         }
-    });
+        _ => return,
+    };
+    let profile = &profile.id;
+
+    if messages == 0 {
+        return;
+    }
+
+    let _ = app_handle.emit(
+        "onProfileMessages",
+        &ProfileMessagesEvent {
+            profile: profile.to_string(),
+            messages,
+        },
+    );
+
+    if let Err(err) = app_handle
+        .notification()
+        .builder()
+        .title(format!("{} ({})", title, profile))
+        .body("New message received")
+        .show()
+    {
+        log::error!(
+            "failed to show notification of profile {}: {}",
+            profile,
+            err
+        );
+    }
 }
 
-struct GlobalFireflyCallback;
+fn emit_event<R: Runtime>(app_handle: &AppHandle<R>, event: FireflyEvent) {
+    match event {
+        FireflyEvent::UserMessage(user_message) => {
+            let b_message = user_message_to_b_user_message(&user_message);
+            let _ = app_handle.emit("onUserMessage", &b_message);
+        }
+        FireflyEvent::UserMessageUpdated(user_message) => {
+            let b_message = user_message_to_b_user_message(&user_message);
+            let _ = app_handle.emit("onUserMessageUpdated", &b_message);
+        }
+        FireflyEvent::GroupMessageUpdated(group_message) => {
+            let b_message = group_message_to_b_group_message(&group_message);
+            let _ = app_handle.emit("onGroupMessageUpdated", &b_message);
+        }
+        FireflyEvent::UserMessageReaction(other, message_id, reactor, emoji) => {
+            let event = ReactionEvent {
+                other: Some(other),
+                group_id: None,
+                message_id,
+                reactor,
+                emoji,
+            };
+            let _ = app_handle.emit("onUserMessageReaction", &event);
+        }
+        FireflyEvent::GroupMessageReaction(group_id, message_id, reactor, emoji) => {
+            let event = ReactionEvent {
+                other: None,
+                group_id: Some(group_id),
+                message_id,
+                reactor,
+                emoji,
+            };
+            let _ = app_handle.emit("onGroupMessageReaction", &event);
+        }
+        FireflyEvent::UserMessagesExpired(other, message_ids) => {
+            let event = MessagesExpiredEvent {
+                other: Some(other),
+                group_id: None,
+                message_ids: message_ids.to_vec(),
+            };
+            let _ = app_handle.emit("onUserMessagesExpired", &event);
+        }
+        FireflyEvent::BlockedChanged(username, blocked) => {
+            let event = BlockedChangedEvent { username, blocked };
+            let _ = app_handle.emit("onBlockedChanged", &event);
+        }
+        FireflyEvent::GroupMessagesExpired(group_id, message_ids) => {
+            let event = MessagesExpiredEvent {
+                other: None,
+                group_id: Some(group_id),
+                message_ids: message_ids.to_vec(),
+            };
+            let _ = app_handle.emit("onGroupMessagesExpired", &event);
+        }
+        FireflyEvent::GroupMessage(group_message) => {
+            let b_message = group_message_to_b_group_message(&group_message);
+            let _ = app_handle.emit("onGroupMessage", &b_message);
+        }
+        // the frontend shows messages and their notifications per message, a
+        // batch is emitted as the events it would have been without batching
+        FireflyEvent::MessagesBatch(user_messages, group_messages) => {
+            for user_message in user_messages.iter() {
+                let b_message = user_message_to_b_user_message(user_message);
+                let _ = app_handle.emit("onUserMessage", &b_message);
+            }
+            for group_message in group_messages.iter() {
+                let b_message = group_message_to_b_group_message(group_message);
+                let _ = app_handle.emit("onGroupMessage", &b_message);
+            }
+        }
+        FireflyEvent::Receipts(other, state, message_ids) => {
+            let event = ReceiptsEvent {
+                other,
+                state: receipt_state_name(state).to_string(),
+                message_ids: message_ids.to_vec(),
+            };
+            let _ = app_handle.emit("onReceipts", &event);
+        }
+        FireflyEvent::OutboxStatus(outbox_message) => {
+            let b_message = outbox_message_to_b_outbox_message(&outbox_message);
+            let _ = app_handle.emit("onOutboxStatusChanged", &b_message);
+        }
+        FireflyEvent::IdentityChanged(username) => {
+            let event = IdentityChangedEvent { username };
+            let _ = app_handle.emit("onIdentityChanged", &event);
+        }
+        FireflyEvent::Typing(conversation, username, typing) => {
+            let (other, group_id, channel_id) = match conversation {
+                TypingConversation::User(other) => (Some(other), None, None),
+                TypingConversation::Group {
+                    group_id,
+                    channel_id,
+                } => (None, Some(group_id), Some(channel_id)),
+            };
+            let event = TypingEvent {
+                other,
+                group_id,
+                channel_id,
+                username,
+                typing,
+            };
+            let _ = app_handle.emit("onTyping", &event);
+        }
+        FireflyEvent::ConnectionState(state) => {
+            let b_state = connection_state_to_b_connection_state(&state);
+            let _ = app_handle.emit("onConnectionStateChanged", &b_state);
+        }
+    }
+}
+
+/// Forwards the events of a profile's client to the listener of
+/// `register_state`.
+pub(crate) struct ProfileFireflyCallback {
+    profile: Arc<str>,
+}
+
+impl ProfileFireflyCallback {
+    pub(crate) fn new(profile: String) -> Self {
+        Self {
+            profile: profile.into(),
+        }
+    }
+
+    fn send(&self, event: FireflyEvent) {
+        let _ = EVENT_CHANNEL.send(ProfileEvent {
+            profile: self.profile.clone(),
+            event,
+        });
+    }
+}
 
 #[async_trait::async_trait]
-impl FireflyWsClientCallback for GlobalFireflyCallback {
+impl FireflyWsClientCallback for ProfileFireflyCallback {
     async fn on_message(&self, message: UserMessage) {
-        let _ = EVENT_CHANNEL.send(FireflyEvent::UserMessage(Arc::new(message)));
+        self.send(FireflyEvent::UserMessage(Arc::new(message)));
     }
 
     async fn on_group_message(&self, message: GroupMessage) {
-        let _ = EVENT_CHANNEL.send(FireflyEvent::GroupMessage(Arc::new(message)));
+        self.send(FireflyEvent::GroupMessage(Arc::new(message)));
+    }
+
+    async fn on_messages_batch(
+        &self,
+        user_messages: Vec<UserMessage>,
+        group_messages: Vec<GroupMessage>,
+    ) {
+        self.send(FireflyEvent::MessagesBatch(
+            Arc::new(user_messages),
+            Arc::new(group_messages),
+        ));
+    }
+
+    async fn on_user_message_updated(&self, message: UserMessage) {
+        self.send(FireflyEvent::UserMessageUpdated(Arc::new(message)));
+    }
+
+    async fn on_group_message_updated(&self, message: GroupMessage) {
+        self.send(FireflyEvent::GroupMessageUpdated(Arc::new(message)));
+    }
+
+    async fn on_user_message_reaction(
+        &self,
+        other: String,
+        message_id: u64,
+        reactor: String,
+        emoji: String,
+    ) {
+        self.send(FireflyEvent::UserMessageReaction(
+            other, message_id, reactor, emoji,
+        ));
+    }
+
+    async fn on_group_message_reaction(
+        &self,
+        group_id: u64,
+        message_id: u64,
+        reactor: String,
+        emoji: String,
+    ) {
+        self.send(FireflyEvent::GroupMessageReaction(
+            group_id, message_id, reactor, emoji,
+        ));
+    }
+
+    async fn on_user_messages_expired(&self, other: String, message_ids: Vec<u64>) {
+        self.send(FireflyEvent::UserMessagesExpired(
+            other,
+            Arc::new(message_ids),
+        ));
+    }
+
+    async fn on_blocked_changed(&self, username: String, blocked: bool) {
+        self.send(FireflyEvent::BlockedChanged(username, blocked));
+    }
+
+    async fn on_group_messages_expired(&self, group_id: u64, message_ids: Vec<u64>) {
+        self.send(FireflyEvent::GroupMessagesExpired(
+            group_id,
+            Arc::new(message_ids),
+        ));
+    }
+
+    async fn on_receipts(&self, other: String, state: ReceiptState, message_ids: Vec<u64>) {
+        self.send(FireflyEvent::Receipts(other, state, Arc::new(message_ids)));
+    }
+
+    async fn on_outbox_status_changed(&self, message: OutboxMessage) {
+        self.send(FireflyEvent::OutboxStatus(Arc::new(message)));
+    }
+
+    async fn on_identity_changed(&self, username: String) {
+        self.send(FireflyEvent::IdentityChanged(username));
+    }
+
+    async fn on_typing(&self, conversation: TypingConversation, username: String, typing: bool) {
+        self.send(FireflyEvent::Typing(conversation, username, typing));
+    }
+
+    async fn on_connection_state_changed(&self, state: ConnectionState) {
+        self.send(FireflyEvent::ConnectionState(state));
+    }
+}
+
+fn connection_state_to_b_connection_state(state: &ConnectionState) -> BConnectionState {
+    let mut b_state = BConnectionState {
+        state: String::new(),
+        since: None,
+        rtt_ms: None,
+        next_attempt_at: None,
+        attempt: None,
+        reason: None,
+    };
+    b_state.state = match state {
+        ConnectionState::Disconnected => "disconnected",
+        ConnectionState::AwaitingLogin => "awaitingLogin",
+        ConnectionState::CheckingSetup => "checkingSetup",
+        ConnectionState::Connecting => "connecting",
+        ConnectionState::Syncing { since } => {
+            b_state.since = Some(*since);
+            "syncing"
+        }
+        ConnectionState::Connected { since, rtt_ms } => {
+            b_state.since = Some(*since);
+            b_state.rtt_ms = *rtt_ms;
+            "connected"
+        }
+        ConnectionState::Backoff {
+            next_attempt_at,
+            attempt,
+            reason,
+        } => {
+            b_state.next_attempt_at = Some(*next_attempt_at);
+            b_state.attempt = Some(*attempt);
+            b_state.reason = Some(reason.clone());
+            "backoff"
+        }
+    }
+    .to_string();
+    b_state
+}
+
+fn identity_trust_to_b_identity_trust(trust: &IdentityTrust) -> BIdentityTrust {
+    let state = match trust.state {
+        IdentityTrustState::Unverified => "unverified",
+        IdentityTrustState::Verified => "verified",
+        IdentityTrustState::Changed => "changed",
+    };
+
+    BIdentityTrust {
+        username: trust.username.clone(),
+        device_id: trust.device_id,
+        state: state.to_string(),
+    }
+}
+
+fn outbox_message_to_b_outbox_message(msg: &OutboxMessage) -> BOutboxMessage {
+    let status = match msg.status {
+        OutboxStatus::Pending => "pending",
+        OutboxStatus::Sent => "sent",
+        OutboxStatus::Failed => "failed",
+    };
+
+    BOutboxMessage {
+        id: msg.id,
+        other: msg.other.clone(),
+        status: status.to_string(),
+        attempts: msg.attempts,
+        next_attempt_at: msg.next_attempt_at,
+        last_error: msg.last_error.clone(),
     }
 }
 
@@ -336,6 +896,17 @@ fn user_message_to_b_user_message(msg: &UserMessage) -> BUserMessage {
         other: msg.other.clone(),
         sent_by_other: msg.sent_by_other,
         text_b64: general_purpose::STANDARD.encode(&msg.message),
+        receipt: receipt_state_name(msg.receipt).to_string(),
+        edited_at: msg.edited_at,
+        deleted: msg.deleted,
+    }
+}
+
+fn receipt_state_name(state: ReceiptState) -> &'static str {
+    match state {
+        ReceiptState::Sent => "sent",
+        ReceiptState::Delivered => "delivered",
+        ReceiptState::Read => "read",
     }
 }
 
@@ -347,6 +918,87 @@ fn group_message_to_b_group_message(msg: &GroupMessage) -> BGroupMessage {
         id: msg.id,
         channel_id: msg.channel_id,
         epoch: msg.epoch,
+        edited_at: msg.edited_at,
+        deleted: msg.deleted,
+    }
+}
+
+fn message_edit_to_b_message_edit(edit: &MessageEdit) -> BMessageEdit {
+    BMessageEdit {
+        edited_at: edit.edited_at,
+        text_b64: general_purpose::STANDARD.encode(&edit.message),
+    }
+}
+
+fn settings_to_b_conversation_settings(settings: &ConversationSettings) -> BConversationSettings {
+    BConversationSettings {
+        flags: settings.flags,
+        disappearing_timer_secs: settings.disappearing_timer_secs,
+        muted_until: settings.muted_until,
+        archived: settings.archived,
+        pinned: settings.pinned,
+        blocked: settings.blocked,
+        notification_level: notification_level_name(settings.notification_level).to_string(),
+    }
+}
+
+fn notification_level_name(level: NotificationLevel) -> &'static str {
+    match level {
+        NotificationLevel::All => "all",
+        NotificationLevel::Mentions => "mentions",
+        NotificationLevel::None => "none",
+    }
+}
+
+fn b_conversation_settings_to_settings(
+    settings: &BConversationSettings,
+) -> Result<ConversationSettings, FireflyError> {
+    let notification_level = match settings.notification_level.as_str() {
+        "all" => NotificationLevel::All,
+        "mentions" => NotificationLevel::Mentions,
+        "none" => NotificationLevel::None,
+        other => {
+            return Err(FireflyError::InvalidInput(format!(
+                "Invalid notification level: {}",
+                other
+            )))
+        }
+    };
+
+    Ok(ConversationSettings {
+        flags: settings.flags,
+        disappearing_timer_secs: settings.disappearing_timer_secs,
+        muted_until: settings.muted_until,
+        archived: settings.archived,
+        pinned: settings.pinned,
+        blocked: settings.blocked,
+        notification_level,
+    })
+}
+
+fn reaction_count_to_b_reaction_count(reaction: &ReactionCount) -> BReactionCount {
+    BReactionCount {
+        message_id: reaction.message_id,
+        emoji: reaction.emoji.clone(),
+        count: reaction.count,
+        reactors: reaction.reactors.clone(),
+    }
+}
+
+fn search_hit_to_b_search_hit(hit: &SearchHit) -> BSearchHit {
+    match hit {
+        SearchHit::User(hit) => BSearchHit {
+            snippet: hit.snippet.clone(),
+            rank: hit.rank,
+            user_message: Some(user_message_to_b_user_message(&hit.message)),
+            group_message: None,
+        },
+        SearchHit::Group(hit) => BSearchHit {
+            snippet: hit.snippet.clone(),
+            rank: hit.rank,
+            user_message: None,
+            group_message: Some(group_message_to_b_group_message(&hit.message)),
+        },
     }
 }
 
@@ -363,26 +1015,162 @@ pub async fn encrypt_and_send<R: Runtime>(
     app: AppHandle<R>,
     text_b64: String,
     to: String,
-) -> Result<BUserMessage, String> {
-    let client_state: State<FireflyClient> = app.state();
-    let client = client_state.inner().clone();
+) -> Result<BUserMessage, FireflyError> {
+    let client = active_profile(&app).await?.client.clone();
 
     let message_bytes = general_purpose::STANDARD
         .decode(&text_b64)
-        .map_err(|e| format!("Failed to decode base64: {}", e))?;
+        .map_err(|e| FireflyError::InvalidInput(format!("Failed to decode base64: {}", e)))?;
 
     let user_message = client
         .encrypt_and_send(to, message_bytes)
         .await
-        .map_err(|e| format!("Failed to encrypt and send: {}", e))?;
+        .map_err(|e| e.context("Failed to encrypt and send"))?;
+
+    Ok(user_message_to_b_user_message(&user_message))
+}
 
-    let store_state: State<MessageStore> = app.state();
-    let store = store_state.inner().clone();
+#[command]
+pub async fn edit_message<R: Runtime>(
+    app: AppHandle<R>,
+    to: String,
+    message_id: u64,
+    payload_b64: String,
+) -> Result<BUserMessage, FireflyError> {
+    let client = active_profile(&app).await?.client.clone();
 
-    let b_user_message = user_message_to_b_user_message(&user_message);
-    let _ = store.insert_user_message(user_message).await;
+    let payload = general_purpose::STANDARD
+        .decode(&payload_b64)
+        .map_err(|e| FireflyError::InvalidInput(format!("Failed to decode base64: {}", e)))?;
 
-    Ok(b_user_message)
+    let user_message = client
+        .edit_message(to, message_id, payload)
+        .await
+        .map_err(|e| e.context("Failed to edit message"))?;
+
+    Ok(user_message_to_b_user_message(&user_message))
+}
+
+#[command]
+pub async fn delete_message<R: Runtime>(
+    app: AppHandle<R>,
+    to: String,
+    message_id: u64,
+) -> Result<BUserMessage, FireflyError> {
+    let profile = active_profile(&app).await?;
+    let client = profile.client.clone();
+
+    let user_message = client
+        .delete_message(to, message_id)
+        .await
+        .map_err(|e| e.context("Failed to delete message"))?;
+
+    let _ = profile
+        .attachments
+        .remove_user_messages(user_message.other.clone(), vec![user_message.id])
+        .await;
+
+    Ok(user_message_to_b_user_message(&user_message))
+}
+
+#[command]
+pub async fn get_message_edits<R: Runtime>(
+    app: AppHandle<R>,
+    other: String,
+    message_id: u64,
+) -> Result<MessageEditsResponse, FireflyError> {
+    let store = active_profile(&app).await?.message_store.clone();
+
+    let edits = store
+        .get_user_message_edits(&other, message_id)
+        .await
+        .map_err(|e| e.context("Failed to get message edits"))?;
+
+    let result = edits.iter().map(message_edit_to_b_message_edit).collect();
+    Ok(MessageEditsResponse { result })
+}
+
+#[command]
+pub async fn react_to_message<R: Runtime>(
+    app: AppHandle<R>,
+    to: String,
+    message_id: u64,
+    emoji: String,
+) -> Result<(), FireflyError> {
+    let client = active_profile(&app).await?.client.clone();
+
+    client
+        .react(to, message_id, emoji)
+        .await
+        .map_err(|e| e.context("Failed to react to message"))
+}
+
+#[command]
+pub async fn get_message_reactions<R: Runtime>(
+    app: AppHandle<R>,
+    other: String,
+    message_ids: Vec<u64>,
+) -> Result<MessageReactionsResponse, FireflyError> {
+    let store = active_profile(&app).await?.message_store.clone();
+
+    let reactions = store
+        .get_user_message_reactions(&other, &message_ids)
+        .await
+        .map_err(|e| e.context("Failed to get message reactions"))?;
+
+    let result = reactions
+        .iter()
+        .map(reaction_count_to_b_reaction_count)
+        .collect();
+    Ok(MessageReactionsResponse { result })
+}
+
+#[command]
+pub async fn retry_message<R: Runtime>(app: AppHandle<R>, id: u64) -> Result<(), FireflyError> {
+    let client = active_profile(&app).await?.client.clone();
+
+    client
+        .retry_outbox_message(id)
+        .await
+        .map_err(|e| e.context("Failed to retry message"))
+}
+
+#[command]
+pub async fn get_connection_state<R: Runtime>(
+    app: AppHandle<R>,
+) -> Result<BConnectionState, FireflyError> {
+    let client = active_profile(&app).await?.client.clone();
+
+    Ok(connection_state_to_b_connection_state(
+        &client.get_connection_state(),
+    ))
+}
+
+#[command]
+pub async fn reconnect_now<R: Runtime>(app: AppHandle<R>) -> Result<(), FireflyError> {
+    let client = active_profile(&app).await?.client.clone();
+
+    client.reconnect_now();
+    Ok(())
+}
+
+#[command]
+pub async fn get_outbox_messages<R: Runtime>(
+    app: AppHandle<R>,
+) -> Result<OutboxMessagesResponse, FireflyError> {
+    let client = active_profile(&app).await?.client.clone();
+
+    let messages = client
+        .get_unsent_outbox_messages()
+        .await
+        .map_err(|e| e.context("Failed to get outbox messages"))?;
+
+    let result = messages
+        .iter()
+        .map(outbox_message_to_b_outbox_message)
+        .collect();
+
+    Ok(OutboxMessagesResponse { result })
 }
 
 #[command]
@@ -391,33 +1179,60 @@ pub async fn get_last_messages<R: Runtime>(
     other: String,
     limit: u64,
     before: u64,
-) -> Result<LastMessagesResponse, String> {
-    let store_state: State<MessageStore> = app.state();
-    let store = store_state.inner().clone();
+) -> Result<LastMessagesResponse, FireflyError> {
+    let store = active_profile(&app).await?.message_store.clone();
 
     let messages = store
         .get_last_messages_of(&other, before as i64, limit as i64)
         .await
-        .map_err(|e| format!("Failed to get messages: {}", e))?;
+        .map_err(|e| e.context("Failed to get messages"))?;
+
+    let result = messages
+        .iter()
+        .map(user_message_to_b_user_message)
+        .collect();
+    Ok(LastMessagesResponse { result })
+}
+
+#[command]
+pub async fn search_messages<R: Runtime>(
+    app: AppHandle<R>,
+    query: String,
+    limit: u32,
+) -> Result<SearchMessagesResponse, FireflyError> {
+    let profile = active_profile(&app).await?;
+    let store = profile.message_store.clone();
+
+    let client = profile.client.clone();
+
+    let user_hits = store
+        .search(&query, limit)
+        .await
+        .map_err(|e| e.context("Failed to search messages"))?;
+
+    let group_hits = client
+        .group_message_store()
+        .search_ffi(query, limit)
+        .await
+        .map_err(|e| e.context("Failed to search group messages"))?;
 
-    let result = messages
+    let result = merge_hits(user_hits, group_hits, limit as usize)
         .iter()
-        .map(user_message_to_b_user_message)
+        .map(search_hit_to_b_search_hit)
         .collect();
-    Ok(LastMessagesResponse { result })
+    Ok(SearchMessagesResponse { result })
 }
 
 #[command]
 pub async fn get_last_messages_from_all_conversations<R: Runtime>(
     app: AppHandle<R>,
-) -> Result<AllConversationsResponse, String> {
-    let store_state: State<MessageStore> = app.state();
-    let store = store_state.inner().clone();
+) -> Result<AllConversationsResponse, FireflyError> {
+    let store = active_profile(&app).await?.message_store.clone();
 
     let conversations = store
         .get_last_message_from_all_conversations()
         .await
-        .map_err(|e| format!("Failed to get conversations: {}", e))?;
+        .map_err(|e| e.context("Failed to get conversations"))?;
 
     let result = conversations
         .iter()
@@ -435,9 +1250,8 @@ pub async fn save_tokens<R: Runtime>(
     app: AppHandle<R>,
     access_token: String,
     refresh_token: String,
-) -> Result<(), String> {
-    let client_state: State<FireflyClient> = app.state();
-    let client = client_state.inner().clone();
+) -> Result<(), FireflyError> {
+    let client = active_profile(&app).await?.client.clone();
 
     let tokens = TokenResponse {
         access_token,
@@ -447,7 +1261,7 @@ pub async fn save_tokens<R: Runtime>(
     client
         .set_auth_tokens(tokens)
         .await
-        .map_err(|e| format!("Failed to save tokens: {}", e))?;
+        .map_err(|e| e.context("Failed to save tokens"))?;
 
     // Run check_setup now that we have a valid token.
     // This uploads pre-key bundles so other users can encrypt messages to this account.
@@ -467,17 +1281,16 @@ pub async fn mark_as_read_until<R: Runtime>(
     app: AppHandle<R>,
     username: String,
     ts: u64,
-) -> Result<(), String> {
-    let store_state: State<MessageStore> = app.state();
-    let store = store_state.inner().clone();
+) -> Result<(), FireflyError> {
+    let profile = active_profile(&app).await?;
+    let client = profile.client.clone();
 
-    store
-        .mark_as_read_until(&username, ts as i64)
+    client
+        .mark_as_read_until(username.clone(), ts)
         .await
-        .map_err(|e| format!("Failed to mark as read: {}", e))?;
+        .map_err(|e| e.context("Failed to mark as read"))?;
 
-    let db_state: State<Database> = app.state();
-    let pool = db_state.inner().clone();
+    let pool = profile.database.clone();
     if let Ok(notification_store) = NotificationStore::new(pool).await {
         let _ = notification_store
             .delete_until_of_sender(&username, ts as i64)
@@ -491,13 +1304,24 @@ pub async fn mark_as_read_until<R: Runtime>(
 pub async fn show_user_notification<R: Runtime>(
     app: AppHandle<R>,
     message: BUserMessage,
-) -> Result<(), String> {
+) -> Result<(), FireflyError> {
+    let profile = active_profile(&app).await?;
+    if !user_message_notifies(&profile, &message.other).await {
+        return Ok(());
+    }
+
+    let mut title = format!("Message from {}", message.other);
+    let profiles: State<Profiles> = app.state();
+    if profiles.has_multiple().await {
+        title = format!("{} ({})", title, profile.id);
+    }
+
     app.notification()
         .builder()
-        .title(format!("Message from {}", message.other))
+        .title(title)
         .body("New message received")
         .show()
-        .map_err(|e| format!("Failed to show notification: {}", e))?;
+        .map_err(|e| FireflyError::from(e).context("Failed to show notification"))?;
 
     Ok(())
 }
@@ -508,34 +1332,40 @@ pub async fn show_call_notification<R: Runtime>(
     caller: String,
     _session_id: u32,
     _conversation_id: u32,
-) -> Result<(), String> {
+) -> Result<(), FireflyError> {
     app.notification()
         .builder()
         .title("Incoming Call")
         .body(format!("{} is calling...", caller))
         .show()
-        .map_err(|e| format!("Failed to show call notification: {}", e))?;
+        .map_err(|e| FireflyError::from(e).context("Failed to show call notification"))?;
 
     Ok(())
 }
 
 #[command]
-pub async fn clear_notifications<R: Runtime>(app: AppHandle<R>) -> Result<(), String> {
+pub async fn clear_notifications<R: Runtime>(app: AppHandle<R>) -> Result<(), FireflyError> {
+    let profile = active_profile(&app).await?;
     let handler = app.state::<Arc<NotificationHandler<R>>>();
-    handler.clear_all().await?;
+    handler
+        .clear_all(&profile.database)
+        .await
+        .map_err(|e| FireflyError::Other(format!("Failed to clear notifications: {}", e)))?;
     Ok(())
 }
 
 #[command]
 pub async fn get_file_server_url<R: Runtime>(
     app: AppHandle<R>,
-) -> Result<FileServerResponse, String> {
+) -> Result<FileServerResponse, FireflyError> {
+    let file_server = active_profile(&app).await?.file_server.clone();
+
     // Wait up to 5 seconds for the file server to be ready
     let port = tokio::time::timeout(
         std::time::Duration::from_secs(5),
         async {
             loop {
-                if let Some(p) = FILE_SERVER_READY.get() {
+                if let Some(p) = file_server.port.get() {
                     return *p;
                 }
                 tokio::time::sleep(std::time::Duration::from_millis(100)).await;
@@ -543,10 +1373,9 @@ pub async fn get_file_server_url<R: Runtime>(
         },
     )
     .await
-    .map_err(|_| "File server not ready".to_string())?;
+    .map_err(|_| FireflyError::Other("File server not ready".to_string()))?;
 
-    let server_state: State<FileServer> = app.state();
-    let server = server_state.inner().clone();
+    let server = file_server.server;
 
     Ok(FileServerResponse {
         url: format!("http://localhost:{}", port),
@@ -554,15 +1383,167 @@ pub async fn get_file_server_url<R: Runtime>(
     })
 }
 
+fn encrypted_file_to_b_encrypted_file(file: pb::firefly::firefly::EncryptedFile) -> BEncryptedFile {
+    BEncryptedFile {
+        url: file.url,
+        secret_key_b64: general_purpose::STANDARD.encode(&file.secret_key),
+        content_type: file.content_type,
+        content_length: file.content_length,
+        metadata: file.metadata.map(|metadata| BMediaMetadata {
+            mime_type: metadata.mime_type,
+            width: metadata.width,
+            height: metadata.height,
+            duration_millis: metadata.duration_millis,
+            thumbnail_b64: general_purpose::STANDARD.encode(&metadata.thumbnail),
+        }),
+    }
+}
+
+fn b_encrypted_file_to_encrypted_file(
+    file: BEncryptedFile,
+) -> Result<pb::firefly::firefly::EncryptedFile, FireflyError> {
+    let secret_key = general_purpose::STANDARD
+        .decode(&file.secret_key_b64)
+        .map_err(|e| FireflyError::InvalidInput(format!("Failed to decode base64: {}", e)))?;
+
+    let metadata = match file.metadata {
+        Some(metadata) => Some(pb::firefly::firefly::MediaMetadata {
+            mime_type: metadata.mime_type,
+            width: metadata.width,
+            height: metadata.height,
+            duration_millis: metadata.duration_millis,
+            thumbnail: general_purpose::STANDARD
+                .decode(&metadata.thumbnail_b64)
+                .map_err(|e| {
+                    FireflyError::InvalidInput(format!("Failed to decode base64: {}", e))
+                })?,
+        }),
+        None => None,
+    };
+
+    Ok(pb::firefly::firefly::EncryptedFile {
+        url: file.url,
+        secret_key,
+        content_type: file.content_type,
+        content_length: file.content_length,
+        metadata,
+    })
+}
+
+/// Stores the raw request body as an attachment to upload, returns the path
+/// `upload_attachment` takes for it.
+#[command]
+pub async fn stage_attachment<R: Runtime>(
+    app: AppHandle<R>,
+    request: tauri::ipc::Request<'_>,
+) -> Result<String, FireflyError> {
+    let tauri::ipc::InvokeBody::Raw(data) = request.body() else {
+        return Err(FireflyError::InvalidInput(
+            "Expected the raw attachment bytes".to_string(),
+        ));
+    };
+
+    let path = active_profile(&app).await?.stage_attachment(data).await?;
+    Ok(path.to_string_lossy().to_string())
+}
+
+/// Uploads an attachment staged by `stage_attachment` and removes it.
+#[command]
+pub async fn upload_attachment<R: Runtime>(
+    app: AppHandle<R>,
+    path: String,
+    content_type: u32,
+) -> Result<BEncryptedFile, FireflyError> {
+    let profile = active_profile(&app).await?;
+    let path = profile.staged_attachment(&path).await?;
+
+    let file = profile
+        .client
+        .upload_attachment(path.to_string_lossy().to_string(), content_type)
+        .await;
+    if let Err(err) = tokio::fs::remove_file(&path).await {
+        log::warn!("failed to remove staged attachment {:?}: {}", path, err);
+    }
+    let file = file.map_err(|e| e.context("Failed to upload attachment"))?;
+
+    Ok(encrypted_file_to_b_encrypted_file(file))
+}
+
+/// Returns the name the decrypted file is served under by the file server.
+/// Attachments belong to the direct message `message_id` with `other` or the
+/// group message in `group_id`, `index` is their position in the message.
+#[command]
+pub async fn download_attachment<R: Runtime>(
+    app: AppHandle<R>,
+    file: BEncryptedFile,
+    other: Option<String>,
+    group_id: Option<u64>,
+    message_id: u64,
+    index: u32,
+) -> Result<String, FireflyError> {
+    let cache = active_profile(&app).await?.attachments.clone();
+
+    let owner = match (other, group_id) {
+        (Some(other), None) => AttachmentOwner::User { other, message_id },
+        (None, Some(group_id)) => AttachmentOwner::Group {
+            group_id,
+            message_id,
+        },
+        _ => {
+            return Err(FireflyError::InvalidInput(
+                "Exactly one of other and groupId is required".to_string(),
+            ))
+        }
+    };
+
+    let file = b_encrypted_file_to_encrypted_file(file)?;
+
+    cache
+        .fetch(file, owner, index)
+        .await
+        .map_err(|e| e.context("Failed to download attachment"))
+}
+
+#[command]
+pub async fn get_attachment_cache_usage<R: Runtime>(
+    app: AppHandle<R>,
+) -> Result<AttachmentCacheUsageResponse, FireflyError> {
+    let cache = active_profile(&app).await?.attachments.clone();
+
+    let usage = cache
+        .usage()
+        .await
+        .map_err(|e| e.context("Failed to get attachment cache usage"))?;
+
+    Ok(AttachmentCacheUsageResponse {
+        files: usage.files,
+        bytes: usage.bytes,
+        quota_bytes: cache.quota(),
+    })
+}
+
+#[command]
+pub async fn set_attachment_cache_quota<R: Runtime>(
+    app: AppHandle<R>,
+    quota_bytes: u64,
+) -> Result<(), FireflyError> {
+    let cache = active_profile(&app).await?.attachments.clone();
+
+    cache
+        .set_quota(quota_bytes)
+        .await
+        .map_err(|e| e.context("Failed to set attachment cache quota"))
+}
+
 #[command]
 pub async fn request_all_required_permissions<R: Runtime>(
     app: AppHandle<R>,
     _permissions: Vec<String>,
-) -> Result<bool, String> {
+) -> Result<bool, FireflyError> {
     app.state::<Arc<NotificationHandler<R>>>()
         .request_all_permissions()
         .await
-        .map_err(|e| format!("Failed to request permissions: {}", e))?;
+        .map_err(|e| FireflyError::Other(format!("Failed to request permissions: {}", e)))?;
 
     Ok(true)
 }
@@ -571,22 +1552,20 @@ pub async fn request_all_required_permissions<R: Runtime>(
 pub async fn handle_message<R: Runtime>(
     _app: AppHandle<R>,
     _message: BUserMessage,
-) -> Result<(), String> {
+) -> Result<(), FireflyError> {
     Ok(())
 }
 
 #[command]
 pub async fn is_ready() -> bool {
-    // All stores must be initialized AND the firefly client must have
+    // The active profile must be open AND its firefly client must have
     // completed check_setup (address_id != 0 means pre-key bundles are uploaded)
-    if let Some(client) = FIREFLY_CLIENT.get() {
-        let is_setup_done = client.is_setup_done();
-        FIREFLY_CLIENT.get().is_some()
-            && MESSAGE_STORE.get().is_some()
-            && DATABASE.get().is_some()
-            && is_setup_done
-    } else {
-        false
+    match PROFILES.get() {
+        Some(profiles) => match profiles.active().await {
+            Ok(profile) => profile.client.is_setup_done(),
+            Err(_) => false,
+        },
+        None => false,
     }
 }
 
@@ -594,7 +1573,7 @@ pub async fn is_ready() -> bool {
 pub async fn test_method<R: Runtime>(
     _app: AppHandle<R>,
     data: serde_json::Value,
-) -> Result<serde_json::Value, String> {
+) -> Result<serde_json::Value, FireflyError> {
     let mut result = serde_json::json!({
         "receivedAt": chrono::Utc::now().timestamp_millis()
     });
@@ -609,30 +1588,101 @@ pub async fn test_method<R: Runtime>(
 }
 
 #[command]
-pub async fn dispose<R: Runtime>(app: AppHandle<R>) -> Result<(), String> {
-    let client_state: State<FireflyClient> = app.state();
-    let client = client_state.inner().clone();
-    client.dispose().await;
+pub async fn dispose<R: Runtime>(app: AppHandle<R>) -> Result<(), FireflyError> {
+    let profiles: State<Profiles> = app.state();
+    profiles.dispose().await;
+
+    Ok(())
+}
+
+/// Deregisters this device and deletes its keys, and the message history
+/// unless `keep_history`. The profile then waits for the next login.
+#[command]
+pub async fn logout<R: Runtime>(app: AppHandle<R>, keep_history: bool) -> Result<(), FireflyError> {
+    let profile = active_profile(&app).await?;
+    profile
+        .client
+        .logout(keep_history)
+        .await
+        .map_err(|e| e.context("Failed to log out"))?;
+
+    if !keep_history {
+        let handler = app.state::<Arc<NotificationHandler<R>>>();
+        handler
+            .clear_all(&profile.database)
+            .await
+            .map_err(|e| FireflyError::Other(format!("Failed to clear notifications: {}", e)))?;
+        profile
+            .attachments
+            .remove_all()
+            .await
+            .map_err(|e| e.context("Failed to remove attachments"))?;
+    }
+
+    Ok(())
+}
+
+#[command]
+pub async fn get_profiles<R: Runtime>(app: AppHandle<R>) -> Result<ProfilesResponse, FireflyError> {
+    let profiles: State<Profiles> = app.state();
+
+    Ok(ProfilesResponse {
+        result: profiles.list().await,
+        background_sync: profiles.background_sync().await,
+    })
+}
+
+/// Creates an empty profile waiting for login, switch to it to log in.
+#[command]
+pub async fn add_profile<R: Runtime>(app: AppHandle<R>, id: String) -> Result<(), FireflyError> {
+    let profiles: State<Profiles> = app.state();
+    profiles
+        .add(&id)
+        .await
+        .map_err(|e| e.context("Failed to add profile"))
+}
+
+/// Every command acts on the new profile afterwards, the frontend reloads its
+/// state on `onProfileSwitched`.
+#[command]
+pub async fn switch_profile<R: Runtime>(app: AppHandle<R>, id: String) -> Result<(), FireflyError> {
+    let profiles: State<Profiles> = app.state();
+    let profile = profiles
+        .switch(&id)
+        .await
+        .map_err(|e| e.context("Failed to switch profile"))?;
 
-    let db_state: State<Database> = app.state();
-    let pool = db_state.inner().clone();
-    pool.close().await;
+    let _ = app.emit("onProfileSwitched", &profile.id);
+    let b_state = connection_state_to_b_connection_state(&profile.client.get_connection_state());
+    let _ = app.emit("onConnectionStateChanged", &b_state);
 
     Ok(())
 }
 
+/// Whether inactive profiles stay connected and notify about new messages.
+#[command]
+pub async fn set_background_sync<R: Runtime>(
+    app: AppHandle<R>,
+    enabled: bool,
+) -> Result<(), FireflyError> {
+    let profiles: State<Profiles> = app.state();
+    profiles
+        .set_background_sync(enabled)
+        .await
+        .map_err(|e| e.context("Failed to set background sync"))
+}
+
 #[command]
 pub async fn get_last_group_messages<R: Runtime>(
     app: AppHandle<R>,
-) -> Result<LastGroupMessagesResponse, String> {
-    let client_state: State<FireflyClient> = app.state();
-    let client = client_state.inner().clone();
+) -> Result<LastGroupMessagesResponse, FireflyError> {
+    let client = active_profile(&app).await?.client.clone();
 
     let messages = client
         .group_message_store()
         .get_all_last_messages_ffi()
         .await
-        .map_err(|e| format!("Failed to get group messages: {}", e))?;
+        .map_err(|e| e.context("Failed to get group messages"))?;
 
     let result = messages
         .iter()
@@ -646,34 +1696,61 @@ pub async fn encrypt_and_send_group_message<R: Runtime>(
     app: AppHandle<R>,
     text_b64: String,
     group_id: u64,
-) -> Result<MessageIdResponse, String> {
-    let client_state: State<FireflyClient> = app.state();
-    let client = client_state.inner().clone();
+) -> Result<MessageIdResponse, FireflyError> {
+    let client = active_profile(&app).await?.client.clone();
 
     let message_bytes = general_purpose::STANDARD
         .decode(&text_b64)
-        .map_err(|e| format!("Failed to decode base64: {}", e))?;
+        .map_err(|e| FireflyError::InvalidInput(format!("Failed to decode base64: {}", e)))?;
 
     let message_id = client
         .encrypt_and_send_group(group_id, message_bytes)
         .await
-        .map_err(|e| format!("Failed to encrypt and send group message: {}", e))?;
+        .map_err(|e| e.context("Failed to encrypt and send group message"))?;
 
     Ok(MessageIdResponse { message_id })
 }
 
+#[command]
+pub async fn send_typing<R: Runtime>(
+    app: AppHandle<R>,
+    to: String,
+    typing: bool,
+) -> Result<(), FireflyError> {
+    let client = active_profile(&app).await?.client.clone();
+
+    client
+        .send_typing(to, typing)
+        .await
+        .map_err(|e| e.context("Failed to send typing indicator"))
+}
+
+#[command]
+pub async fn send_group_typing<R: Runtime>(
+    app: AppHandle<R>,
+    group_id: u64,
+    channel_id: u32,
+    typing: bool,
+) -> Result<(), FireflyError> {
+    let client = active_profile(&app).await?.client.clone();
+
+    client
+        .send_group_typing(group_id, channel_id, typing)
+        .await
+        .map_err(|e| e.context("Failed to send group typing indicator"))
+}
+
 #[command]
 pub async fn get_group_extension<R: Runtime>(
     app: AppHandle<R>,
     group_id: u64,
-) -> Result<GroupExtensionResponse, String> {
-    let client_state: State<FireflyClient> = app.state();
-    let client = client_state.inner().clone();
+) -> Result<GroupExtensionResponse, FireflyError> {
+    let client = active_profile(&app).await?.client.clone();
 
     let extension = client
         .get_group_extension(group_id)
         .await
-        .map_err(|e| format!("Failed to get group extension: {}", e))?;
+        .map_err(|e| e.context("Failed to get group extension"))?;
 
     Ok(GroupExtensionResponse {
         result_b64: general_purpose::STANDARD.encode(&extension),
@@ -684,28 +1761,28 @@ pub async fn get_group_extension<R: Runtime>(
 pub async fn create_group<R: Runtime>(
     app: AppHandle<R>,
     group_name: String,
-) -> Result<BGroupInfo, String> {
-    let client_state: State<FireflyClient> = app.state();
-    let client = client_state.inner().clone();
+) -> Result<BGroupInfo, FireflyError> {
+    let client = active_profile(&app).await?.client.clone();
 
     let group_info = client
         .create_group(group_name, "".into())
         .await
-        .map_err(|e| format!("Failed to create group: {}", e))?;
+        .map_err(|e| e.context("Failed to create group"))?;
 
     Ok(group_info_to_b_group_info(&group_info))
 }
 
 #[command]
-pub async fn get_group_infos<R: Runtime>(app: AppHandle<R>) -> Result<GroupInfosResponse, String> {
-    let client_state: State<FireflyClient> = app.state();
-    let client = client_state.inner().clone();
+pub async fn get_group_infos<R: Runtime>(
+    app: AppHandle<R>,
+) -> Result<GroupInfosResponse, FireflyError> {
+    let client = active_profile(&app).await?.client.clone();
 
     let groups = client
         .group_info_store()
         .get_all_ffi()
         .await
-        .map_err(|e| format!("Failed to get group infos: {}", e))?;
+        .map_err(|e| e.context("Failed to get group infos"))?;
 
     let result = groups.iter().map(group_info_to_b_group_info).collect();
     Ok(GroupInfosResponse { result })
@@ -715,20 +1792,19 @@ pub async fn get_group_infos<R: Runtime>(app: AppHandle<R>) -> Result<GroupInfos
 pub async fn get_group_info_and_extension<R: Runtime>(
     app: AppHandle<R>,
     group_id: u64,
-) -> Result<GroupInfoAndExtension, String> {
-    let client_state: State<FireflyClient> = app.state();
-    let client = client_state.inner().clone();
+) -> Result<GroupInfoAndExtension, FireflyError> {
+    let client = active_profile(&app).await?.client.clone();
 
     let group_info = client
         .group_info_store()
         .get_ffi(group_id)
         .await
-        .map_err(|e| format!("Failed to get group info: {}", e))?;
+        .map_err(|e| e.context("Failed to get group info"))?;
 
     let extension = client
         .get_group_extension(group_id)
         .await
-        .map_err(|e| format!("Failed to get group extension: {}", e))?;
+        .map_err(|e| e.context("Failed to get group extension"))?;
 
     Ok(GroupInfoAndExtension {
         name: group_info.name,
@@ -744,15 +1820,14 @@ pub async fn get_group_messages<R: Runtime>(
     group_id: u64,
     start_before: u64,
     limit: u32,
-) -> Result<LastGroupMessagesResponse, String> {
-    let client_state: State<FireflyClient> = app.state();
-    let client = client_state.inner().clone();
+) -> Result<LastGroupMessagesResponse, FireflyError> {
+    let client = active_profile(&app).await?.client.clone();
 
     let messages = client
         .group_message_store()
         .get_ffi(group_id, start_before, limit)
         .await
-        .map_err(|e| format!("Failed to get group messages: {}", e))?;
+        .map_err(|e| e.context("Failed to get group messages"))?;
 
     let result = messages
         .iter()
@@ -761,6 +1836,103 @@ pub async fn get_group_messages<R: Runtime>(
     Ok(LastGroupMessagesResponse { result })
 }
 
+#[command]
+pub async fn edit_group_message<R: Runtime>(
+    app: AppHandle<R>,
+    group_id: u64,
+    message_id: u64,
+    payload_b64: String,
+) -> Result<BGroupMessage, FireflyError> {
+    let client = active_profile(&app).await?.client.clone();
+
+    let payload = general_purpose::STANDARD
+        .decode(&payload_b64)
+        .map_err(|e| FireflyError::InvalidInput(format!("Failed to decode base64: {}", e)))?;
+
+    let group_message = client
+        .edit_group_message(group_id, message_id, payload)
+        .await
+        .map_err(|e| e.context("Failed to edit group message"))?;
+
+    Ok(group_message_to_b_group_message(&group_message))
+}
+
+#[command]
+pub async fn delete_group_message<R: Runtime>(
+    app: AppHandle<R>,
+    group_id: u64,
+    message_id: u64,
+) -> Result<BGroupMessage, FireflyError> {
+    let profile = active_profile(&app).await?;
+    let client = profile.client.clone();
+
+    let group_message = client
+        .delete_group_message(group_id, message_id)
+        .await
+        .map_err(|e| e.context("Failed to delete group message"))?;
+
+    let _ = profile
+        .attachments
+        .remove_group_messages(group_id, vec![message_id])
+        .await;
+
+    Ok(group_message_to_b_group_message(&group_message))
+}
+
+#[command]
+pub async fn get_group_message_edits<R: Runtime>(
+    app: AppHandle<R>,
+    group_id: u64,
+    message_id: u64,
+) -> Result<MessageEditsResponse, FireflyError> {
+    let client = active_profile(&app).await?.client.clone();
+
+    let edits = client
+        .group_message_store()
+        .get_edits_ffi(group_id, message_id)
+        .await
+        .map_err(|e| e.context("Failed to get group message edits"))?;
+
+    let result = edits.iter().map(message_edit_to_b_message_edit).collect();
+    Ok(MessageEditsResponse { result })
+}
+
+#[command]
+pub async fn react_to_group_message<R: Runtime>(
+    app: AppHandle<R>,
+    group_id: u64,
+    message_id: u64,
+    emoji: String,
+) -> Result<(), FireflyError> {
+    let client = active_profile(&app).await?.client.clone();
+
+    client
+        .react_group(group_id, message_id, emoji)
+        .await
+        .map_err(|e| e.context("Failed to react to group message"))
+}
+
+#[command]
+pub async fn get_group_message_reactions<R: Runtime>(
+    app: AppHandle<R>,
+    group_id: u64,
+    message_ids: Vec<u64>,
+) -> Result<MessageReactionsResponse, FireflyError> {
+    let client = active_profile(&app).await?.client.clone();
+
+    let reactions = client
+        .group_message_store()
+        .get_reactions_ffi(group_id, message_ids)
+        .await
+        .map_err(|e| e.context("Failed to get group message reactions"))?;
+
+    let result = reactions
+        .iter()
+        .map(reaction_count_to_b_reaction_count)
+        .collect();
+    Ok(MessageReactionsResponse { result })
+}
+
 #[command]
 pub async fn update_group_channel<R: Runtime>(
     app: AppHandle<R>,
@@ -770,27 +1942,35 @@ pub async fn update_group_channel<R: Runtime>(
     name: String,
     channel_ty: u8,
     default_permissions: u32,
-) -> Result<MessageIdResponse, String> {
-    let client_state: State<FireflyClient> = app.state();
-    let client = client_state.inner().clone();
+) -> Result<MessageIdResponse, FireflyError> {
+    let client = active_profile(&app).await?.client.clone();
 
     let message_id = client
         .update_group_channel(group_id, id, delete, name, channel_ty, default_permissions)
         .await
-        .map_err(|e| format!("Failed to update group channel: {}", e))?;
+        .map_err(|e| e.context("Failed to update group channel"))?;
 
     Ok(MessageIdResponse { message_id })
 }
 
 #[command]
-pub async fn delete_group<R: Runtime>(app: AppHandle<R>, group_id: u64) -> Result<(), String> {
-    let client_state: State<FireflyClient> = app.state();
-    let client = client_state.inner().clone();
+pub async fn delete_group<R: Runtime>(
+    app: AppHandle<R>,
+    group_id: u64,
+) -> Result<(), FireflyError> {
+    let profile = active_profile(&app).await?;
+    let client = profile.client.clone();
 
     client
         .delete_group(group_id)
         .await
-        .map_err(|e| format!("Failed to delete group: {}", e))?;
+        .map_err(|e| e.context("Failed to delete group"))?;
+
+    profile
+        .attachments
+        .remove_group(group_id)
+        .await
+        .map_err(|e| e.context("Failed to remove group attachments"))?;
 
     Ok(())
 }
@@ -800,9 +1980,8 @@ pub async fn update_group_roles<R: Runtime>(
     app: AppHandle<R>,
     group_id: u64,
     roles: Vec<UpdateRoleProposal>,
-) -> Result<MessageIdResponse, String> {
-    let client_state: State<FireflyClient> = app.state();
-    let client = client_state.inner().clone();
+) -> Result<MessageIdResponse, FireflyError> {
+    let client = active_profile(&app).await?.client.clone();
 
     let ffi_roles: Vec<UpdateRoleProposalFfi> = roles
         .into_iter()
@@ -817,7 +1996,7 @@ pub async fn update_group_roles<R: Runtime>(
     let message_id = client
         .update_group_roles(group_id, ffi_roles)
         .await
-        .map_err(|e| format!("Failed to update group roles: {}", e))?;
+        .map_err(|e| e.context("Failed to update group roles"))?;
 
     Ok(MessageIdResponse { message_id })
 }
@@ -828,9 +2007,8 @@ pub async fn update_group_roles_in_channel<R: Runtime>(
     group_id: u64,
     channel_id: u32,
     roles: Vec<UpdateRoleProposal>,
-) -> Result<MessageIdResponse, String> {
-    let client_state: State<FireflyClient> = app.state();
-    let client = client_state.inner().clone();
+) -> Result<MessageIdResponse, FireflyError> {
+    let client = active_profile(&app).await?.client.clone();
 
     let ffi_roles: Vec<UpdateRoleProposalFfi> = roles
         .into_iter()
@@ -845,7 +2023,7 @@ pub async fn update_group_roles_in_channel<R: Runtime>(
     let message_id = client
         .update_group_roles_in_channel(group_id, channel_id, ffi_roles)
         .await
-        .map_err(|e| format!("Failed to update group roles in channel: {}", e))?;
+        .map_err(|e| e.context("Failed to update group roles in channel"))?;
 
     Ok(MessageIdResponse { message_id })
 }
@@ -855,9 +2033,8 @@ pub async fn update_group_users<R: Runtime>(
     app: AppHandle<R>,
     group_id: u64,
     users: Vec<UpdateUserProposal>,
-) -> Result<MessageIdResponse, String> {
-    let client_state: State<FireflyClient> = app.state();
-    let client = client_state.inner().clone();
+) -> Result<MessageIdResponse, FireflyError> {
+    let client = active_profile(&app).await?.client.clone();
 
     let ffi_users: Vec<UpdateUserProposalFfi> = users
         .into_iter()
@@ -870,49 +2047,233 @@ pub async fn update_group_users<R: Runtime>(
     let message_id = client
         .update_group_users(group_id, ffi_users)
         .await
-        .map_err(|e| format!("Failed to update group users: {}", e))?;
+        .map_err(|e| e.context("Failed to update group users"))?;
 
     Ok(MessageIdResponse { message_id })
 }
 
+#[command]
+pub async fn get_identity_trust<R: Runtime>(
+    app: AppHandle<R>,
+    username: String,
+) -> Result<IdentityTrustResponse, FireflyError> {
+    let client = active_profile(&app).await?.client.clone();
+
+    let trusts = client
+        .get_identity_trust(username)
+        .await
+        .map_err(|e| e.context("Failed to get identity trust"))?;
+
+    let result = trusts
+        .iter()
+        .map(identity_trust_to_b_identity_trust)
+        .collect();
+    Ok(IdentityTrustResponse { result })
+}
+
+#[command]
+pub async fn get_safety_number<R: Runtime>(
+    app: AppHandle<R>,
+    username: String,
+    device_id: u8,
+) -> Result<SafetyNumberResponse, FireflyError> {
+    let client = active_profile(&app).await?.client.clone();
+
+    let safety_number = client
+        .get_safety_number(username, device_id)
+        .await
+        .map_err(|e| e.context("Failed to get safety number"))?;
+
+    Ok(SafetyNumberResponse { safety_number })
+}
+
+#[command]
+pub async fn acknowledge_identity_change<R: Runtime>(
+    app: AppHandle<R>,
+    username: String,
+) -> Result<(), FireflyError> {
+    let client = active_profile(&app).await?.client.clone();
+
+    client
+        .acknowledge_identity_change(username)
+        .await
+        .map_err(|e| e.context("Failed to acknowledge identity change"))
+}
+
+#[command]
+pub async fn set_identity_verified<R: Runtime>(
+    app: AppHandle<R>,
+    username: String,
+    device_id: u8,
+    verified: bool,
+) -> Result<(), FireflyError> {
+    let client = active_profile(&app).await?.client.clone();
+
+    client
+        .set_identity_verified(username, device_id, verified)
+        .await
+        .map_err(|e| e.context("Failed to set identity verified"))
+}
+
 #[command]
 pub async fn get_conversations<R: Runtime>(
     app: AppHandle<R>,
     token: String,
-) -> Result<ConversationsResponse, String> {
-    let client_state: State<FireflyClient> = app.state();
-    let client = client_state.inner().clone();
+) -> Result<ConversationsResponse, FireflyError> {
+    let client = active_profile(&app).await?.client.clone();
 
     let conversations = client
         .get_conversations(token)
         .await
-        .map_err(|e| format!("Failed to get conversations: {}", e))?;
+        .map_err(|e| e.context("Failed to get conversations"))?;
 
     let result = conversations
         .into_iter()
         .map(|c| Conversation {
             other: c.other,
-            settings: c.settings,
+            settings: c.settings.synced_bits(),
+            conversation_settings: settings_to_b_conversation_settings(&c.settings),
         })
         .collect();
 
     Ok(ConversationsResponse { result })
 }
 
+#[command]
+pub async fn set_disappearing_timer<R: Runtime>(
+    app: AppHandle<R>,
+    other: String,
+    timer_secs: u32,
+) -> Result<BConversationSettings, FireflyError> {
+    let client = active_profile(&app).await?.client.clone();
+
+    let settings = client
+        .set_disappearing_timer(other, timer_secs)
+        .await
+        .map_err(|e| e.context("Failed to set disappearing timer"))?;
+
+    Ok(settings_to_b_conversation_settings(&settings))
+}
+
+#[command]
+pub async fn get_conversation_settings<R: Runtime>(
+    app: AppHandle<R>,
+    other: String,
+) -> Result<Option<BConversationSettings>, FireflyError> {
+    let client = active_profile(&app).await?.client.clone();
+
+    let settings = client
+        .get_conversation_settings(other)
+        .await
+        .map_err(|e| e.context("Failed to get conversation settings"))?;
+
+    Ok(settings.as_ref().map(settings_to_b_conversation_settings))
+}
+
+#[command]
+pub async fn set_blocked<R: Runtime>(
+    app: AppHandle<R>,
+    username: String,
+    blocked: bool,
+) -> Result<(), FireflyError> {
+    let client = active_profile(&app).await?.client.clone();
+
+    client
+        .set_blocked(username, blocked)
+        .await
+        .map_err(|e| e.context("Failed to set blocked"))
+}
+
+#[command]
+pub async fn get_blocked_users<R: Runtime>(
+    app: AppHandle<R>,
+) -> Result<BlockedUsersResponse, FireflyError> {
+    let client = active_profile(&app).await?.client.clone();
+
+    let result = client
+        .get_blocked()
+        .await
+        .map_err(|e| e.context("Failed to get blocked users"))?;
+
+    Ok(BlockedUsersResponse { result })
+}
+
+#[command]
+pub async fn update_conversation_settings<R: Runtime>(
+    app: AppHandle<R>,
+    other: String,
+    settings: BConversationSettings,
+) -> Result<BConversationSettings, FireflyError> {
+    let client = active_profile(&app).await?.client.clone();
+
+    let settings = client
+        .update_conversation_settings(other, b_conversation_settings_to_settings(&settings)?)
+        .await
+        .map_err(|e| e.context("Failed to update conversation settings"))?;
+
+    Ok(settings_to_b_conversation_settings(&settings))
+}
+
+#[command]
+pub async fn set_group_disappearing_timer<R: Runtime>(
+    app: AppHandle<R>,
+    group_id: u64,
+    timer_secs: u32,
+) -> Result<BConversationSettings, FireflyError> {
+    let client = active_profile(&app).await?.client.clone();
+
+    let settings = client
+        .set_group_disappearing_timer(group_id, timer_secs)
+        .await
+        .map_err(|e| e.context("Failed to set group disappearing timer"))?;
+
+    Ok(settings_to_b_conversation_settings(&settings))
+}
+
+#[command]
+pub async fn get_group_settings<R: Runtime>(
+    app: AppHandle<R>,
+    group_id: u64,
+) -> Result<Option<BConversationSettings>, FireflyError> {
+    let client = active_profile(&app).await?.client.clone();
+
+    let settings = client
+        .get_group_settings(group_id)
+        .await
+        .map_err(|e| e.context("Failed to get group settings"))?;
+
+    Ok(settings.as_ref().map(settings_to_b_conversation_settings))
+}
+
+#[command]
+pub async fn update_group_settings<R: Runtime>(
+    app: AppHandle<R>,
+    group_id: u64,
+    settings: BConversationSettings,
+) -> Result<BConversationSettings, FireflyError> {
+    let client = active_profile(&app).await?.client.clone();
+
+    let settings = client
+        .update_group_settings(group_id, b_conversation_settings_to_settings(&settings)?)
+        .await
+        .map_err(|e| e.context("Failed to update group settings"))?;
+
+    Ok(settings_to_b_conversation_settings(&settings))
+}
+
 #[command]
 pub async fn add_group_member<R: Runtime>(
     app: AppHandle<R>,
     group_id: u64,
     username: String,
     role_id: u32,
-) -> Result<(), String> {
-    let client_state: State<FireflyClient> = app.state();
-    let client = client_state.inner().clone();
+) -> Result<(), FireflyError> {
+    let client = active_profile(&app).await?.client.clone();
 
     client
         .add_group_member(group_id, username, role_id)
         .await
-        .map_err(|e| format!("Failed to add group member: {}", e))?;
+        .map_err(|e| e.context("Failed to add group member"))?;
 
     Ok(())
 }
@@ -922,14 +2283,13 @@ pub async fn kick_group_member<R: Runtime>(
     app: AppHandle<R>,
     group_id: u64,
     username: String,
-) -> Result<(), String> {
-    let client_state: State<FireflyClient> = app.state();
-    let client = client_state.inner().clone();
+) -> Result<(), FireflyError> {
+    let client = active_profile(&app).await?.client.clone();
 
     client
         .kick_group_member(group_id, username)
         .await
-        .map_err(|e| format!("Failed to kick group member: {}", e))?;
+        .map_err(|e| e.context("Failed to kick group member"))?;
 
     Ok(())
 }
@@ -3,6 +3,7 @@ use tauri_plugin_deep_link::DeepLinkExt;
 
 mod encryption_plugin;
 mod notification;
+mod profiles;
 
 #[cfg(desktop)]
 use tauri_plugin_single_instance;
@@ -26,7 +27,17 @@ pub fn run() {
         .plugin(tauri_plugin_shell::init())
         .invoke_handler(tauri::generate_handler![
             encryption_plugin::encrypt_and_send,
+            encryption_plugin::edit_message,
+            encryption_plugin::delete_message,
+            encryption_plugin::get_message_edits,
+            encryption_plugin::react_to_message,
+            encryption_plugin::get_message_reactions,
+            encryption_plugin::retry_message,
+            encryption_plugin::get_outbox_messages,
+            encryption_plugin::reconnect_now,
+            encryption_plugin::get_connection_state,
             encryption_plugin::get_last_messages,
+            encryption_plugin::search_messages,
             encryption_plugin::get_last_messages_from_all_conversations,
             encryption_plugin::save_tokens,
             encryption_plugin::mark_as_read_until,
@@ -36,19 +47,48 @@ pub fn run() {
             encryption_plugin::request_all_required_permissions,
             encryption_plugin::handle_message,
             encryption_plugin::get_file_server_url,
+            encryption_plugin::stage_attachment,
+            encryption_plugin::upload_attachment,
+            encryption_plugin::download_attachment,
+            encryption_plugin::get_attachment_cache_usage,
+            encryption_plugin::set_attachment_cache_quota,
             encryption_plugin::get_last_group_messages,
             encryption_plugin::encrypt_and_send_group_message,
+            encryption_plugin::send_typing,
+            encryption_plugin::send_group_typing,
             encryption_plugin::get_group_extension,
             encryption_plugin::create_group,
             encryption_plugin::get_group_infos,
             encryption_plugin::get_group_info_and_extension,
             encryption_plugin::get_group_messages,
+            encryption_plugin::edit_group_message,
+            encryption_plugin::delete_group_message,
+            encryption_plugin::get_group_message_edits,
+            encryption_plugin::react_to_group_message,
+            encryption_plugin::get_group_message_reactions,
             encryption_plugin::update_group_channel,
             encryption_plugin::update_group_roles,
             encryption_plugin::update_group_roles_in_channel,
             encryption_plugin::update_group_users,
             encryption_plugin::get_conversations,
+            encryption_plugin::set_disappearing_timer,
+            encryption_plugin::get_conversation_settings,
+            encryption_plugin::update_conversation_settings,
+            encryption_plugin::set_blocked,
+            encryption_plugin::get_blocked_users,
+            encryption_plugin::set_group_disappearing_timer,
+            encryption_plugin::get_group_settings,
+            encryption_plugin::update_group_settings,
+            encryption_plugin::get_identity_trust,
+            encryption_plugin::get_safety_number,
+            encryption_plugin::acknowledge_identity_change,
+            encryption_plugin::set_identity_verified,
             encryption_plugin::dispose,
+            encryption_plugin::logout,
+            encryption_plugin::get_profiles,
+            encryption_plugin::add_profile,
+            encryption_plugin::switch_profile,
+            encryption_plugin::set_background_sync,
             encryption_plugin::add_group_member,
             encryption_plugin::kick_group_member,
             encryption_plugin::clear_notifications,
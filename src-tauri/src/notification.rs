@@ -1,7 +1,10 @@
 use std::sync::Arc;
 
 use firefly_protos::{deserialize_proto, firefly::UserMessageInner};
-use firefly_signal::db::messages::UserMessage;
+use firefly_signal::db::{
+    messages::UserMessage,
+    migrations::{migrate, Migration},
+};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use sqlx::{Row, SqlitePool};
 use tauri::{
@@ -11,7 +14,20 @@ use tauri::{
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 use tauri_plugin_notification::NotificationExt;
 
-use crate::encryption_plugin::DATABASE;
+/// Schema of `app.db`, see `firefly_signal::db::migrations` for the rules.
+pub const APP_DB_MIGRATIONS: &[Migration] = &[Migration {
+    version: 1,
+    description: "initial schema",
+    sql: r#"
+CREATE TABLE IF NOT EXISTS user_message_notifications (
+    msg_id INTEGER NOT NULL,
+    other TEXT NOT NULL,
+    text BLOB NOT NULL,
+    sent_by_me BOOLEAN NOT NULL,
+    PRIMARY KEY (other, msg_id)
+);
+"#,
+}];
 
 #[derive(Debug, Clone)]
 pub struct MessageNotification {
@@ -32,19 +48,9 @@ impl NotificationStore {
     }
 
     async fn init_tables(&self) -> Result<(), String> {
-        sqlx::query(
-            "CREATE TABLE IF NOT EXISTS user_message_notifications (
-                msg_id INTEGER NOT NULL,
-                other TEXT NOT NULL,
-                text BLOB NOT NULL,
-                sent_by_me BOOLEAN NOT NULL,
-                PRIMARY KEY (other, msg_id)
-            )",
-        )
-        .execute(&self.pool)
-        .await
-        .map_err(|e| e.to_string())?;
-        Ok(())
+        migrate(&self.pool, APP_DB_MIGRATIONS)
+            .await
+            .map_err(|e| e.to_string())
     }
 
     pub async fn get_from_user(
@@ -96,6 +102,26 @@ impl NotificationStore {
         Ok(())
     }
 
+    pub async fn delete_of_sender(&self, sender: &str, message_ids: &[i64]) -> Result<(), String> {
+        if message_ids.is_empty() {
+            return Ok(());
+        }
+
+        let query = format!(
+            "DELETE FROM user_message_notifications WHERE other = ? AND msg_id IN ({})",
+            vec!["?"; message_ids.len()].join(", ")
+        );
+        let mut query = sqlx::query(&query).bind(sender);
+        for message_id in message_ids {
+            query = query.bind(message_id);
+        }
+        query
+            .execute(&self.pool)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
     pub async fn delete_until_of_sender(
         &self,
         sender: &str,
@@ -124,6 +150,8 @@ struct NotificationData {
 #[derive(Serialize, Deserialize, Clone)]
 struct UserBundledNotification {
     other: String,
+    /// Profile the messages were received on, set when there is more than one.
+    account: Option<String>,
     messages: Vec<NotificationData>,
 }
 
@@ -155,14 +183,20 @@ fn show_user_bundled_notification_desktop<R: Runtime>(
     use std::collections::hash_map::DefaultHasher;
     use std::hash::{Hash, Hasher};
     let mut hasher = DefaultHasher::new();
+    notification.account.hash(&mut hasher);
     notification.other.hash(&mut hasher);
     let notification_id = hasher.finish() as i32;
 
+    let title = match &notification.account {
+        Some(account) => format!("{} ({})", notification.other, account),
+        None => notification.other.clone(),
+    };
+
     // Create notification using Tauri's notification API
     app.notification()
         .builder()
         .id(notification_id) // Group notifications by sender using hashed ID
-        .title(&notification.other)
+        .title(&title)
         .body(&body)
         .show()
         .map_err(|e| format!("Failed to show notification: {}", e))?;
@@ -226,19 +260,24 @@ impl<R: Runtime> NotificationHandler<R> {
         }
     }
 
+    /// `pool` is the app db of the profile `msg` was received on, `account`
+    /// tags the notification with it.
     pub async fn show_user_bundled_notification<R2: Runtime>(
         &self,
         msg: &UserMessage,
         app: &AppHandle<R2>,
+        pool: &SqlitePool,
+        account: Option<String>,
     ) -> Result<(), String> {
-        self.add_message_to_history(msg).await?;
+        self.add_message_to_history(msg, pool).await?;
 
-        let store = NotificationStore::new(DATABASE.get().unwrap().clone()).await?;
+        let store = NotificationStore::new(pool.clone()).await?;
 
         let messages = store.get_from_user(&msg.other, 6).await?;
 
         let mut notification = UserBundledNotification {
             other: msg.other.clone(),
+            account,
             messages: Vec::new(),
         };
 
@@ -280,19 +319,23 @@ impl<R: Runtime> NotificationHandler<R> {
         Ok(())
     }
 
-    pub async fn add_message_to_history(&self, msg: &UserMessage) -> Result<(), String> {
+    pub async fn add_message_to_history(
+        &self,
+        msg: &UserMessage,
+        pool: &SqlitePool,
+    ) -> Result<(), String> {
         let notification = MessageNotification {
             msg_id: msg.id as i64,
             other: msg.other.clone(),
             text: msg.message.clone(),
             sent_by_me: !msg.sent_by_other,
         };
-        let store = NotificationStore::new(DATABASE.get().unwrap().clone()).await?;
+        let store = NotificationStore::new(pool.clone()).await?;
         store.put(&notification).await
     }
 
-    pub async fn clear_all(&self) -> Result<(), String> {
-        let store = NotificationStore::new(DATABASE.get().unwrap().clone()).await?;
+    pub async fn clear_all(&self, pool: &SqlitePool) -> Result<(), String> {
+        let store = NotificationStore::new(pool.clone()).await?;
         store.delete_all().await
     }
 
@@ -0,0 +1,476 @@
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
+
+use firefly_signal::{
+    attachments::{AttachmentCache, DEFAULT_ATTACHMENT_CACHE_QUOTA},
+    db::{messages::MessagesStore, migrations::migrate, setup_pool_from_path},
+    error::FireflyError,
+    websocket::FfiFireflyWsClient,
+    FfiFileServer,
+};
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use tokio::{
+    sync::{Mutex, OnceCell},
+    task::JoinHandle,
+};
+
+use crate::{
+    encryption_plugin::{Constants, ProfileFireflyCallback},
+    notification::APP_DB_MIGRATIONS,
+};
+
+/// The account that existed before profiles, its files stay directly under the
+/// app data dir so nothing has to be moved.
+pub const DEFAULT_PROFILE: &str = "default";
+
+const PROFILES_FILE: &str = "profiles.json";
+
+/// Persisted in `profiles.json` of the app data dir.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ProfilesConfig {
+    active: String,
+    profiles: Vec<String>,
+    #[serde(rename = "backgroundSync")]
+    background_sync: bool,
+}
+
+impl Default for ProfilesConfig {
+    fn default() -> Self {
+        Self {
+            active: DEFAULT_PROFILE.to_string(),
+            profiles: vec![DEFAULT_PROFILE.to_string()],
+            background_sync: false,
+        }
+    }
+}
+
+/// File servers can't be stopped, so one is kept per profile for the whole
+/// process and reused when the profile is opened again.
+#[derive(Clone)]
+pub struct ProfileFileServer {
+    pub server: Arc<FfiFileServer>,
+    /// Set once the server is bound.
+    pub port: Arc<OnceCell<u16>>,
+}
+
+/// Everything that belongs to one account.
+pub struct Profile {
+    pub id: String,
+    pub database: SqlitePool,
+    pub message_store: Arc<MessagesStore>,
+    pub client: Arc<FfiFireflyWsClient>,
+    pub file_server: ProfileFileServer,
+    pub attachments: Arc<AttachmentCache>,
+    /// Attachments waiting to be uploaded, the only files uploads read.
+    staging_dir: PathBuf,
+    /// Taken by `close`, which waits for them to stop.
+    tasks: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl Profile {
+    async fn open(
+        id: &str,
+        profile_dir: PathBuf,
+        file_server: ProfileFileServer,
+    ) -> Result<Self, FireflyError> {
+        let dbs_dir = profile_dir.join("dbs");
+        let files_dir = profile_dir.join("files");
+        let staging_dir = profile_dir.join("staging");
+        tokio::fs::create_dir_all(&dbs_dir).await?;
+        tokio::fs::create_dir_all(&files_dir).await?;
+
+        // leftovers of uploads that never finished
+        if let Err(err) = tokio::fs::remove_dir_all(&staging_dir).await {
+            if err.kind() != std::io::ErrorKind::NotFound {
+                return Err(err.into());
+            }
+        }
+        tokio::fs::create_dir_all(&staging_dir).await?;
+
+        log::info!("opening profile {} in {:?}", id, profile_dir);
+
+        let db_path = dbs_dir.join("app.db");
+        let database = setup_pool_from_path(&db_path.display().to_string(), 5)
+            .await
+            .map_err(FireflyError::from_anyhow)?;
+        migrate(&database, APP_DB_MIGRATIONS)
+            .await
+            .map_err(|e| FireflyError::from_anyhow(e).context("Failed to migrate app db"))?;
+
+        let messages_db_path = dbs_dir.join("user_messages.db");
+        let message_store =
+            MessagesStore::from_path(messages_db_path.to_string_lossy().to_string())
+                .await
+                .map_err(|e| e.context("Failed to create messages store"))?;
+        let message_store = Arc::new(message_store);
+
+        let firefly_db_path = dbs_dir.join("firefly.db");
+        let client = FfiFireflyWsClient::create(
+            Constants::FIREFLY_API_URL.to_string(),
+            Constants::FIREFLY_WS_URL.to_string(),
+            1000,
+            60_000,
+            Box::new(ProfileFireflyCallback::new(id.to_string())),
+            firefly_db_path.to_string_lossy().to_string(),
+            5000,
+            Constants::AUTH0_CLIENT_ID.to_string(),
+            Constants::AUTH0_DOMAIN.to_string(),
+            Some(message_store.clone()),
+        )
+        .await
+        .map_err(|e| e.context("Failed to create firefly client"))?;
+        let client = Arc::new(client);
+
+        // Files of the file server dir are tracked by the attachment cache
+        let cache_db_path = dbs_dir.join("attachments.db");
+        let attachments = AttachmentCache::from_path(
+            cache_db_path.to_string_lossy().to_string(),
+            client.api(),
+            files_dir.to_string_lossy().to_string(),
+            DEFAULT_ATTACHMENT_CACHE_QUOTA,
+        )
+        .await
+        .map_err(|e| e.context("Failed to create attachment cache"))?;
+        let attachments = Arc::new(attachments);
+
+        let cache_clone = attachments.clone();
+        let garbage_task = tokio::spawn(async move {
+            if let Err(err) = cache_clone.collect_garbage().await {
+                log::error!("attachment cache garbage collection failed: {}", err);
+            }
+        });
+
+        let client_clone = client.clone();
+        let client_task = tokio::spawn(async move {
+            if let Err(err) = client_clone.initialize_with_retrying().await {
+                log::error!("retry initing failed: {:?}", err);
+            }
+        });
+
+        Ok(Self {
+            id: id.to_string(),
+            database,
+            message_store,
+            client,
+            file_server,
+            attachments,
+            staging_dir,
+            tasks: Mutex::new(vec![garbage_task, client_task]),
+        })
+    }
+
+    /// Writes an attachment to upload into the staging dir.
+    pub async fn stage_attachment(&self, data: &[u8]) -> Result<PathBuf, FireflyError> {
+        let name: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(16)
+            .map(char::from)
+            .collect();
+
+        let path = self.staging_dir.join(name);
+        tokio::fs::write(&path, data).await?;
+        Ok(path)
+    }
+
+    /// Resolves `path` to a file of the staging dir, anything else is refused
+    /// so the webview can't have arbitrary files uploaded.
+    pub async fn staged_attachment(&self, path: &str) -> Result<PathBuf, FireflyError> {
+        let path = tokio::fs::canonicalize(path).await?;
+        let staging_dir = tokio::fs::canonicalize(&self.staging_dir).await?;
+        if path.parent() != Some(staging_dir.as_path()) {
+            return Err(FireflyError::InvalidInput(format!(
+                "{:?} is not a staged attachment",
+                path
+            )));
+        }
+
+        Ok(path)
+    }
+
+    /// Stops the client and closes every database, so the profile can be
+    /// opened again right away.
+    async fn close(&self) {
+        log::info!("closing profile {}", self.id);
+        self.client.dispose().await;
+        for task in self.tasks.lock().await.drain(..) {
+            if let Err(err) = task.await {
+                log::error!("task of profile {} failed: {}", self.id, err);
+            }
+        }
+
+        self.client.close().await;
+        self.attachments.close().await;
+        self.message_store.close().await;
+        self.database.close().await;
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProfileInfo {
+    pub id: String,
+    pub active: bool,
+    /// Whether its client is running, always true for the active profile.
+    pub open: bool,
+}
+
+struct ProfilesState {
+    config: ProfilesConfig,
+    open: HashMap<String, Arc<Profile>>,
+    file_servers: HashMap<String, ProfileFileServer>,
+}
+
+/// Keeps a separate set of databases, client and file server per account.
+///
+/// Only the active profile is open unless background sync is enabled, then
+/// every profile stays connected and receives messages.
+pub struct ProfileManager {
+    app_data_dir: PathBuf,
+    state: Mutex<ProfilesState>,
+}
+
+impl ProfileManager {
+    /// Opens the active profile, and the others too if background sync is on.
+    pub async fn load(app_data_dir: PathBuf) -> Result<Self, FireflyError> {
+        let config_path = app_data_dir.join(PROFILES_FILE);
+        let config = match tokio::fs::read(&config_path).await {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => ProfilesConfig::default(),
+            Err(err) => return Err(err.into()),
+        };
+
+        let manager = Self {
+            app_data_dir,
+            state: Mutex::new(ProfilesState {
+                config,
+                open: HashMap::new(),
+                file_servers: HashMap::new(),
+            }),
+        };
+
+        {
+            let mut state = manager.state.lock().await;
+            let to_open = if state.config.background_sync {
+                state.config.profiles.clone()
+            } else {
+                vec![state.config.active.clone()]
+            };
+            for id in to_open {
+                manager.open_profile(&mut state, &id).await?;
+            }
+        }
+
+        Ok(manager)
+    }
+
+    pub async fn active(&self) -> Result<Arc<Profile>, FireflyError> {
+        let state = self.state.lock().await;
+        state
+            .open
+            .get(&state.config.active)
+            .cloned()
+            .ok_or_else(|| FireflyError::NotFound("active profile is not open".to_string()))
+    }
+
+    /// The profile if it's open.
+    pub async fn get(&self, id: &str) -> Option<Arc<Profile>> {
+        self.state.lock().await.open.get(id).cloned()
+    }
+
+    pub async fn is_active(&self, id: &str) -> bool {
+        self.state.lock().await.config.active == id
+    }
+
+    /// Used to tag notifications, there is nothing to tell apart with a single
+    /// profile.
+    pub async fn has_multiple(&self) -> bool {
+        self.state.lock().await.config.profiles.len() > 1
+    }
+
+    pub async fn background_sync(&self) -> bool {
+        self.state.lock().await.config.background_sync
+    }
+
+    pub async fn list(&self) -> Vec<ProfileInfo> {
+        let state = self.state.lock().await;
+        state
+            .config
+            .profiles
+            .iter()
+            .map(|id| ProfileInfo {
+                id: id.clone(),
+                active: *id == state.config.active,
+                open: state.open.contains_key(id),
+            })
+            .collect()
+    }
+
+    /// Creates an empty profile, it starts out waiting for login.
+    pub async fn add(&self, id: &str) -> Result<(), FireflyError> {
+        validate_profile_id(id)?;
+
+        let mut state = self.state.lock().await;
+        if state.config.profiles.iter().any(|p| p == id) {
+            return Err(FireflyError::InvalidInput(format!(
+                "profile {} already exists",
+                id
+            )));
+        }
+
+        state.config.profiles.push(id.to_string());
+        self.save(&state.config).await?;
+
+        if state.config.background_sync {
+            self.open_profile(&mut state, id).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Makes `id` the active profile, closing the previous one unless
+    /// background sync is on.
+    pub async fn switch(&self, id: &str) -> Result<Arc<Profile>, FireflyError> {
+        let mut state = self.state.lock().await;
+        if !state.config.profiles.iter().any(|p| p == id) {
+            return Err(FireflyError::NotFound(format!("no profile {}", id)));
+        }
+
+        let profile = self.open_profile(&mut state, id).await?;
+
+        state.config.active = id.to_string();
+        self.save(&state.config).await?;
+
+        if !state.config.background_sync {
+            close_inactive(&mut state).await;
+        }
+
+        Ok(profile)
+    }
+
+    pub async fn set_background_sync(&self, enabled: bool) -> Result<(), FireflyError> {
+        let mut state = self.state.lock().await;
+        state.config.background_sync = enabled;
+        self.save(&state.config).await?;
+
+        if enabled {
+            for id in state.config.profiles.clone() {
+                self.open_profile(&mut state, &id).await?;
+            }
+        } else {
+            close_inactive(&mut state).await;
+        }
+
+        Ok(())
+    }
+
+    /// Closes every open profile, for when the app exits.
+    pub async fn dispose(&self) {
+        let mut state = self.state.lock().await;
+        for (_, profile) in state.open.drain() {
+            profile.close().await;
+        }
+    }
+
+    fn profile_dir(&self, id: &str) -> PathBuf {
+        if id == DEFAULT_PROFILE {
+            self.app_data_dir.clone()
+        } else {
+            self.app_data_dir.join("profiles").join(id)
+        }
+    }
+
+    async fn open_profile(
+        &self,
+        state: &mut ProfilesState,
+        id: &str,
+    ) -> Result<Arc<Profile>, FireflyError> {
+        if let Some(profile) = state.open.get(id) {
+            return Ok(profile.clone());
+        }
+
+        let profile_dir = self.profile_dir(id);
+        let file_server = match state.file_servers.get(id) {
+            Some(file_server) => file_server.clone(),
+            None => {
+                let files_dir = profile_dir.join("files");
+                tokio::fs::create_dir_all(&files_dir).await?;
+                let file_server = start_file_server(files_dir);
+                state
+                    .file_servers
+                    .insert(id.to_string(), file_server.clone());
+                file_server
+            }
+        };
+
+        let profile = Arc::new(Profile::open(id, profile_dir, file_server).await?);
+        state.open.insert(id.to_string(), profile.clone());
+        Ok(profile)
+    }
+
+    async fn save(&self, config: &ProfilesConfig) -> Result<(), FireflyError> {
+        let bytes = serde_json::to_vec_pretty(config)?;
+        tokio::fs::write(self.app_data_dir.join(PROFILES_FILE), bytes).await?;
+        Ok(())
+    }
+}
+
+async fn close_inactive(state: &mut ProfilesState) {
+    let inactive = state
+        .open
+        .keys()
+        .filter(|id| **id != state.config.active)
+        .cloned()
+        .collect::<Vec<_>>();
+
+    for id in inactive {
+        if let Some(profile) = state.open.remove(&id) {
+            profile.close().await;
+        }
+    }
+}
+
+fn start_file_server(files_dir: PathBuf) -> ProfileFileServer {
+    let file_server_token: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect();
+
+    let server = Arc::new(FfiFileServer::create(
+        files_dir.to_string_lossy().to_string(),
+        file_server_token,
+    ));
+    let port = Arc::new(OnceCell::new());
+
+    let server_clone = server.clone();
+    let port_clone = port.clone();
+    tokio::spawn(async move {
+        match server_clone.start_serving(None).await {
+            Ok(p) => {
+                let _ = port_clone.set(p);
+                log::info!("file server ready on port {}", p);
+            }
+            Err(e) => log::error!("file server failed: {}", e),
+        }
+    });
+
+    ProfileFileServer { server, port }
+}
+
+/// Profile ids name directories, so they are kept to a safe set of characters.
+fn validate_profile_id(id: &str) -> Result<(), FireflyError> {
+    let valid = !id.is_empty()
+        && id.len() <= 64
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+
+    if valid {
+        Ok(())
+    } else {
+        Err(FireflyError::InvalidInput(format!(
+            "invalid profile id {:?}, use up to 64 letters, digits, '-' or '_'",
+            id
+        )))
+    }
+}